@@ -0,0 +1,28 @@
+//! Feed arbitrary bytes through the lexer and optimizer.
+//!
+//! Malformed source must be rejected with an error, never a panic.
+
+#![no_main]
+
+use brainfuck_interpreter::lexer::Lexer;
+use brainfuck_interpreter::optimizer::{OptLevel, Optimizer, OptimizerConfig};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for (opt_level, lenient) in [
+        (OptLevel::Full, false),
+        (OptLevel::Fold, false),
+        (OptLevel::None, false),
+        (OptLevel::Full, true),
+    ] {
+        let config = OptimizerConfig {
+            opt_level,
+            lenient,
+            ..Default::default()
+        };
+        let mut optimizer = Optimizer::with_config(config);
+        if let Ok(instructions) = optimizer.optimize(Lexer::new(data)) {
+            assert_eq!(instructions.len(), optimizer.positions().len());
+        }
+    }
+});
@@ -0,0 +1,269 @@
+//! An experimental interpreter whose cells hold arbitrary-precision integers.
+//!
+//! `+` and `-` never wrap, so a cell can count well past 255 or below zero,
+//! and loops run until their cell is exactly zero. `.` writes the cell modulo
+//! 256. Folded runs and recognised loops assume byte cells, so programs must
+//! be optimized with [`OptLevel::None`](crate::optimizer::OptLevel::None).
+
+use crate::error::BrainfuckError;
+use crate::interpreter::{EofBehavior, InterpreterConfig, TIMEOUT_CHECK_INTERVAL};
+use crate::optimizer::Instruction;
+use anyhow::Result;
+use num_bigint::{BigInt, Sign};
+use std::io::{self, BufReader, Read, Write};
+use std::time::Instant;
+
+/// Runs unoptimized instructions on a tape of unbounded integers
+///
+/// Of the [`InterpreterConfig`] it honours the tape size, initial cell value,
+/// end-of-input behavior, whether I/O is allowed, the input buffer size and
+/// the timeout.
+pub struct BignumInterpreter<R, W> {
+    instructions: Vec<Instruction>,
+    memory: Vec<BigInt>,
+    pointer: usize,
+    instruction_pointer: usize,
+    config: InterpreterConfig,
+    input: BufReader<R>,
+    output: W,
+}
+
+impl<R: Read, W: Write> BignumInterpreter<R, W> {
+    /// Create an interpreter that reads from `input` and writes to `output`
+    pub fn with_io(instructions: Vec<Instruction>, config: InterpreterConfig, input: R, output: W) -> Self {
+        Self {
+            instructions,
+            memory: vec![BigInt::from(config.initial_cell_value); config.memory_size],
+            pointer: 0,
+            instruction_pointer: 0,
+            input: BufReader::with_capacity(config.input_buffer_size, input),
+            config,
+            output,
+        }
+    }
+
+    /// The cells of the tape
+    pub fn memory_state(&self) -> &[BigInt] {
+        &self.memory
+    }
+
+    /// Index of the instruction to run next, or of the one that failed
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    /// Copy `cells` onto the start of the tape, ignoring any that do not fit
+    ///
+    /// Cells past the end of `cells` keep their initial value.
+    pub fn load_memory(&mut self, cells: &[u8]) {
+        for (cell, &value) in self.memory.iter_mut().zip(cells) {
+            *cell = BigInt::from(value);
+        }
+    }
+
+    /// Run the program to the end
+    pub fn run(&mut self) -> Result<()> {
+        let started = Instant::now();
+        let mut steps = 0u64;
+        while self.instruction_pointer < self.instructions.len() {
+            self.step()?;
+            steps += 1;
+            if let Some(timeout) = self.config.timeout {
+                if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && started.elapsed() > timeout {
+                    return Err(BrainfuckError::RuntimeError {
+                        message: format!("execution exceeded the {:?} timeout", timeout),
+                    }
+                    .into());
+                }
+            }
+        }
+        self.output.flush().map_err(|e| io_error("flush output", e))?;
+        Ok(())
+    }
+
+    /// Execute one instruction
+    fn step(&mut self) -> Result<()> {
+        let pointer = self.pointer;
+        match &self.instructions[self.instruction_pointer] {
+            Instruction::MoveRight(count) => match self.pointer.checked_add(*count) {
+                Some(address) if address < self.memory.len() => self.pointer = address,
+                _ => return Err(out_of_bounds(self.pointer.saturating_add(*count), self.memory.len())),
+            },
+            Instruction::MoveLeft(count) => match self.pointer.checked_sub(*count) {
+                Some(address) => self.pointer = address,
+                None => return Err(out_of_bounds(self.pointer.wrapping_sub(*count), self.memory.len())),
+            },
+            Instruction::Increment(count) => self.memory[pointer] += *count,
+            Instruction::Decrement(count) => self.memory[pointer] -= *count,
+            Instruction::Output(count) => {
+                self.check_io_allowed("output")?;
+                // Two's complement keeps the low byte right for negative cells
+                let byte = u8::try_from(&self.memory[pointer] & BigInt::from(u8::MAX)).expect("masked to a byte");
+                let bytes = vec![byte; *count];
+                self.output.write_all(&bytes).map_err(|e| io_error("write output", e))?;
+            }
+            Instruction::Input(count) => {
+                self.check_io_allowed("input")?;
+                for _ in 0..*count {
+                    self.read_cell()?;
+                }
+            }
+            Instruction::JumpForward(end) => {
+                if self.memory[pointer].sign() == Sign::NoSign {
+                    self.instruction_pointer = *end;
+                    return Ok(());
+                }
+            }
+            Instruction::JumpBackward(start) => {
+                if self.memory[pointer].sign() != Sign::NoSign {
+                    self.instruction_pointer = *start;
+                    return Ok(());
+                }
+            }
+            Instruction::Nop => {}
+            other => {
+                // Name what put the instruction there, so it can be left out
+                let cause = match other {
+                    Instruction::DebugDump => "`#` with --debug",
+                    Instruction::Halt => "the --halt-char character",
+                    Instruction::StoreRegister | Instruction::RestoreRegister => "`$` and `}` with --register",
+                    Instruction::SwitchInput => "`~` with --second-input",
+                    Instruction::Extension(_) => "a custom command",
+                    _ => "optimization; run without it",
+                };
+                return Err(BrainfuckError::RuntimeError {
+                    message: format!("{} is not supported with bignum cells; it comes from {}", other.name(), cause),
+                }
+                .into());
+            }
+        }
+        self.instruction_pointer += 1;
+        Ok(())
+    }
+
+    /// Read a byte of input into the current cell
+    fn read_cell(&mut self) -> Result<()> {
+        let mut buf = [0u8; 1];
+        let cell = &mut self.memory[self.pointer];
+        match self.input.read_exact(&mut buf) {
+            Ok(()) => *cell = BigInt::from(buf[0]),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => match self.config.eof_behavior {
+                EofBehavior::Error => return Err(io_error("read input", e)),
+                EofBehavior::Unchanged => {}
+                EofBehavior::Zero => *cell = BigInt::from(0),
+                // Without wrapping, -1 really is negative
+                EofBehavior::NegativeOne => *cell = BigInt::from(-1),
+                EofBehavior::Value(value) => *cell = BigInt::from(value),
+            },
+            Err(e) => return Err(io_error("read input", e)),
+        }
+        Ok(())
+    }
+
+    /// Fail if `.` and `,` are disabled
+    fn check_io_allowed(&self, what: &str) -> Result<()> {
+        if self.config.allow_io {
+            return Ok(());
+        }
+        Err(BrainfuckError::RuntimeError {
+            message: format!("program tried to use {} with I/O disabled", what),
+        }
+        .into())
+    }
+}
+
+fn out_of_bounds(address: usize, tape_size: usize) -> anyhow::Error {
+    BrainfuckError::MemoryOutOfBounds { address, tape_size }.into()
+}
+
+fn io_error(action: &str, e: io::Error) -> anyhow::Error {
+    BrainfuckError::IoError {
+        message: format!("Failed to {}: {}", action, e),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::optimizer::{OptLevel, Optimizer, OptimizerConfig};
+    use std::io::Cursor;
+
+    /// Run `program` unoptimized and return the tape and the output
+    fn run(program: &str) -> (Vec<BigInt>, Vec<u8>) {
+        let mut optimizer = Optimizer::with_config(OptimizerConfig {
+            opt_level: OptLevel::None,
+            ..Default::default()
+        });
+        let instructions = optimizer.optimize(Lexer::new(Cursor::new(program))).unwrap();
+        let config = InterpreterConfig {
+            memory_size: 4,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = BignumInterpreter::with_io(instructions, config, io::empty(), &mut output);
+        interpreter.run().unwrap();
+        let memory = interpreter.memory_state().to_vec();
+        (memory, output)
+    }
+
+    #[test]
+    fn test_cells_grow_past_a_byte() {
+        let (memory, output) = run(&format!("{}.", "+".repeat(300)));
+        assert_eq!(memory[0], BigInt::from(300));
+        // Output still takes the value modulo 256
+        assert_eq!(output, [44]);
+
+        let (memory, output) = run("-.");
+        assert_eq!(memory[0], BigInt::from(-1));
+        assert_eq!(output, [255]);
+    }
+
+    #[test]
+    fn test_unsupported_instructions_name_their_cause() {
+        let run = |instructions| {
+            let mut interpreter =
+                BignumInterpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), io::sink());
+            interpreter.run().unwrap_err().to_string()
+        };
+        let halt = run(vec![Instruction::Halt]);
+        assert!(halt.ends_with("Halt is not supported with bignum cells; it comes from the --halt-char character"));
+        assert!(run(vec![Instruction::SetZero]).ends_with("it comes from optimization; run without it"));
+    }
+
+    #[test]
+    fn test_timeout_and_loaded_memory() {
+        let config = InterpreterConfig {
+            timeout: Some(std::time::Duration::from_millis(10)),
+            ..Default::default()
+        };
+        // `+[]`
+        let endless = vec![Instruction::Increment(1), Instruction::JumpForward(2), Instruction::JumpBackward(1)];
+        let mut interpreter = BignumInterpreter::with_io(endless, config, io::empty(), io::sink());
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(error.to_string(), "Runtime error: execution exceeded the 10ms timeout");
+
+        let mut output = Vec::new();
+        let instructions = vec![Instruction::Output(1), Instruction::MoveRight(1), Instruction::Output(1)];
+        let mut interpreter =
+            BignumInterpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+        interpreter.load_memory(b"AB");
+        interpreter.run().unwrap();
+        assert_eq!(output, b"AB");
+    }
+
+    #[test]
+    fn test_loop_counts_down_from_a_large_cell() {
+        // 1000 iterations add one each to the next cell, which a wrapping
+        // byte cell could never hold
+        let (memory, _) = run(&format!("{}[->+<]", "+".repeat(1000)));
+        assert_eq!(memory[0], BigInt::from(0));
+        assert_eq!(memory[1], BigInt::from(1000));
+
+        // 100 × 100 with nested loops
+        let hundred = "+".repeat(100);
+        let (memory, _) = run(&format!("{}[>{}[>+<-]<-]", hundred, hundred));
+        assert_eq!(memory[2], BigInt::from(10_000));
+    }
+}
@@ -0,0 +1,402 @@
+//! A compact binary encoding of optimized instructions, so large programs
+//! can be run again without lexing and optimizing them.
+//!
+//! The encoding starts with [`MAGIC`], followed by one opcode byte per
+//! instruction and its operands. Counts and lengths are unsigned LEB128
+//! varints, offsets are zigzag-encoded varints and cell values are single
+//! bytes. Jumps hold the distance to their partner as an offset, so they stay
+//! small in large programs and fragments can be joined without renumbering.
+
+use crate::error::BrainfuckError;
+use crate::optimizer::Instruction;
+use anyhow::Result;
+
+/// The bytes every encoding starts with, ending in the format version
+///
+/// Version 2 was written with the `--fill` value folded into known cells,
+/// which a run with another tape would silently get wrong, so it has to be
+/// compiled again.
+pub const MAGIC: [u8; 4] = *b"BFC\x03";
+
+const MOVE_RIGHT: u8 = 0;
+const MOVE_LEFT: u8 = 1;
+const INCREMENT: u8 = 2;
+const DECREMENT: u8 = 3;
+const OUTPUT: u8 = 4;
+const INPUT: u8 = 5;
+const JUMP_FORWARD: u8 = 6;
+const JUMP_BACKWARD: u8 = 7;
+const SET_ZERO: u8 = 8;
+const COPY: u8 = 9;
+const MULTIPLY_ADD: u8 = 10;
+const ADD_AT_OFFSET: u8 = 11;
+const SET_AT_OFFSET: u8 = 12;
+const PRINT_CONST: u8 = 13;
+const SCAN_ADD: u8 = 14;
+const ECHO_STREAM: u8 = 15;
+const DEBUG_DUMP: u8 = 16;
+const HALT: u8 = 17;
+const STORE_REGISTER: u8 = 18;
+const RESTORE_REGISTER: u8 = 19;
+const EXTENSION: u8 = 20;
+const SCAN_RIGHT: u8 = 21;
+const SCAN_LEFT: u8 = 22;
+const SWITCH_INPUT: u8 = 23;
+const NOP: u8 = 24;
+const SET_VALUE: u8 = 25;
+
+/// Encode instructions as bytecode
+///
+/// Bytecode can be run with any starting tape, so the instructions should
+/// be optimized without an
+/// [`initial_cell_value`](crate::optimizer::OptimizerConfig::initial_cell_value).
+pub fn serialize(instructions: &[Instruction]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::MoveRight(n) => write_opcode_count(&mut bytes, MOVE_RIGHT, *n),
+            Instruction::MoveLeft(n) => write_opcode_count(&mut bytes, MOVE_LEFT, *n),
+            Instruction::Increment(n) => bytes.extend_from_slice(&[INCREMENT, *n]),
+            Instruction::Decrement(n) => bytes.extend_from_slice(&[DECREMENT, *n]),
+            Instruction::Output(n) => write_opcode_count(&mut bytes, OUTPUT, *n),
+            Instruction::Input(n) => write_opcode_count(&mut bytes, INPUT, *n),
+            Instruction::JumpForward(target) => write_jump(&mut bytes, JUMP_FORWARD, index, *target),
+            Instruction::JumpBackward(target) => write_jump(&mut bytes, JUMP_BACKWARD, index, *target),
+            Instruction::SetZero => bytes.push(SET_ZERO),
+            Instruction::SetValue(value) => bytes.extend_from_slice(&[SET_VALUE, *value]),
+            Instruction::Copy { targets } => {
+                write_opcode_count(&mut bytes, COPY, targets.len());
+                for &offset in targets {
+                    write_offset(&mut bytes, offset);
+                }
+            }
+            Instruction::MultiplyAdd { targets } => {
+                write_opcode_count(&mut bytes, MULTIPLY_ADD, targets.len());
+                write_adds(&mut bytes, targets);
+            }
+            Instruction::AddAtOffset { offset, value } => {
+                bytes.push(ADD_AT_OFFSET);
+                write_offset(&mut bytes, *offset);
+                bytes.push(*value);
+            }
+            Instruction::SetAtOffset { offset, value } => {
+                bytes.push(SET_AT_OFFSET);
+                write_offset(&mut bytes, *offset);
+                bytes.push(*value);
+            }
+            Instruction::PrintConst(values) => {
+                write_opcode_count(&mut bytes, PRINT_CONST, values.len());
+                bytes.extend_from_slice(values);
+            }
+            Instruction::ScanAdd { stride, adds } => {
+                bytes.push(SCAN_ADD);
+                write_offset(&mut bytes, *stride);
+                write_varint(&mut bytes, adds.len() as u64);
+                write_adds(&mut bytes, adds);
+            }
+            Instruction::ScanRight(n) => write_opcode_count(&mut bytes, SCAN_RIGHT, *n),
+            Instruction::ScanLeft(n) => write_opcode_count(&mut bytes, SCAN_LEFT, *n),
+            Instruction::EchoStream => bytes.push(ECHO_STREAM),
+            Instruction::DebugDump => bytes.push(DEBUG_DUMP),
+            Instruction::Halt => bytes.push(HALT),
+            Instruction::StoreRegister => bytes.push(STORE_REGISTER),
+            Instruction::RestoreRegister => bytes.push(RESTORE_REGISTER),
+            Instruction::SwitchInput => bytes.push(SWITCH_INPUT),
+            Instruction::Nop => bytes.push(NOP),
+            Instruction::Extension(c) => {
+                bytes.push(EXTENSION);
+                write_varint(&mut bytes, u64::from(*c));
+            }
+        }
+    }
+
+    bytes
+}
+
+/// Whether `bytes` start with the bytecode header
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Decode bytecode written by [`serialize`]
+///
+/// Fails on a missing header, truncated or unknown instructions, and jumps
+/// that do not pair up, so the result is always safe to run.
+pub fn deserialize(bytes: &[u8]) -> Result<Vec<Instruction>> {
+    let body = match bytes.strip_prefix(&MAGIC) {
+        Some(body) => body,
+        None => match bytes {
+            [b'B', b'F', b'C', version, ..] => {
+                return Err(invalid(&format!("unsupported bytecode version {}; compile the program again", version)))
+            }
+            _ => return Err(invalid("missing bytecode header")),
+        },
+    };
+    let mut reader = Reader { bytes: body, pos: 0 };
+    let mut instructions = Vec::new();
+
+    while let Some(opcode) = reader.next_byte() {
+        let instruction = match opcode {
+            MOVE_RIGHT => Instruction::MoveRight(reader.count()?),
+            MOVE_LEFT => Instruction::MoveLeft(reader.count()?),
+            INCREMENT => Instruction::Increment(reader.byte()?),
+            DECREMENT => Instruction::Decrement(reader.byte()?),
+            OUTPUT => Instruction::Output(reader.count()?),
+            INPUT => Instruction::Input(reader.count()?),
+            JUMP_FORWARD => Instruction::JumpForward(reader.jump(instructions.len())?),
+            JUMP_BACKWARD => Instruction::JumpBackward(reader.jump(instructions.len())?),
+            SET_ZERO => Instruction::SetZero,
+            SET_VALUE => Instruction::SetValue(reader.byte()?),
+            COPY => {
+                let len = reader.count()?;
+                let targets = (0..len).map(|_| reader.offset()).collect::<Result<_>>()?;
+                Instruction::Copy { targets }
+            }
+            MULTIPLY_ADD => {
+                let len = reader.count()?;
+                Instruction::MultiplyAdd { targets: reader.adds(len)? }
+            }
+            ADD_AT_OFFSET => Instruction::AddAtOffset {
+                offset: reader.offset()?,
+                value: reader.byte()?,
+            },
+            SET_AT_OFFSET => Instruction::SetAtOffset {
+                offset: reader.offset()?,
+                value: reader.byte()?,
+            },
+            PRINT_CONST => {
+                let len = reader.count()?;
+                Instruction::PrintConst(reader.take(len)?.to_vec())
+            }
+            SCAN_ADD => {
+                let stride = reader.offset()?;
+                let len = reader.count()?;
+                Instruction::ScanAdd { stride, adds: reader.adds(len)? }
+            }
+            SCAN_RIGHT => Instruction::ScanRight(reader.count()?),
+            SCAN_LEFT => Instruction::ScanLeft(reader.count()?),
+            ECHO_STREAM => Instruction::EchoStream,
+            DEBUG_DUMP => Instruction::DebugDump,
+            HALT => Instruction::Halt,
+            STORE_REGISTER => Instruction::StoreRegister,
+            RESTORE_REGISTER => Instruction::RestoreRegister,
+            SWITCH_INPUT => Instruction::SwitchInput,
+            NOP => Instruction::Nop,
+            EXTENSION => {
+                let code = u32::try_from(reader.varint()?).ok().and_then(char::from_u32);
+                Instruction::Extension(code.ok_or_else(|| invalid("invalid custom command character"))?)
+            }
+            _ => return Err(invalid(&format!("unknown opcode {} at byte {}", opcode, reader.pos + MAGIC.len() - 1))),
+        };
+        instructions.push(instruction);
+    }
+
+    check_jumps(&instructions)?;
+    Ok(instructions)
+}
+
+/// Make sure every jump lands on the matching jump back
+fn check_jumps(instructions: &[Instruction]) -> Result<()> {
+    for (index, instruction) in instructions.iter().enumerate() {
+        let paired = match *instruction {
+            Instruction::JumpForward(target) => {
+                matches!(instructions.get(target), Some(&Instruction::JumpBackward(start)) if start == index)
+            }
+            Instruction::JumpBackward(target) => {
+                matches!(instructions.get(target), Some(&Instruction::JumpForward(end)) if end == index)
+            }
+            _ => true,
+        };
+        if !paired {
+            return Err(invalid(&format!("jump at instruction {} has no matching jump back", index)));
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> anyhow::Error {
+    BrainfuckError::InvalidBytecode {
+        message: message.to_string(),
+    }
+    .into()
+}
+
+fn write_opcode_count(bytes: &mut Vec<u8>, opcode: u8, count: usize) {
+    bytes.push(opcode);
+    write_varint(bytes, count as u64);
+}
+
+/// Write a jump at `index` as the distance to `target`
+fn write_jump(bytes: &mut Vec<u8>, opcode: u8, index: usize, target: usize) {
+    bytes.push(opcode);
+    write_offset(bytes, (target as isize).wrapping_sub(index as isize));
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn write_offset(bytes: &mut Vec<u8>, offset: isize) {
+    let offset = offset as i64;
+    write_varint(bytes, ((offset << 1) ^ (offset >> 63)) as u64);
+}
+
+fn write_adds(bytes: &mut Vec<u8>, adds: &[(isize, u8)]) {
+    for &(offset, value) in adds {
+        write_offset(bytes, offset);
+        bytes.push(value);
+    }
+}
+
+/// Reads operands from the bytecode after the header
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn next_byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        self.next_byte().ok_or_else(|| invalid("bytecode ends in the middle of an instruction"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid("bytecode ends in the middle of an instruction"))?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint is too long"))
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        usize::try_from(self.varint()?).map_err(|_| invalid("count does not fit in memory"))
+    }
+
+    fn offset(&mut self) -> Result<isize> {
+        let zigzag = self.varint()?;
+        let offset = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        isize::try_from(offset).map_err(|_| invalid("offset does not fit in memory"))
+    }
+
+    /// Read the target of a jump at `index`, stored as the distance to it
+    fn jump(&mut self, index: usize) -> Result<usize> {
+        index
+            .checked_add_signed(self.offset()?)
+            .ok_or_else(|| invalid(&format!("jump at instruction {} lands outside the program", index)))
+    }
+
+    fn adds(&mut self, len: usize) -> Result<Vec<(isize, u8)>> {
+        (0..len).map(|_| Ok((self.offset()?, self.byte()?))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{Lexer, LexerConfig};
+    use crate::optimizer::Optimizer;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let config = LexerConfig {
+            register: true,
+            input_switch: true,
+            extensions: vec!['?'],
+            ..Default::default()
+        };
+        let source = "++++++++[>++++[>++>+++<<-]>+<<-]>>.,[.,]>[-]<<[>+>]$}?>>>[<+>-<<]+.[>>][<]~,";
+        let lexer = Lexer::with_config(Cursor::new(source.as_bytes()), config);
+        let mut instructions = Optimizer::new().optimize(lexer).unwrap();
+        instructions.extend([
+            Instruction::Copy { targets: vec![-3, 2] },
+            Instruction::MultiplyAdd { targets: vec![(isize::MIN, 7)] },
+            Instruction::SetAtOffset { offset: -1, value: 200 },
+            Instruction::SetValue(9),
+            Instruction::PrintConst(b"hi".to_vec()),
+            Instruction::MoveRight(1 << 40),
+            Instruction::DebugDump,
+            Instruction::Nop,
+            Instruction::Halt,
+        ]);
+
+        assert_eq!(deserialize(&serialize(&instructions)).unwrap(), instructions);
+    }
+
+    #[test]
+    fn test_relative_jumps() {
+        let fragment = [
+            Instruction::Increment(1),
+            Instruction::JumpForward(3),
+            Instruction::Decrement(1),
+            Instruction::JumpBackward(1),
+        ];
+        let bytes = serialize(&fragment);
+        assert_eq!(&bytes[MAGIC.len()..], [INCREMENT, 1, JUMP_FORWARD, 4, DECREMENT, 1, JUMP_BACKWARD, 3]);
+
+        // The same loop encodes the same way anywhere, so fragments join up
+        let joined = [&bytes[..], &bytes[MAGIC.len()..]].concat();
+        let mut expected = fragment.to_vec();
+        expected.extend([
+            Instruction::Increment(1),
+            Instruction::JumpForward(7),
+            Instruction::Decrement(1),
+            Instruction::JumpBackward(5),
+        ]);
+        assert_eq!(deserialize(&joined).unwrap(), expected);
+
+        // A short loop far into a program still takes a byte per jump target
+        let mut long = vec![Instruction::MoveRight(1); 100_000];
+        long.extend([
+            Instruction::JumpForward(100_002),
+            Instruction::Decrement(1),
+            Instruction::JumpBackward(100_000),
+        ]);
+        let bytes = serialize(&long);
+        assert_eq!(bytes.len(), MAGIC.len() + 2 * 100_000 + 6);
+        assert_eq!(deserialize(&bytes).unwrap(), long);
+    }
+
+    #[test]
+    fn test_rejects_malformed_bytecode() {
+        assert!(deserialize(b"+++").is_err());
+        assert!(deserialize(&[&MAGIC[..], &[OUTPUT]].concat()).is_err());
+        assert!(deserialize(&[&MAGIC[..], &[200]].concat()).is_err());
+        let err = deserialize(b"BFC\x01\x04\x01").unwrap_err();
+        assert!(err.to_string().contains("unsupported bytecode version 1"), "{}", err);
+        let err = deserialize(b"BFC\x02\x04\x01").unwrap_err();
+        assert!(err.to_string().contains("compile the program again"), "{}", err);
+        let err = deserialize(&[&MAGIC[..], &[JUMP_BACKWARD, 3]].concat()).unwrap_err();
+        assert!(err.to_string().contains("lands outside the program"), "{}", err);
+
+        // A jump whose partner is missing would index out of the program
+        let unpaired = serialize(&[Instruction::JumpForward(5)]);
+        let err = deserialize(&unpaired).unwrap_err();
+        assert!(err.to_string().contains("jump at instruction 0 has no matching jump back"));
+    }
+}
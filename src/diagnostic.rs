@@ -0,0 +1,95 @@
+//! Rustc-style rendering of errors that point into the program source.
+
+use crate::error::Position;
+use std::fmt::Write as _;
+
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+/// Program source kept around so errors can quote it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceText {
+    /// Name shown after the `-->` arrow, usually the file path
+    name: String,
+    /// The full program text
+    text: String,
+}
+
+impl SourceText {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Self {
+        let mut text = text.into();
+        // The lexer skips a leading byte order mark, so columns start after it
+        if text.starts_with('\u{feff}') {
+            text.remove(0);
+        }
+        Self { name: name.into(), text }
+    }
+
+    /// The program text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Render `message` followed by the source line at `position` with a
+    /// caret under its column, using ANSI colors when `color` is set
+    ///
+    /// Tabs before the column are kept in the caret line so the caret still
+    /// lines up. A position past the end of the text only gets the arrow.
+    pub fn render(&self, message: &str, position: Position, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+        let line_number = position.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let mut rendered = paint(BOLD_RED, message);
+        let _ = write!(rendered, "\n{}{} {}:{}", gutter, paint(BOLD_BLUE, "-->"), self.name, position);
+
+        if let Some(line) = self.text.lines().nth(position.line.saturating_sub(1)) {
+            let indent: String = line
+                .chars()
+                .take(position.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let bar = paint(BOLD_BLUE, "|");
+            let _ = write!(rendered, "\n{} {}", gutter, bar);
+            let _ = write!(rendered, "\n{} {} {}", paint(BOLD_BLUE, &line_number), bar, line);
+            let _ = write!(rendered, "\n{} {} {}{}", gutter, bar, indent, paint(BOLD_RED, "^"));
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caret_under_column() {
+        let source = SourceText::new("prog.bf", "+++\n++[>+\n");
+        assert_eq!(
+            source.render("error: Unmatched bracket", Position::new(2, 3), false),
+            "error: Unmatched bracket\n --> prog.bf:2:3\n  |\n2 | ++[>+\n  |   ^"
+        );
+    }
+
+    #[test]
+    fn test_tabs_keep_caret_aligned() {
+        let source = SourceText::new("prog.bf", "\t+[");
+        let rendered = source.render("error", Position::new(1, 3), false);
+        assert!(rendered.ends_with("1 | \t+[\n  | \t ^"));
+    }
+
+    #[test]
+    fn test_color_and_missing_line() {
+        let source = SourceText::new("prog.bf", "+");
+        let rendered = source.render("error", Position::new(5, 1), true);
+        assert_eq!(rendered, "\x1b[1;31merror\x1b[0m\n \x1b[1;34m-->\x1b[0m prog.bf:5:1");
+    }
+}
@@ -35,8 +35,8 @@ pub enum BrainfuckError {
     #[error("Invalid character '{character}' at position {position}")]
     InvalidCharacter { character: char, position: Position },
 
-    #[error("Memory access out of bounds at address {address}")]
-    MemoryOutOfBounds { address: usize },
+    #[error("Memory access out of bounds: address {address} exceeds tape size {tape_size}")]
+    MemoryOutOfBounds { address: usize, tape_size: usize },
 
     #[error("Input/output error: {message}")]
     IoError { message: String },
@@ -46,6 +46,27 @@ pub enum BrainfuckError {
 
     #[error("Runtime error: {message}")]
     RuntimeError { message: String },
+
+    #[error("Invalid configuration: {message}")]
+    InvalidConfig { message: String },
+
+    #[error("Invalid bytecode: {message}")]
+    InvalidBytecode { message: String },
+
+    #[error("Execution interrupted after {steps} steps")]
+    Interrupted { steps: u64 },
+}
+
+impl BrainfuckError {
+    /// Where in the source the error occurred, if known
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Self::UnmatchedBracket { position }
+            | Self::InvalidCharacter { position, .. }
+            | Self::ParseError { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
 }
 
 /// Extension trait for Result to add context with positions
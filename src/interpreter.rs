@@ -1,85 +1,1192 @@
 use crate::error::BrainfuckError;
 use crate::optimizer::Instruction;
+use crate::tape::{Tape, TapeKind};
 use anyhow::Result;
-use std::io::{self, Read, Write};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Steps [`Interpreter::run`] takes between checks of the clock when a
+/// timeout is set, and of the interrupt flag when one is
+pub(crate) const TIMEOUT_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// Default for [`InterpreterConfig::input_buffer_size`], matching the
+/// standard library's own buffers
+const DEFAULT_INPUT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Every how many runs of an instruction its time is measured when timing,
+/// since reading the clock costs more than most instructions
+const TIMING_SAMPLE_INTERVAL: u64 = 16;
+
+/// How the `.` command encodes the current cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(clap::ValueEnum))]
+pub enum OutputMode {
+    /// Write the cell value as a single raw byte
+    #[default]
+    Bytes,
+    /// Treat the cell value as a Unicode code point and write its UTF-8 encoding
+    Utf8Codepoint,
+}
+
+/// How `\n` bytes written by `.` reach the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NewlineMode {
+    /// Write `\n` as is
+    #[default]
+    Raw,
+    /// Write `\n` as `\r\n`, as Windows tools expect
+    CrLf,
+}
+
+/// What `,` does when the input is exhausted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofBehavior {
+    /// Fail with an I/O error
+    #[default]
+    Error,
+    /// Leave the current cell unchanged
+    Unchanged,
+    /// Store 0 in the current cell
+    Zero,
+    /// Store 255 (-1) in the current cell
+    NegativeOne,
+    /// Store a specific byte in the current cell
+    Value(u8),
+}
+
+impl EofBehavior {
+    /// The value the cell should hold after EOF, or `None` if EOF is an error
+    fn apply(self, cell: u8) -> Option<u8> {
+        match self {
+            Self::Error => None,
+            Self::Unchanged => Some(cell),
+            Self::Zero => Some(0),
+            Self::NegativeOne => Some(u8::MAX),
+            Self::Value(value) => Some(value),
+        }
+    }
+}
 
 /// Configuration for the Brainfuck interpreter
 #[derive(Debug, Clone)]
 pub struct InterpreterConfig {
     /// Size of the memory tape (default: 30000)
     pub memory_size: usize,
+    /// How the memory tape is stored (default: dense)
+    ///
+    /// A sparse tape only stores cells that have been written, so
+    /// `memory_size` can be as large as `usize::MAX`.
+    pub tape: TapeKind,
     /// Whether to enable debug output
     pub debug: bool,
     /// Whether to enable optimizations
     pub optimize: bool,
+    /// How output cells are encoded (default: raw bytes)
+    pub output_mode: OutputMode,
+    /// How `\n` is written (default: as is)
+    ///
+    /// Only cells written by `.` are translated; wide output and padding are
+    /// written as is.
+    pub output_newline: NewlineMode,
+    /// What `,` does at end of input (default: error)
+    pub eof_behavior: EofBehavior,
+    /// Value every cell starts with (default: 0)
+    pub initial_cell_value: u8,
+    /// Cells shown on each side of the pointer by a debug dump (default: 8)
+    pub debug_window: usize,
+    /// Whether debug output shows cells as signed `i8` values (default: false)
+    ///
+    /// Cells are stored and wrap the same either way, and loops still test
+    /// them against zero; only how values are displayed changes.
+    pub signed: bool,
+    /// Modulus `+` and `-` wrap at instead of 256, between 1 and 256
+    /// (default: natural byte wrapping)
+    ///
+    /// Only `Increment` and `Decrement` honour it; the optimizer assumes byte
+    /// wrapping, so run unoptimized instructions when setting this.
+    pub cell_modulus: Option<u32>,
+    /// Whether to print a prompt before every byte `,` reads (default: false)
+    pub interactive_input: bool,
+    /// Most times a single run of any loop may jump back to its start before
+    /// execution fails (default: unlimited)
+    pub max_loop_iterations: Option<u64>,
+    /// Wall-clock time [`Interpreter::run`] may take before execution fails
+    /// (default: unlimited)
+    pub timeout: Option<Duration>,
+    /// Pad the output with zero bytes to a multiple of this size whenever
+    /// [`Interpreter::run`] finishes successfully (default: no padding)
+    pub pad_output: Option<NonZeroUsize>,
+    /// Most bytes the program may write before execution fails, counting
+    /// padding (default: unlimited)
+    ///
+    /// Output up to the limit is still written.
+    pub max_output_bytes: Option<u64>,
+    /// Whether `.` and `,` work on 16-bit values spread across the current
+    /// and next cell, high byte first (default: false)
+    ///
+    /// `.` writes both cells as raw bytes whatever the output mode, and `,`
+    /// reads two bytes. Both fail when the next cell is past the end of the
+    /// tape. The optimizer folds output of known cells a byte at a time, so
+    /// optimize at most to [`OptLevel::Fold`](crate::optimizer::OptLevel::Fold)
+    /// when setting this.
+    pub wide_io: bool,
+    /// Most bytes of cells the tape may hold (default: unlimited)
+    ///
+    /// A dense tape holds every cell from the start, so
+    /// [`InterpreterConfigBuilder::build`] rejects one with more cells than
+    /// this. A sparse tape stores cells as they are written, and execution
+    /// fails once it holds more. Every cell is one byte.
+    pub max_memory_bytes: Option<usize>,
+    /// Whether `.` and `,` may touch the output and input (default: true)
+    ///
+    /// When disabled, running either fails instead, so an untrusted program
+    /// can only compute on the tape.
+    pub allow_io: bool,
+    /// Bytes of input read ahead at a time (default: 8 KiB)
+    ///
+    /// Input is buffered so `,` does not cost a read call per byte. Zero
+    /// reads straight from the source.
+    pub input_buffer_size: usize,
 }
 
 impl Default for InterpreterConfig {
     fn default() -> Self {
         Self {
             memory_size: 30000,
+            tape: TapeKind::Dense,
             debug: false,
             optimize: true,
+            output_mode: OutputMode::Bytes,
+            output_newline: NewlineMode::Raw,
+            eof_behavior: EofBehavior::Error,
+            initial_cell_value: 0,
+            debug_window: 8,
+            signed: false,
+            cell_modulus: None,
+            interactive_input: false,
+            max_loop_iterations: None,
+            timeout: None,
+            pad_output: None,
+            max_output_bytes: None,
+            wide_io: false,
+            max_memory_bytes: None,
+            allow_io: true,
+            input_buffer_size: DEFAULT_INPUT_BUFFER_SIZE,
+        }
+    }
+}
+
+impl InterpreterConfig {
+    /// Start building a configuration from the defaults
+    pub fn builder() -> InterpreterConfigBuilder {
+        InterpreterConfigBuilder::default()
+    }
+}
+
+/// Builds an [`InterpreterConfig`] with chainable setters, checking the
+/// settings fit together once at the end
+#[derive(Debug, Clone, Default)]
+pub struct InterpreterConfigBuilder {
+    config: InterpreterConfig,
+}
+
+impl InterpreterConfigBuilder {
+    /// Set [`InterpreterConfig::memory_size`]
+    pub fn memory_size(mut self, memory_size: usize) -> Self {
+        self.config.memory_size = memory_size;
+        self
+    }
+
+    /// Set [`InterpreterConfig::tape`]
+    pub fn tape(mut self, tape: TapeKind) -> Self {
+        self.config.tape = tape;
+        self
+    }
+
+    /// Set [`InterpreterConfig::debug`]
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.config.debug = debug;
+        self
+    }
+
+    /// Set [`InterpreterConfig::optimize`]
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.config.optimize = optimize;
+        self
+    }
+
+    /// Set [`InterpreterConfig::output_mode`]
+    pub fn output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.config.output_mode = output_mode;
+        self
+    }
+
+    /// Set [`InterpreterConfig::output_newline`]
+    pub fn output_newline(mut self, output_newline: NewlineMode) -> Self {
+        self.config.output_newline = output_newline;
+        self
+    }
+
+    /// Set [`InterpreterConfig::eof_behavior`]
+    pub fn eof_behavior(mut self, eof_behavior: EofBehavior) -> Self {
+        self.config.eof_behavior = eof_behavior;
+        self
+    }
+
+    /// Set [`InterpreterConfig::initial_cell_value`]
+    pub fn initial_cell_value(mut self, initial_cell_value: u8) -> Self {
+        self.config.initial_cell_value = initial_cell_value;
+        self
+    }
+
+    /// Set [`InterpreterConfig::debug_window`]
+    pub fn debug_window(mut self, debug_window: usize) -> Self {
+        self.config.debug_window = debug_window;
+        self
+    }
+
+    /// Set [`InterpreterConfig::signed`]
+    pub fn signed(mut self, signed: bool) -> Self {
+        self.config.signed = signed;
+        self
+    }
+
+    /// Set [`InterpreterConfig::cell_modulus`]
+    pub fn cell_modulus(mut self, cell_modulus: Option<u32>) -> Self {
+        self.config.cell_modulus = cell_modulus;
+        self
+    }
+
+    /// Set [`InterpreterConfig::interactive_input`]
+    pub fn interactive_input(mut self, interactive_input: bool) -> Self {
+        self.config.interactive_input = interactive_input;
+        self
+    }
+
+    /// Set [`InterpreterConfig::max_loop_iterations`]
+    pub fn max_loop_iterations(mut self, max_loop_iterations: Option<u64>) -> Self {
+        self.config.max_loop_iterations = max_loop_iterations;
+        self
+    }
+
+    /// Set [`InterpreterConfig::timeout`]
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Set [`InterpreterConfig::pad_output`]
+    pub fn pad_output(mut self, pad_output: Option<NonZeroUsize>) -> Self {
+        self.config.pad_output = pad_output;
+        self
+    }
+
+    /// Set [`InterpreterConfig::max_output_bytes`]
+    pub fn max_output_bytes(mut self, max_output_bytes: Option<u64>) -> Self {
+        self.config.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    /// Set [`InterpreterConfig::wide_io`]
+    pub fn wide_io(mut self, wide_io: bool) -> Self {
+        self.config.wide_io = wide_io;
+        self
+    }
+
+    /// Set [`InterpreterConfig::max_memory_bytes`]
+    pub fn max_memory_bytes(mut self, max_memory_bytes: Option<usize>) -> Self {
+        self.config.max_memory_bytes = max_memory_bytes;
+        self
+    }
+
+    /// Set [`InterpreterConfig::allow_io`]
+    pub fn allow_io(mut self, allow_io: bool) -> Self {
+        self.config.allow_io = allow_io;
+        self
+    }
+
+    /// Set [`InterpreterConfig::input_buffer_size`]
+    pub fn input_buffer_size(mut self, input_buffer_size: usize) -> Self {
+        self.config.input_buffer_size = input_buffer_size;
+        self
+    }
+
+    /// Check the settings and return the configuration
+    ///
+    /// Fails if the tape is empty, a dense tape would take more than the
+    /// memory limit, or the cell modulus is outside 1 to 256.
+    pub fn build(self) -> Result<InterpreterConfig> {
+        let invalid = |message: &str| -> Result<InterpreterConfig> {
+            Err(BrainfuckError::InvalidConfig {
+                message: message.to_string(),
+            }
+            .into())
+        };
+
+        if self.config.memory_size == 0 {
+            return invalid("memory size must be greater than 0");
+        }
+        if let Some(max) = self.config.max_memory_bytes {
+            if self.config.tape == TapeKind::Dense && self.config.memory_size > max {
+                return invalid(&format!(
+                    "a dense tape of {} cells needs more than the memory limit of {} bytes",
+                    self.config.memory_size, max
+                ));
+            }
+        }
+        if self.config.cell_modulus.is_some_and(|modulus| !(1..=256).contains(&modulus)) {
+            return invalid("cell modulus must be between 1 and 256");
         }
+        Ok(self.config)
     }
 }
 
+/// A saved copy of the interpreter's execution state
+///
+/// Taking a snapshot clones the whole memory tape, so its cost grows with
+/// `memory_size`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterpreterState {
+    /// Memory tape contents
+    pub memory: Tape,
+    /// Memory pointer position
+    pub pointer: usize,
+    /// Instruction pointer
+    pub instruction_pointer: usize,
+}
+
+/// The behaviour of a custom command character
+///
+/// Lex the character with [`LexerConfig::extensions`](crate::lexer::LexerConfig::extensions)
+/// and register the handler with [`Interpreter::register_handler`].
+pub trait InstructionHandler {
+    /// The character this handler runs for
+    fn character(&self) -> char;
+
+    /// Run the command with the tape and memory pointer
+    ///
+    /// The pointer must still be on the tape afterwards.
+    fn execute(&mut self, memory: &mut Tape, pointer: &mut usize) -> Result<()>;
+}
+
+/// An instruction that has just run, as passed to the callback set with
+/// [`Interpreter::set_on_step`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepEvent<'a> {
+    /// Index of the instruction
+    pub instruction_pointer: usize,
+    /// Memory pointer after the instruction ran
+    pub pointer: usize,
+    /// The instruction itself
+    pub instruction: &'a Instruction,
+}
+
+/// A callback run after every executed instruction
+pub type StepCallback = Box<dyn FnMut(&StepEvent)>;
+
+/// A watched cell that changed during [`Interpreter::run_until_watchpoint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// Address of the watched cell
+    pub address: usize,
+    /// Value before the instruction that changed it
+    pub old: u8,
+    /// Value after the instruction that changed it
+    pub new: u8,
+}
+
 /// The Brainfuck interpreter that executes optimized instructions
-pub struct Interpreter {
+///
+/// Program input is read from `R` and output written to `W`, which default to
+/// stdin and stdout.
+pub struct Interpreter<R = io::Stdin, W = io::Stdout> {
     /// The program instructions
     instructions: Vec<Instruction>,
-    /// Memory tape (array of u8 cells)
-    memory: Vec<u8>,
+    /// Memory tape of u8 cells
+    memory: Tape,
     /// Current memory pointer position
     pointer: usize,
+    /// Highest address the program has reached, by moving there or touching
+    /// a cell at an offset
+    max_pointer: usize,
+    /// One bit per cell ever read or written, when tracking a dense tape
+    touched: Option<Vec<u64>>,
     /// Current instruction pointer
     instruction_pointer: usize,
+    /// Number of instructions executed so far
+    steps: u64,
+    /// Scratch cell for `$` and `}`
+    register: u8,
     /// Configuration
     config: InterpreterConfig,
+    /// Source of bytes for `,`
+    input: BufReader<R>,
+    /// Further sources `~` switches `,` to, after `input`
+    extra_inputs: Vec<BufReader<Box<dyn Read>>>,
+    /// Which source `,` reads from: 0 for `input`, then each extra input
+    active_input: usize,
+    /// Destination of bytes written by `.`
+    output: W,
+    /// Optional destination for a tab-separated execution trace
+    trace: Option<Box<dyn Write>>,
+    /// Recorded trace every step is checked against, past its header row
+    replay: Option<Box<dyn BufRead>>,
+    /// Destination for debug dumps, or stderr when unset
+    debug_output: Option<Box<dyn Write>>,
+    /// Destination for interactive input prompts, or stderr when unset
+    prompt_output: Option<Box<dyn Write>>,
+    /// How many times each instruction has run, when profiling
+    profile: Option<Vec<u64>>,
+    /// Time spent in the sampled runs of each instruction, when timing
+    timing: Option<Vec<Duration>>,
+    /// Addresses whose changes stop [`Interpreter::run_until_watchpoint`]
+    watchpoints: Vec<usize>,
+    /// Backward jumps taken by the current run of each loop, indexed by its `]`
+    loop_iterations: Vec<u64>,
+    /// Loops that can run without bounds checks, keyed by their `[` index
+    safe_loops: HashMap<usize, SafeLoop>,
+    /// Steps between clock and interrupt checks
+    timeout_check_interval: u64,
+    /// When the current run started, and how long it may take
+    deadline: Option<(Instant, Duration)>,
+    /// Set from elsewhere, such as a signal handler, to stop the run
+    interrupt: Option<Arc<AtomicBool>>,
+    /// Bytes written to `output` so far, for padding
+    bytes_written: usize,
+    /// Handlers for custom commands
+    handlers: Vec<Box<dyn InstructionHandler>>,
+    /// Called after every executed instruction
+    on_step: Option<StepCallback>,
+}
+
+/// An innermost loop whose pointer range is the same on every iteration
+///
+/// The body contains only instructions that move the pointer by a fixed
+/// amount or touch cells at fixed offsets, and its moves sum to zero. Each
+/// iteration therefore starts with the pointer where the `[` left it and
+/// touches exactly the cells from `lowest` to `highest` relative to it. If
+/// that whole range lies on the tape when the loop is entered, no iteration
+/// can leave it, so the per-instruction bounds checks can be skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SafeLoop {
+    /// Index of the matching `]`
+    end: usize,
+    /// Lowest offset from the loop's starting pointer touched by the body
+    lowest: isize,
+    /// Highest offset from the loop's starting pointer touched by the body
+    highest: isize,
+}
+
+/// Find every innermost loop that qualifies as a [`SafeLoop`]
+fn find_safe_loops(instructions: &[Instruction]) -> HashMap<usize, SafeLoop> {
+    let mut safe_loops = HashMap::new();
+
+    for (start, instruction) in instructions.iter().enumerate() {
+        let Instruction::JumpForward(end) = *instruction else {
+            continue;
+        };
+        if let Some((lowest, highest)) = instructions.get(start + 1..end).and_then(loop_range) {
+            safe_loops.insert(start, SafeLoop { end, lowest, highest });
+        }
+    }
+
+    safe_loops
+}
+
+/// The range of offsets a loop body touches, or `None` if the body moves the
+/// pointer by a data-dependent or non-zero net amount
+fn loop_range(body: &[Instruction]) -> Option<(isize, isize)> {
+    let mut offset: isize = 0;
+    let (mut lowest, mut highest) = (0, 0);
+    let mut touch = |offset: isize| {
+        lowest = lowest.min(offset);
+        highest = highest.max(offset);
+    };
+
+    for instruction in body {
+        match instruction {
+            Instruction::MoveRight(n) => offset = offset.checked_add(isize::try_from(*n).ok()?)?,
+            Instruction::MoveLeft(n) => offset = offset.checked_sub(isize::try_from(*n).ok()?)?,
+            Instruction::Increment(_)
+            | Instruction::Decrement(_)
+            | Instruction::Output(_)
+            | Instruction::Input(_)
+            | Instruction::SetZero
+            | Instruction::SetValue(_)
+            | Instruction::PrintConst(_)
+            | Instruction::DebugDump
+            | Instruction::StoreRegister
+            | Instruction::RestoreRegister
+            | Instruction::SwitchInput
+            | Instruction::Nop => {}
+            Instruction::Copy { targets } => {
+                for &target in targets {
+                    touch(offset.checked_add(target)?);
+                }
+            }
+            Instruction::MultiplyAdd { targets } => {
+                for &(target, _) in targets {
+                    touch(offset.checked_add(target)?);
+                }
+            }
+            Instruction::AddAtOffset { offset: target, .. } | Instruction::SetAtOffset { offset: target, .. } => {
+                touch(offset.checked_add(*target)?)
+            }
+            // Nested loops, scans, echo loops, halts and custom commands change
+            // control flow or move the pointer by an amount only known at run
+            // time
+            Instruction::JumpForward(_)
+            | Instruction::JumpBackward(_)
+            | Instruction::ScanAdd { .. }
+            | Instruction::ScanRight(_)
+            | Instruction::ScanLeft(_)
+            | Instruction::EchoStream
+            | Instruction::Halt
+            | Instruction::Extension(_) => return None,
+        }
+        touch(offset);
+    }
+
+    (offset == 0).then_some((lowest, highest))
 }
 
 impl Interpreter {
     /// Create a new interpreter with the given instructions and configuration
+    /// that reads from stdin and writes to stdout
     pub fn new(instructions: Vec<Instruction>, config: InterpreterConfig) -> Self {
+        Self::with_io(instructions, config, io::stdin(), io::stdout())
+    }
+}
+
+impl<R, W> Interpreter<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Create a new interpreter with the given instructions and configuration
+    /// that uses the provided input and output streams
+    pub fn with_io(instructions: Vec<Instruction>, config: InterpreterConfig, input: R, output: W) -> Self {
         Self {
-            memory: vec![0; config.memory_size],
+            memory: Tape::new(config.tape, config.memory_size, config.initial_cell_value),
             pointer: 0,
+            max_pointer: 0,
+            touched: None,
             instruction_pointer: 0,
-            instructions,
+            steps: 0,
+            register: 0,
+            input: BufReader::with_capacity(config.input_buffer_size, input),
             config,
+            extra_inputs: Vec::new(),
+            active_input: 0,
+            output,
+            trace: None,
+            replay: None,
+            debug_output: None,
+            prompt_output: None,
+            profile: None,
+            timing: None,
+            watchpoints: Vec::new(),
+            loop_iterations: vec![0; instructions.len()],
+            safe_loops: find_safe_loops(&instructions),
+            instructions,
+            timeout_check_interval: TIMEOUT_CHECK_INTERVAL,
+            deadline: None,
+            interrupt: None,
+            bytes_written: 0,
+            handlers: Vec::new(),
+            on_step: None,
+        }
+    }
+
+    /// Add another source of bytes for `,`, which `~` switches to after
+    /// the sources added before it
+    ///
+    /// `~` cycles through the sources and back to the first, and is only a
+    /// command when [`LexerConfig::input_switch`](crate::lexer::LexerConfig::input_switch)
+    /// is set.
+    pub fn add_input(&mut self, reader: Box<dyn Read>) {
+        self.extra_inputs.push(BufReader::with_capacity(self.config.input_buffer_size, reader));
+    }
+
+    /// Run `handler` for every custom command with its character, replacing
+    /// any handler already registered for it
+    pub fn register_handler(&mut self, handler: Box<dyn InstructionHandler>) {
+        let character = handler.character();
+        self.handlers.retain(|existing| existing.character() != character);
+        self.handlers.push(handler);
+    }
+
+    /// Call `callback` after every executed instruction
+    ///
+    /// Like a trace, this disables the fast path for loops so every step is
+    /// seen.
+    pub fn set_on_step(&mut self, callback: StepCallback) {
+        self.on_step = Some(callback);
+    }
+
+    /// Stop [`Interpreter::run`] with [`BrainfuckError::Interrupted`] soon
+    /// after `flag` is set, leaving the state as it was after the last step
+    ///
+    /// The flag is polled every few thousand steps and on every iteration of
+    /// a loop taking the fast path, so setting it costs nothing until then.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Send debug dumps to `writer` instead of stderr
+    pub fn set_debug_output(&mut self, writer: Box<dyn Write>) {
+        self.debug_output = Some(writer);
+    }
+
+    /// Start counting how many times each instruction runs
+    ///
+    /// Profiling disables the fast path for loops so every step is counted.
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(vec![0; self.instructions.len()]);
+    }
+
+    /// Execution count of each instruction, if profiling is enabled
+    pub fn profile(&self) -> Option<&[u64]> {
+        self.profile.as_deref()
+    }
+
+    /// Start recording which cells the program reads or writes, for
+    /// [`touched_cells`](Self::touched_cells)
+    ///
+    /// The record is a bitset sized to the tape, so only dense tapes are
+    /// tracked. Like the profile, tracking disables the fast path for loops.
+    pub fn enable_touch_tracking(&mut self) {
+        if let Some(cells) = self.memory.as_slice() {
+            self.touched = Some(vec![0; cells.len().div_ceil(64)]);
+        }
+    }
+
+    /// Number of distinct cells read or written so far, if tracking is
+    /// enabled
+    ///
+    /// Moving the pointer over a cell does not touch it, and neither does a
+    /// constant folded from cells known at optimize time.
+    pub fn touched_cells(&self) -> Option<usize> {
+        self.touched
+            .as_ref()
+            .map(|touched| touched.iter().map(|word| word.count_ones() as usize).sum())
+    }
+
+    /// Start measuring the wall-clock time spent in each instruction, which
+    /// also enables the profile
+    ///
+    /// Only every sixteenth run of each instruction is timed, starting with
+    /// the first, and the samples are scaled up to the number of runs, so
+    /// the totals are estimates for instructions that run more than once.
+    pub fn enable_timing(&mut self) {
+        if self.profile.is_none() {
+            self.enable_profile();
+        }
+        self.timing = Some(vec![Duration::ZERO; self.instructions.len()]);
+    }
+
+    /// Estimated time spent in each instruction, if timing is enabled
+    pub fn timing(&self) -> Option<Vec<Duration>> {
+        let (timing, profile) = (self.timing.as_ref()?, self.profile.as_ref()?);
+        let estimates = timing.iter().zip(profile).map(|(&sampled, &runs)| match runs.div_ceil(TIMING_SAMPLE_INTERVAL) {
+            0 => sampled,
+            samples => sampled.mul_f64(runs as f64 / samples as f64),
+        });
+        Some(estimates.collect())
+    }
+
+    /// Send interactive input prompts to `writer` instead of stderr
+    pub fn set_prompt_output(&mut self, writer: Box<dyn Write>) {
+        self.prompt_output = Some(writer);
+    }
+
+    /// Record every executed instruction to `writer`
+    ///
+    /// The trace is tab-separated with a header row followed by one row per
+    /// step: `step`, `ip`, `pointer`, `cell` and `instruction`, all captured
+    /// before the instruction runs.
+    pub fn set_trace(&mut self, mut writer: Box<dyn Write>) -> Result<()> {
+        writeln!(writer, "{}", TRACE_HEADER).map_err(|e| {
+            BrainfuckError::IoError {
+                message: format!("Failed to write trace: {}", e),
+            }
+        })?;
+        self.trace = Some(writer);
+        Ok(())
+    }
+
+    /// Check every step against a trace written by
+    /// [`set_trace`](Self::set_trace), failing at the first step whose
+    /// instruction pointer, pointer or cell differs from the recorded row
+    ///
+    /// [`Interpreter::run`] also fails if the program finishes before the
+    /// trace does. Like a trace, this disables the fast path for loops.
+    pub fn set_replay(&mut self, mut reader: Box<dyn BufRead>) -> Result<()> {
+        let mut header = String::new();
+        reader.read_line(&mut header).map_err(replay_read_error)?;
+        if header.trim_end() != TRACE_HEADER {
+            return Err(BrainfuckError::RuntimeError {
+                message: "replay trace does not start with the trace header row".to_string(),
+            }
+            .into());
         }
+        self.replay = Some(reader);
+        Ok(())
     }
 
     /// Run the interpreter until completion
     pub fn run(&mut self) -> Result<()> {
-        while self.instruction_pointer < self.instructions.len() {
-            if self.config.debug {
-                eprintln!(
-                    "IP: {}, PTR: {}, CELL: {}, INST: {:?}",
-                    self.instruction_pointer,
-                    self.pointer,
-                    self.memory[self.pointer],
-                    self.instructions[self.instruction_pointer]
-                );
+        let result = self
+            .run_to_end()
+            .and_then(|()| self.finish_replay())
+            .and_then(|()| self.pad_output());
+
+        // Make sure the trace is complete even if execution failed
+        if let Some(trace) = &mut self.trace {
+            trace.flush().map_err(|e| {
+                BrainfuckError::IoError {
+                    message: format!("Failed to flush trace: {}", e),
+                }
+            })?;
+        }
+
+        result
+    }
+
+    /// Watch the cell at `address` for changes
+    pub fn add_watchpoint(&mut self, address: usize) -> Result<()> {
+        if address >= self.memory.len() {
+            return Err(BrainfuckError::MemoryOutOfBounds {
+                address,
+                tape_size: self.memory.len(),
+            }
+            .into());
+        }
+        if !self.watchpoints.contains(&address) {
+            self.watchpoints.push(address);
+        }
+        Ok(())
+    }
+
+    /// Stop watching the cell at `address`
+    pub fn remove_watchpoint(&mut self, address: usize) {
+        self.watchpoints.retain(|&watched| watched != address);
+    }
+
+    /// Step until an instruction changes a watched cell, returning which one
+    /// and how, or `None` once the program finishes
+    ///
+    /// When one instruction changes several watched cells, the one added
+    /// first is reported. Calling this again resumes after that instruction.
+    pub fn run_until_watchpoint(&mut self) -> Result<Option<WatchpointHit>> {
+        loop {
+            let before: Vec<u8> = self.watchpoints.iter().map(|&address| self.memory[address]).collect();
+            if !self.step()? {
+                return Ok(None);
+            }
+
+            let changed = self
+                .watchpoints
+                .iter()
+                .zip(before)
+                .find(|&(&address, old)| self.memory[address] != old);
+            if let Some((&address, old)) = changed {
+                return Ok(Some(WatchpointHit {
+                    address,
+                    old,
+                    new: self.memory[address],
+                }));
+            }
+        }
+    }
+
+    /// Step until the program finishes or fails
+    ///
+    /// Loops found safe by [`find_safe_loops`] run through
+    /// [`run_safe_loop`](Self::run_safe_loop) unless debug output, a trace or
+    /// replay, the profile, touch tracking, the step callback, the loop
+    /// iteration limit or the timeout needs to see every step. The clock and
+    /// the interrupt flag are only read every `timeout_check_interval` steps.
+    fn run_to_end(&mut self) -> Result<()> {
+        let fast_path = !self.config.debug
+            && self.trace.is_none()
+            && self.replay.is_none()
+            && self.profile.is_none()
+            && self.touched.is_none()
+            && self.on_step.is_none()
+            && self.config.max_loop_iterations.is_none()
+            && self.config.timeout.is_none();
+        self.deadline = self.config.timeout.map(|timeout| (Instant::now(), timeout));
+        let mut next_check = self.steps.saturating_add(self.timeout_check_interval);
+
+        loop {
+            if self.steps >= next_check {
+                next_check = self.steps.saturating_add(self.timeout_check_interval);
+                self.check_timeout()?;
+                self.check_interrupt()?;
             }
 
-            self.execute_instruction()?;
+            if fast_path {
+                if let Some(&safe_loop) = self.safe_loops.get(&self.instruction_pointer) {
+                    if self.memory[self.pointer] != 0 && self.loop_in_bounds(safe_loop) {
+                        self.run_safe_loop(safe_loop)?;
+                        continue;
+                    }
+                }
+            }
+
+            if !self.step()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether every cell `safe_loop` touches is on the tape from the current
+    /// pointer
+    fn loop_in_bounds(&self, safe_loop: SafeLoop) -> bool {
+        let lowest = self.pointer.checked_add_signed(safe_loop.lowest);
+        let highest = self.pointer.checked_add_signed(safe_loop.highest);
+        lowest.is_some() && highest.is_some_and(|address| address < self.memory.len())
+    }
+
+    /// Run the loop at the instruction pointer to completion without checking
+    /// pointer moves against the tape bounds
+    ///
+    /// The caller must have checked [`loop_in_bounds`](Self::loop_in_bounds)
+    /// and that the current cell is not zero. Steps are counted exactly as if
+    /// every instruction had been stepped.
+    fn run_safe_loop(&mut self, safe_loop: SafeLoop) -> Result<()> {
+        let start = self.instruction_pointer;
+        // The `[`
+        self.steps += 1;
+        // Every iteration covers the same range, and the first always runs
+        self.max_pointer = self.max_pointer.max(self.pointer.wrapping_add_signed(safe_loop.highest));
+
+        loop {
+            for index in start + 1..safe_loop.end {
+                match self.instructions[index] {
+                    Instruction::MoveRight(count) => self.pointer += count,
+                    Instruction::MoveLeft(count) => self.pointer -= count,
+                    Instruction::AddAtOffset { offset, value } => {
+                        let address = self.pointer.wrapping_add_signed(offset);
+                        self.memory[address] = self.memory[address].wrapping_add(value);
+                    }
+                    Instruction::SetAtOffset { offset, value } => {
+                        self.memory[self.pointer.wrapping_add_signed(offset)] = value;
+                    }
+                    _ => {
+                        self.instruction_pointer = index;
+                        self.execute_instruction()?;
+                    }
+                }
+                self.steps += 1;
+            }
+
+            // The `]`, which jumps back to re-run the `[` when the cell is set
+            self.steps += 1;
+            if self.memory[self.pointer] == 0 {
+                break;
+            }
+            self.instruction_pointer = start;
+            self.check_interrupt()?;
+            self.check_memory_limit()?;
+            self.steps += 1;
         }
+
+        self.instruction_pointer = safe_loop.end + 1;
         Ok(())
     }
 
+    /// Fail if I/O is disabled, naming what the program tried to do
+    fn check_io_allowed(&self, what: &str) -> Result<()> {
+        if self.config.allow_io {
+            return Ok(());
+        }
+        Err(BrainfuckError::RuntimeError {
+            message: format!("program tried to use {} with I/O disabled", what),
+        }
+        .into())
+    }
+
+    /// Fail if a sparse tape has grown past the memory limit
+    fn check_memory_limit(&self) -> Result<()> {
+        match self.config.max_memory_bytes {
+            Some(max) if self.memory.allocated() > max => Err(BrainfuckError::RuntimeError {
+                message: format!("tape grew past the memory limit of {} bytes", max),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compare the state before this step with the next row of the replayed
+    /// trace
+    fn check_replay(&mut self) -> Result<()> {
+        let Some(reader) = &mut self.replay else {
+            return Ok(());
+        };
+        let mut row = String::new();
+        reader.read_line(&mut row).map_err(replay_read_error)?;
+
+        let actual = [
+            self.instruction_pointer.to_string(),
+            self.pointer.to_string(),
+            self.memory[self.pointer].to_string(),
+        ];
+        let expected: Vec<&str> = row.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let message = match expected.as_slice() {
+            [] | [""] => format!("the trace ended but execution continued at ip {}", actual[0]),
+            [step, ip, pointer, cell, ..] if *step == self.steps.to_string() => {
+                if [*ip, *pointer, *cell] == actual.each_ref().map(String::as_str) {
+                    return Ok(());
+                }
+                format!(
+                    "expected ip {}, pointer {}, cell {} but found ip {}, pointer {}, cell {}",
+                    ip, pointer, cell, actual[0], actual[1], actual[2]
+                )
+            }
+            _ => format!("malformed trace row {:?}", row.trim_end()),
+        };
+        Err(BrainfuckError::RuntimeError {
+            message: format!("replay diverged at step {}: {}", self.steps, message),
+        }
+        .into())
+    }
+
+    /// Fail if the replayed trace has rows left after the program finished
+    fn finish_replay(&mut self) -> Result<()> {
+        let Some(reader) = &mut self.replay else {
+            return Ok(());
+        };
+        let mut row = String::new();
+        reader.read_line(&mut row).map_err(replay_read_error)?;
+        if row.trim_end().is_empty() {
+            return Ok(());
+        }
+        Err(BrainfuckError::RuntimeError {
+            message: format!("replay diverged at step {}: the program finished but the trace continues", self.steps),
+        }
+        .into())
+    }
+
+    /// Record the cells touched by the instruction at `index`, given the
+    /// pointer and current cell from before it ran
+    fn mark_touched(&mut self, index: usize, pointer: usize, cell: u8) {
+        let Some(touched) = &mut self.touched else {
+            return;
+        };
+        let mut mark = |address: usize| touched[address / 64] |= 1 << (address % 64);
+
+        match &self.instructions[index] {
+            Instruction::MoveRight(_)
+            | Instruction::MoveLeft(_)
+            | Instruction::PrintConst(_)
+            | Instruction::Halt
+            | Instruction::SwitchInput
+            | Instruction::Nop => {}
+            Instruction::AddAtOffset { offset, .. } | Instruction::SetAtOffset { offset, .. } => {
+                mark(pointer.wrapping_add_signed(*offset))
+            }
+            Instruction::Copy { targets } => {
+                mark(pointer);
+                if cell != 0 {
+                    targets.iter().for_each(|&target| mark(pointer.wrapping_add_signed(target)));
+                }
+            }
+            Instruction::MultiplyAdd { targets } => {
+                mark(pointer);
+                if cell != 0 {
+                    targets.iter().for_each(|&(target, _)| mark(pointer.wrapping_add_signed(target)));
+                }
+            }
+            // Every stop is tested, and all but the last add to their cells
+            Instruction::ScanAdd { stride, adds } => {
+                let mut stop = pointer;
+                while stop != self.pointer {
+                    mark(stop);
+                    adds.iter().for_each(|&(offset, _)| mark(stop.wrapping_add_signed(offset)));
+                    stop = stop.wrapping_add_signed(*stride);
+                }
+                mark(stop);
+            }
+            Instruction::ScanRight(stride) | Instruction::ScanLeft(stride) => {
+                let (low, high) = (pointer.min(self.pointer), pointer.max(self.pointer));
+                (low..=high).step_by(*stride).for_each(&mut mark);
+            }
+            instruction => {
+                mark(pointer);
+                let wide = matches!(instruction, Instruction::Output(_) | Instruction::Input(_) | Instruction::EchoStream);
+                if wide && self.config.wide_io {
+                    mark(pointer + 1);
+                }
+            }
+        }
+    }
+
+    /// Fail if the current run has taken longer than its timeout
+    fn check_timeout(&self) -> Result<()> {
+        match self.deadline {
+            Some((start, timeout)) if start.elapsed() > timeout => Err(BrainfuckError::RuntimeError {
+                message: format!("execution exceeded the {:?} timeout", timeout),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if the interrupt flag has been set
+    fn check_interrupt(&self) -> Result<()> {
+        match &self.interrupt {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(BrainfuckError::Interrupted { steps: self.steps }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Execute a single instruction
+    ///
+    /// Returns `Ok(false)` without doing anything once the program has finished.
+    pub fn step(&mut self) -> Result<bool> {
+        if self.instruction_pointer >= self.instructions.len() {
+            return Ok(false);
+        }
+
+        if self.config.debug {
+            eprintln!(
+                "IP: {}, PTR: {}, CELL: {}, INST: {:?}",
+                self.instruction_pointer,
+                self.pointer,
+                self.display_value(self.memory[self.pointer]),
+                self.instructions[self.instruction_pointer]
+            );
+        }
+
+        if let Some(trace) = &mut self.trace {
+            writeln!(
+                trace,
+                "{}\t{}\t{}\t{}\t{:?}",
+                self.steps,
+                self.instruction_pointer,
+                self.pointer,
+                self.memory[self.pointer],
+                self.instructions[self.instruction_pointer]
+            )
+            .map_err(|e| {
+                BrainfuckError::IoError {
+                    message: format!("Failed to write trace: {}", e),
+                }
+            })?;
+        }
+
+        self.check_replay()?;
+
+        let index = self.instruction_pointer;
+        let sampled = match (&self.timing, &self.profile) {
+            (Some(_), Some(profile)) => profile[index] % TIMING_SAMPLE_INTERVAL == 0,
+            _ => false,
+        };
+        let started = sampled.then(Instant::now);
+        let before = self.touched.is_some().then(|| (self.pointer, self.memory[self.pointer]));
+        self.execute_instruction()?;
+        if let Some((pointer, cell)) = before {
+            self.mark_touched(index, pointer, cell);
+        }
+        if let (Some(started), Some(timing)) = (started, &mut self.timing) {
+            timing[index] += started.elapsed();
+        }
+        self.check_memory_limit()?;
+        self.steps += 1;
+        if let Some(profile) = &mut self.profile {
+            profile[index] += 1;
+        }
+        if let Some(on_step) = &mut self.on_step {
+            on_step(&StepEvent {
+                instruction_pointer: index,
+                pointer: self.pointer,
+                instruction: &self.instructions[index],
+            });
+        }
+        Ok(true)
+    }
+
+    /// Replace the program, keeping the tape and memory pointer as they are
+    fn load(&mut self, instructions: Vec<Instruction>) {
+        self.safe_loops = find_safe_loops(&instructions);
+        self.loop_iterations = vec![0; instructions.len()];
+        if self.profile.is_some() {
+            self.profile = Some(vec![0; instructions.len()]);
+        }
+        if self.timing.is_some() {
+            self.timing = Some(vec![Duration::ZERO; instructions.len()]);
+        }
+        self.instructions = instructions;
+        self.instruction_pointer = 0;
+    }
+
+    /// Refill the tape and rewind both pointers so the program can run again
+    pub fn reset(&mut self) {
+        self.memory.fill(self.config.initial_cell_value);
+        self.pointer = 0;
+        self.max_pointer = 0;
+        if let Some(touched) = &mut self.touched {
+            touched.fill(0);
+        }
+        self.instruction_pointer = 0;
+        self.steps = 0;
+        self.register = 0;
+        self.active_input = 0;
+        self.bytes_written = 0;
+        self.loop_iterations.fill(0);
+        if let Some(profile) = &mut self.profile {
+            profile.fill(0);
+        }
+        if let Some(timing) = &mut self.timing {
+            timing.fill(Duration::ZERO);
+        }
+    }
+
+    /// Capture the current memory, pointer and instruction pointer
+    ///
+    /// This clones the entire memory tape.
+    pub fn snapshot(&self) -> InterpreterState {
+        InterpreterState {
+            memory: self.memory.clone(),
+            pointer: self.pointer,
+            instruction_pointer: self.instruction_pointer,
+        }
+    }
+
+    /// Restore a state previously captured with [`Interpreter::snapshot`]
+    pub fn restore(&mut self, state: InterpreterState) {
+        self.memory = state.memory;
+        self.pointer = state.pointer;
+        self.instruction_pointer = state.instruction_pointer;
+    }
+
     /// Execute a single instruction
     fn execute_instruction(&mut self) -> Result<()> {
         let instruction = &self.instructions[self.instruction_pointer];
 
         match instruction {
             Instruction::MoveRight(count) => {
-                self.pointer = self.pointer.wrapping_add(*count);
-                if self.pointer >= self.memory.len() {
-                    return Err(BrainfuckError::MemoryOutOfBounds {
-                        address: self.pointer,
+                // Overflowing the address space is as far off the tape as it gets
+                match self.pointer.checked_add(*count) {
+                    Some(address) if address < self.memory.len() => {
+                        self.pointer = address;
+                        self.max_pointer = self.max_pointer.max(address);
+                    }
+                    _ => {
+                        return Err(BrainfuckError::MemoryOutOfBounds {
+                            address: self.pointer.saturating_add(*count),
+                            tape_size: self.memory.len(),
+                        }
+                        .into());
                     }
-                    .into());
                 }
                 self.instruction_pointer += 1;
             }
@@ -88,6 +1195,7 @@ impl Interpreter {
                 if self.pointer < *count {
                     return Err(BrainfuckError::MemoryOutOfBounds {
                         address: self.pointer.wrapping_sub(*count),
+                        tape_size: self.memory.len(),
                     }
                     .into());
                 }
@@ -96,50 +1204,63 @@ impl Interpreter {
             }
 
             Instruction::Increment(count) => {
-                self.memory[self.pointer] = self.memory[self.pointer].wrapping_add(*count);
+                self.memory[self.pointer] = self.add_to_cell(self.memory[self.pointer], *count);
                 self.instruction_pointer += 1;
             }
 
             Instruction::Decrement(count) => {
-                self.memory[self.pointer] = self.memory[self.pointer].wrapping_sub(*count);
+                self.memory[self.pointer] = self.subtract_from_cell(self.memory[self.pointer], *count);
                 self.instruction_pointer += 1;
             }
 
             Instruction::Output(count) => {
-                let mut stdout = io::stdout();
-                for _ in 0..*count {
-                    stdout
-                        .write_all(&[self.memory[self.pointer]])
-                        .map_err(|e| {
-                            BrainfuckError::IoError {
-                                message: format!("Failed to write to stdout: {}", e),
-                            }
-                        })?;
-                }
-                stdout.flush().map_err(|e| {
-                    BrainfuckError::IoError {
-                        message: format!("Failed to flush stdout: {}", e),
-                    }
-                })?;
+                let count = *count;
+                self.write_cell(count)?;
                 self.instruction_pointer += 1;
             }
 
             Instruction::Input(count) => {
-                let mut stdin = io::stdin();
                 for _ in 0..*count {
-                    let mut buf = [0u8; 1];
-                    stdin
-                        .read_exact(&mut buf)
-                        .map_err(|e| {
-                            BrainfuckError::IoError {
-                                message: format!("Failed to read from stdin: {}", e),
-                            }
-                        })?;
-                    self.memory[self.pointer] = buf[0];
+                    self.read_cell()?;
                 }
                 self.instruction_pointer += 1;
             }
 
+            Instruction::EchoStream => {
+                // The whole stream is echoed in one step, so the limits the
+                // run loop checks between steps are checked here too
+                let mut echoed = 0u64;
+                loop {
+                    let at_eof = self.read_cell()?;
+                    if self.memory[self.pointer] == 0 {
+                        self.loop_iterations[self.instruction_pointer] = 0;
+                        self.instruction_pointer += 1;
+                        break;
+                    }
+                    // Every byte but the first is a jump back in `,[.,]`
+                    if echoed > 0 {
+                        if let Some(max) = self.config.max_loop_iterations {
+                            self.count_loop_iteration(self.instruction_pointer, max)?;
+                        }
+                    }
+                    self.write_cell(1)?;
+                    echoed += 1;
+                    if echoed.is_multiple_of(self.timeout_check_interval) {
+                        self.check_timeout()?;
+                        self.check_interrupt()?;
+                    }
+
+                    // A non-zero byte at end of input repeats forever, so go
+                    // round once per step to let the step limits see it
+                    if at_eof {
+                        if let Some(max) = self.config.max_loop_iterations {
+                            self.count_loop_iteration(self.instruction_pointer, max)?;
+                        }
+                        break;
+                    }
+                }
+            }
+
             Instruction::JumpForward(target) => {
                 if self.memory[self.pointer] == 0 {
                     self.instruction_pointer = *target;
@@ -149,61 +1270,674 @@ impl Interpreter {
             }
 
             Instruction::JumpBackward(target) => {
+                let target = *target;
                 if self.memory[self.pointer] != 0 {
-                    self.instruction_pointer = *target;
+                    if let Some(max) = self.config.max_loop_iterations {
+                        self.count_loop_iteration(target, max)?;
+                    }
+                    self.instruction_pointer = target;
                 } else {
+                    // The loop is done, so its next run starts counting afresh
+                    self.loop_iterations[self.instruction_pointer] = 0;
                     self.instruction_pointer += 1;
                 }
             }
+
+            Instruction::SetZero => {
+                self.memory[self.pointer] = 0;
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::SetValue(value) => {
+                self.memory[self.pointer] = *value;
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::Copy { targets } => {
+                let value = self.memory[self.pointer];
+                if value != 0 {
+                    for &offset in targets {
+                        let address = self.offset_address(offset)?;
+                        self.memory[address] = self.memory[address].wrapping_add(value);
+                        self.max_pointer = self.max_pointer.max(address);
+                    }
+                    self.memory[self.pointer] = 0;
+                }
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::MultiplyAdd { targets } => {
+                let value = self.memory[self.pointer];
+                if value != 0 {
+                    for &(offset, factor) in targets {
+                        let address = self.offset_address(offset)?;
+                        self.memory[address] = self.memory[address].wrapping_add(value.wrapping_mul(factor));
+                        self.max_pointer = self.max_pointer.max(address);
+                    }
+                    self.memory[self.pointer] = 0;
+                }
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::AddAtOffset { offset, value } => {
+                let address = self.offset_address(*offset)?;
+                self.memory[address] = self.memory[address].wrapping_add(*value);
+                self.max_pointer = self.max_pointer.max(address);
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::SetAtOffset { offset, value } => {
+                let address = self.offset_address(*offset)?;
+                self.memory[address] = *value;
+                self.max_pointer = self.max_pointer.max(address);
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::ScanAdd { stride, adds } => {
+                while self.memory[self.pointer] != 0 {
+                    for &(offset, value) in adds {
+                        let address = self.offset_address(offset)?;
+                        self.memory[address] = self.memory[address].wrapping_add(value);
+                        self.max_pointer = self.max_pointer.max(address);
+                    }
+                    self.pointer = self.offset_address(*stride)?;
+                    self.max_pointer = self.max_pointer.max(self.pointer);
+                    self.check_memory_limit()?;
+                }
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::ScanRight(stride) => {
+                while self.memory[self.pointer] != 0 {
+                    match self.pointer.checked_add(*stride) {
+                        Some(address) if address < self.memory.len() => self.pointer = address,
+                        _ => {
+                            return Err(BrainfuckError::MemoryOutOfBounds {
+                                address: self.pointer.saturating_add(*stride),
+                                tape_size: self.memory.len(),
+                            }
+                            .into());
+                        }
+                    }
+                }
+                self.max_pointer = self.max_pointer.max(self.pointer);
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::ScanLeft(stride) => {
+                while self.memory[self.pointer] != 0 {
+                    if self.pointer < *stride {
+                        return Err(BrainfuckError::MemoryOutOfBounds {
+                            address: self.pointer.wrapping_sub(*stride),
+                            tape_size: self.memory.len(),
+                        }
+                        .into());
+                    }
+                    self.pointer -= stride;
+                }
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::DebugDump => {
+                let dump = self.memory_window(self.config.debug_window);
+                match &mut self.debug_output {
+                    Some(writer) => writeln!(writer, "{}", dump).map_err(|e| {
+                        BrainfuckError::IoError {
+                            message: format!("Failed to write debug dump: {}", e),
+                        }
+                    })?,
+                    None => eprintln!("{}", dump),
+                }
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::Halt => {
+                self.instruction_pointer = self.instructions.len();
+            }
+
+            Instruction::StoreRegister => {
+                self.register = self.memory[self.pointer];
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::RestoreRegister => {
+                self.memory[self.pointer] = self.register;
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::SwitchInput => {
+                self.active_input = (self.active_input + 1) % (self.extra_inputs.len() + 1);
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::Nop => self.instruction_pointer += 1,
+
+            Instruction::Extension(c) => {
+                let handler = self
+                    .handlers
+                    .iter_mut()
+                    .find(|handler| handler.character() == *c)
+                    .ok_or_else(|| BrainfuckError::RuntimeError {
+                        message: format!("no handler registered for custom command '{}'", c),
+                    })?;
+                handler.execute(&mut self.memory, &mut self.pointer)?;
+                if self.pointer >= self.memory.len() {
+                    return Err(BrainfuckError::MemoryOutOfBounds {
+                        address: self.pointer,
+                        tape_size: self.memory.len(),
+                    }
+                    .into());
+                }
+                self.max_pointer = self.max_pointer.max(self.pointer);
+                self.instruction_pointer += 1;
+            }
+
+            Instruction::PrintConst(values) => {
+                let mut buf = [0u8; 4];
+                let mut encoded = Vec::with_capacity(values.len());
+                for &value in values {
+                    encoded.extend_from_slice(encode_cell(value, self.config.output_mode, self.config.output_newline, &mut buf));
+                }
+                self.write_output(&encoded)?;
+                self.flush_output()?;
+                self.instruction_pointer += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count a backward jump to `start` by the `]` at the instruction
+    /// pointer, failing if its loop has now jumped back more than `max` times
+    fn count_loop_iteration(&mut self, start: usize, max: u64) -> Result<()> {
+        let iterations = &mut self.loop_iterations[self.instruction_pointer];
+        *iterations += 1;
+        if *iterations > max {
+            return Err(BrainfuckError::RuntimeError {
+                message: format!("loop starting at instruction {} exceeded {} iterations", start, max),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Write the current cell, or it and the next with wide I/O, `count` times
+    /// and flush the output
+    fn write_cell(&mut self, count: usize) -> Result<()> {
+        let mut buf = [0u8; 4];
+        let bytes = if self.config.wide_io {
+            let next = self.wide_address()?;
+            buf[..2].copy_from_slice(&[self.memory[self.pointer], self.memory[next]]);
+            &buf[..2]
+        } else {
+            encode_cell(self.memory[self.pointer], self.config.output_mode, self.config.output_newline, &mut buf)
+        };
+        for _ in 0..count {
+            self.write_output(bytes)?;
+        }
+        self.flush_output()
+    }
+
+    /// Write `bytes` to the output, counting them for padding
+    ///
+    /// Past the output limit, only the bytes that fit are written before
+    /// execution fails.
+    fn write_output(&mut self, bytes: &[u8]) -> Result<()> {
+        self.check_io_allowed("output")?;
+        let allowed = match self.config.max_output_bytes {
+            Some(max) => {
+                let remaining = usize::try_from(max).unwrap_or(usize::MAX).saturating_sub(self.bytes_written);
+                bytes.len().min(remaining)
+            }
+            None => bytes.len(),
+        };
+
+        self.output.write_all(&bytes[..allowed]).map_err(|e| {
+            BrainfuckError::IoError {
+                message: format!("Failed to write output: {}", e),
+            }
+        })?;
+        self.bytes_written += allowed;
+
+        match self.config.max_output_bytes {
+            Some(max) if allowed < bytes.len() => {
+                self.flush_output()?;
+                Err(BrainfuckError::RuntimeError {
+                    message: format!("output exceeded the limit of {} bytes", max),
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Flush the output
+    fn flush_output(&mut self) -> Result<()> {
+        self.output.flush().map_err(|e| {
+            BrainfuckError::IoError {
+                message: format!("Failed to flush output: {}", e),
+            }
+            .into()
+        })
+    }
+
+    /// Write zero bytes until the output is a multiple of the configured
+    /// record size
+    fn pad_output(&mut self) -> Result<()> {
+        let Some(size) = self.config.pad_output else {
+            return Ok(());
+        };
+        let remainder = self.bytes_written % size;
+        if remainder != 0 {
+            self.write_output(&vec![0; size.get() - remainder])?;
+            self.flush_output()?;
+        }
+        Ok(())
+    }
+
+    /// Read one byte of input into the current cell, or two into it and the next
+    /// with wide I/O, returning whether the input had ended and the EOF behavior
+    /// supplied a value
+    fn read_cell(&mut self) -> Result<bool> {
+        self.check_io_allowed("input")?;
+        if self.config.interactive_input {
+            self.prompt_for_input()?;
+        }
+        if self.config.wide_io {
+            let next = self.wide_address()?;
+            let at_eof = self.read_byte(self.pointer)?;
+            return Ok(self.read_byte(next)? || at_eof);
+        }
+        self.read_byte(self.pointer)
+    }
+
+    /// Address of the low byte of a wide value, which must be on the tape
+    fn wide_address(&mut self) -> Result<usize> {
+        let address = self.offset_address(1)?;
+        self.max_pointer = self.max_pointer.max(address);
+        Ok(address)
+    }
+
+    /// Read a byte of input into the cell at `address`, and return whether
+    /// the input was exhausted
+    fn read_byte(&mut self, address: usize) -> Result<bool> {
+        let mut buf = [0u8; 1];
+        let read = match self.active_input {
+            0 => self.input.read_exact(&mut buf),
+            index => self.extra_inputs[index - 1].read_exact(&mut buf),
+        };
+        let (value, at_eof) = match read {
+            Ok(()) => (buf[0], false),
+            Err(e) => {
+                let at_eof = e.kind() == io::ErrorKind::UnexpectedEof;
+                match self.config.eof_behavior.apply(self.memory[address]) {
+                    Some(value) if at_eof => (value, true),
+                    _ => {
+                        return Err(BrainfuckError::IoError {
+                            message: format!("Failed to read input: {}", e),
+                        }
+                        .into())
+                    }
+                }
+            }
+        };
+        self.memory[address] = value;
+        Ok(at_eof)
+    }
+
+    /// Ask the user for the next input byte
+    fn prompt_for_input(&mut self) -> Result<()> {
+        let result = match &mut self.prompt_output {
+            Some(writer) => write!(writer, "input byte: ").and_then(|()| writer.flush()),
+            None => {
+                let mut stderr = io::stderr();
+                write!(stderr, "input byte: ").and_then(|()| stderr.flush())
+            }
+        };
+        result.map_err(|e| {
+            BrainfuckError::IoError {
+                message: format!("Failed to write input prompt: {}", e),
+            }
+            .into()
+        })
+    }
+
+    /// Add `value` to a cell value, wrapping at the configured modulus
+    fn add_to_cell(&self, cell: u8, value: u8) -> u8 {
+        match self.config.cell_modulus {
+            Some(modulus) => ((u32::from(cell) + u32::from(value)) % modulus) as u8,
+            None => cell.wrapping_add(value),
+        }
+    }
+
+    /// Subtract `value` from a cell value, wrapping at the configured modulus
+    fn subtract_from_cell(&self, cell: u8, value: u8) -> u8 {
+        match self.config.cell_modulus {
+            Some(modulus) => ((u32::from(cell) + modulus - u32::from(value) % modulus) % modulus) as u8,
+            None => cell.wrapping_sub(value),
+        }
+    }
+
+    /// Resolve the address `offset` cells away from the pointer, failing if it
+    /// falls outside the tape
+    fn offset_address(&self, offset: isize) -> Result<usize> {
+        match self.pointer.checked_add_signed(offset) {
+            Some(address) if address < self.memory.len() => Ok(address),
+            _ => Err(BrainfuckError::MemoryOutOfBounds {
+                address: self.pointer.wrapping_add_signed(offset),
+                tape_size: self.memory.len(),
+            }
+            .into()),
+        }
+    }
+
+    /// Read the cell at `address`, or `None` if it is outside the tape
+    pub fn cell(&self, address: usize) -> Option<u8> {
+        self.memory.get(address)
+    }
+
+    /// Overwrite the cell at `address`
+    ///
+    /// This bypasses normal execution and is intended for seeding memory
+    /// before calling [`Interpreter::run`].
+    pub fn set_cell(&mut self, address: usize, value: u8) -> Result<()> {
+        if address >= self.memory.len() {
+            return Err(BrainfuckError::MemoryOutOfBounds {
+                address,
+                tape_size: self.memory.len(),
+            }
+            .into());
+        }
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    /// Copy `cells` onto the start of the tape, ignoring any that do not fit
+    ///
+    /// Cells past the end of `cells` keep their initial value. Like
+    /// [`Interpreter::set_cell`], this is intended for seeding memory before
+    /// calling [`Interpreter::run`], and [`Interpreter::reset`] undoes it.
+    pub fn load_memory(&mut self, cells: &[u8]) {
+        for (address, &value) in cells.iter().take(self.memory.len()).enumerate() {
+            self.memory[address] = value;
         }
+    }
 
+    /// Move the memory pointer to `address`
+    ///
+    /// Like [`Interpreter::set_cell`], this bypasses normal execution.
+    pub fn set_pointer(&mut self, address: usize) -> Result<()> {
+        if address >= self.memory.len() {
+            return Err(BrainfuckError::MemoryOutOfBounds {
+                address,
+                tape_size: self.memory.len(),
+            }
+            .into());
+        }
+        self.pointer = address;
         Ok(())
     }
 
     /// Get the current memory state (for debugging)
+    ///
+    /// # Panics
+    ///
+    /// With a sparse tape, which has no contiguous contents; use
+    /// [`Interpreter::tape`] instead.
     pub fn memory_state(&self) -> &[u8] {
+        self.memory.as_slice().expect("a sparse tape has no contiguous memory state")
+    }
+
+    /// The memory tape, whichever way it is stored
+    pub fn tape(&self) -> &Tape {
         &self.memory
     }
 
+    /// Render the cells in `range` as a grid of addresses, values and their
+    /// ASCII characters, with a `^` under the pointer
+    ///
+    /// Cells that are not printable ASCII show as `.`, and the range is
+    /// clipped to the tape.
+    ///
+    /// ```text
+    /// addr |   0 |   1 |   2 |   3 |
+    /// val  |  72 | 105 |  10 |   0 |
+    /// char |   H |   i |   . |   . |
+    ///      |     |     |   ^ |     |
+    /// ```
+    pub fn tape_to_string(&self, range: Range<usize>) -> String {
+        let range = range.start.min(self.memory.len())..range.end.min(self.memory.len());
+        let width = range.end.saturating_sub(1).to_string().len().max(3);
+        let mut rows = [
+            String::from("addr |"),
+            String::from("val  |"),
+            String::from("char |"),
+            String::from("     |"),
+        ];
+
+        for address in range {
+            let value = self.memory[address];
+            let character = if value.is_ascii_graphic() { value as char } else { '.' };
+            let marker = if address == self.pointer { "^" } else { "" };
+
+            let _ = write!(rows[0], " {:>width$} |", address);
+            let _ = write!(rows[1], " {:>width$} |", self.display_value(value));
+            let _ = write!(rows[2], " {:>width$} |", character);
+            let _ = write!(rows[3], " {:>width$} |", marker);
+        }
+
+        rows.join("\n")
+    }
+
+    /// Render the cells within `radius` of the pointer, with the current cell
+    /// in brackets, e.g. `0..5: 0 3 [7] 0 0`
+    pub fn memory_window(&self, radius: usize) -> String {
+        // A restored state can leave the pointer past the end
+        let pointer = self.pointer.min(self.memory.len() - 1);
+        let start = pointer.saturating_sub(radius);
+        let end = pointer.saturating_add(radius + 1).min(self.memory.len());
+
+        let cells: Vec<String> = (start..end)
+            .map(|address| {
+                let value = self.display_value(self.memory[address]);
+                if address == self.pointer {
+                    format!("[{}]", value)
+                } else {
+                    value.to_string()
+                }
+            })
+            .collect();
+
+        format!("{}..{}: {}", start, end, cells.join(" "))
+    }
+
+    /// A cell value as debug output shows it, signed if so configured
+    fn display_value(&self, value: u8) -> i16 {
+        if self.config.signed {
+            i16::from(value as i8)
+        } else {
+            i16::from(value)
+        }
+    }
+
     /// Get the current pointer position
     pub fn pointer(&self) -> usize {
         self.pointer
     }
 
+    /// Get the highest address the program has reached so far, which is the
+    /// memory it needs less one
+    ///
+    /// Cells that an optimized loop touches at an offset count as reached,
+    /// since the loop as written moves the pointer there.
+    pub fn max_pointer(&self) -> usize {
+        self.max_pointer
+    }
+
     /// Get the current instruction pointer
     pub fn instruction_pointer(&self) -> usize {
         self.instruction_pointer
     }
 
+    /// Get the number of instructions executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
     /// Get the number of instructions
     pub fn instruction_count(&self) -> usize {
         self.instructions.len()
     }
 }
 
+/// Run `programs` one after another on a shared tape and return everything
+/// they printed
+///
+/// Each program starts with the tape and memory pointer the previous one left
+/// behind. `,` always sees end of input.
+pub fn run_chain(programs: &[Vec<Instruction>], config: InterpreterConfig) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_io(Vec::new(), config, io::empty(), &mut output);
+
+    for program in programs {
+        interpreter.load(program.clone());
+        interpreter.run()?;
+    }
+
+    Ok(output)
+}
+
+/// Run each of `programs` on its own fresh tape and return what each printed,
+/// or the error that stopped it
+///
+/// A failing program does not stop the ones after it. `,` always sees end of
+/// input.
+pub fn run_all(programs: &[Vec<Instruction>], config: InterpreterConfig) -> Vec<Result<Vec<u8>>> {
+    programs
+        .iter()
+        .map(|program| {
+            let mut output = Vec::new();
+            Interpreter::with_io(program.clone(), config.clone(), io::empty(), &mut output).run()?;
+            Ok(output)
+        })
+        .collect()
+}
+
+/// Whether programs `a` and `b` behave the same on each of `inputs`: they
+/// print the same bytes, end with the same tape and either both succeed or
+/// both fail with the same error
+///
+/// Each run starts from a fresh tape with `config`. A program that never
+/// stops makes this never return, so set a
+/// [`timeout`](InterpreterConfig::timeout) when that is possible.
+pub fn programs_equivalent(a: &[Instruction], b: &[Instruction], inputs: &[Vec<u8>], config: InterpreterConfig) -> bool {
+    let run = |program: &[Instruction], input: &[u8]| {
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(program.to_vec(), config.clone(), input, &mut output);
+        let result = interpreter.run().map_err(|e| e.to_string());
+        let tape = interpreter.tape().clone();
+        drop(interpreter);
+        (output, tape, result)
+    };
+
+    inputs.iter().all(|input| {
+        let (output_a, tape_a, result_a) = run(a, input);
+        let (output_b, tape_b, result_b) = run(b, input);
+        output_a == output_b && tape_a.same_cells(&tape_b) && result_a == result_b
+    })
+}
+
+/// Header row of a trace written by [`Interpreter::set_trace`]
+const TRACE_HEADER: &str = "step\tip\tpointer\tcell\tinstruction";
+
+fn replay_read_error(e: io::Error) -> anyhow::Error {
+    BrainfuckError::IoError {
+        message: format!("Failed to read replay trace: {}", e),
+    }
+    .into()
+}
+
+/// Encode a cell for output according to the output and newline modes
+fn encode_cell(value: u8, mode: OutputMode, newline: NewlineMode, buf: &mut [u8; 4]) -> &[u8] {
+    if value == b'\n' && newline == NewlineMode::CrLf {
+        buf[..2].copy_from_slice(b"\r\n");
+        return &buf[..2];
+    }
+    match mode {
+        OutputMode::Bytes => {
+            buf[0] = value;
+            &buf[..1]
+        }
+        OutputMode::Utf8Codepoint => char::from(value).encode_utf8(buf).as_bytes(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::Lexer;
-    use crate::optimizer::Optimizer;
+    use crate::lexer::{Lexer, LexerConfig};
+    use crate::optimizer::{OptLevel, Optimizer, OptimizerConfig};
     use std::io::Cursor;
 
-    fn run_program(input: &str) -> Result<String> {
-        let cursor = Cursor::new(input.as_bytes());
+    /// Run `program` on `input`, treating end of input as 0, and return
+    /// everything it printed
+    fn run_program(program: &str, input: &[u8]) -> Result<String> {
+        let cursor = Cursor::new(program.as_bytes());
         let lexer = Lexer::new(cursor);
         let mut optimizer = Optimizer::new();
         let instructions = optimizer.optimize(lexer)?;
-        
-        let config = InterpreterConfig::default();
-        let mut interpreter = Interpreter::new(instructions, config);
-        interpreter.run()?;
-        
-        // Capture output
+
+        let config = InterpreterConfig {
+            eof_behavior: EofBehavior::Zero,
+            ..Default::default()
+        };
         let mut output = Vec::new();
-        io::stdout().write_all(&output)?;
+        let mut interpreter = Interpreter::with_io(instructions, config, Cursor::new(input), &mut output);
+        interpreter.run()?;
+
         Ok(String::from_utf8_lossy(&output).to_string())
     }
 
+    #[test]
+    fn test_hello_world_output() {
+        let program = include_str!("../examples/hello_world.bf");
+        assert_eq!(run_program(program, b"").unwrap(), "Hello World!\n");
+    }
+
+    #[test]
+    fn test_cat_program() {
+        assert_eq!(run_program(",[.,]", b"cat me").unwrap(), "cat me");
+        assert_eq!(run_program(",[.,]", b"").unwrap(), "");
+    }
+
+    #[test]
+    fn test_echo_stream() {
+        assert_eq!(optimize(",[.,]"), [Instruction::EchoStream]);
+        assert_eq!(run_program(",[.,]", b"echo\0ignored").unwrap(), "echo");
+        assert_eq!(run_program(",,[.,]", b"xecho").unwrap(), "echo");
+
+        // The end of input is an error by default, as for the naive loop
+        let mut interpreter = Interpreter::with_io(optimize(",[.,]"), InterpreterConfig::default(), Cursor::new("ab"), io::sink());
+        assert!(interpreter.run().is_err());
+
+        // A byte repeated forever at end of input is still caught by the
+        // loop iteration limit
+        let config = InterpreterConfig {
+            eof_behavior: EofBehavior::Value(b'!'),
+            max_loop_iterations: Some(3),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize(",[.,]"), config, Cursor::new("ab"), &mut output);
+        assert!(interpreter.run().is_err());
+        // As many bytes as the naive loop writes before its fourth jump back
+        assert_eq!(output, b"ab!!");
+
+        // Only the exact shape is an echo loop
+        assert!(!optimize(",[.]").contains(&Instruction::EchoStream));
+        assert!(!optimize(",[..,]").contains(&Instruction::EchoStream));
+    }
+
     #[test]
     fn test_simple_increment() {
         let input = "+++";
@@ -235,6 +1969,49 @@ mod tests {
         assert_eq!(interpreter.memory_state()[1], 3);
     }
 
+    #[test]
+    fn test_max_pointer() {
+        let program = format!("{}{}", ">".repeat(100), "<".repeat(100));
+        let instructions = Optimizer::new().optimize(Lexer::new(Cursor::new(program.as_bytes()))).unwrap();
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.pointer(), 0);
+        assert_eq!(interpreter.max_pointer(), 100);
+
+        // The copy never moves the pointer, but the loop as written reaches
+        // two cells right
+        let instructions = Optimizer::new().optimize(Lexer::new(Cursor::new(b"+[->+>+<<]".as_slice()))).unwrap();
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.max_pointer(), 2);
+
+        interpreter.reset();
+        assert_eq!(interpreter.max_pointer(), 0);
+    }
+
+    #[test]
+    fn test_touched_cells() {
+        let program = format!("+{}+{}+", ">".repeat(5), ">".repeat(95));
+        let mut interpreter = Interpreter::new(optimize(&program), InterpreterConfig::default());
+        interpreter.enable_touch_tracking();
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.touched_cells(), Some(3));
+
+        // A scan tests each cell it stops on, and a copy writes its targets
+        let mut interpreter = Interpreter::new(optimize(">>+>>+>>+[<<]>>>+[-<+<<+>>>]"), InterpreterConfig::default());
+        interpreter.enable_touch_tracking();
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.touched_cells(), Some(5));
+
+        let config = InterpreterConfig {
+            tape: TapeKind::Sparse,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(optimize("+"), config);
+        interpreter.enable_touch_tracking();
+        assert_eq!(interpreter.touched_cells(), None);
+    }
+
     #[test]
     fn test_simple_loop() {
         let input = "+++[>+<-]";
@@ -265,6 +2042,1312 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_memory_window() {
+        let input = "+>++>+++<";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+        let instructions = optimizer.optimize(lexer).unwrap();
+
+        let config = InterpreterConfig::default();
+        let mut interpreter = Interpreter::new(instructions, config);
+        interpreter.run().unwrap();
+
+        assert_eq!(interpreter.memory_window(1), "0..3: 1 [2] 3");
+        assert_eq!(interpreter.memory_window(3), "0..5: 1 [2] 3 0 0");
+    }
+
+    #[test]
+    fn test_output_mode_bytes() {
+        // 233 is 'é' in Latin-1
+        let instructions = vec![Instruction::Increment(233), Instruction::Output(1)];
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+        interpreter.run().unwrap();
+
+        assert_eq!(output, vec![233]);
+    }
+
+    #[test]
+    fn test_output_mode_utf8_codepoint() {
+        let instructions = vec![
+            Instruction::Increment(233),
+            Instruction::Output(1),
+            Instruction::MoveRight(1),
+            Instruction::Increment(b'A'),
+            Instruction::Output(1),
+        ];
+        let config = InterpreterConfig {
+            output_mode: OutputMode::Utf8Codepoint,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(instructions, config, io::empty(), &mut output);
+        interpreter.run().unwrap();
+
+        assert_eq!(output, "éA".as_bytes());
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let input = "++++[>+<--]";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+        let instructions = optimizer.optimize(lexer).unwrap();
+
+        let config = InterpreterConfig::default();
+        let mut interpreter = Interpreter::new(instructions, config);
+
+        // Stop inside the first loop iteration, after `>+`
+        for _ in 0..4 {
+            assert!(interpreter.step().unwrap());
+        }
+        let snapshot = interpreter.snapshot();
+        assert_eq!(snapshot.pointer, 1);
+        assert_eq!(snapshot.instruction_pointer, 4);
+        assert_eq!([snapshot.memory[0], snapshot.memory[1]], [4, 1]);
+
+        interpreter.run().unwrap();
+        assert_eq!(&interpreter.memory_state()[..2], &[0, 2]);
+        assert!(!interpreter.step().unwrap());
+
+        interpreter.restore(snapshot.clone());
+        assert_eq!(interpreter.snapshot(), snapshot);
+        assert_eq!(interpreter.tape(), &snapshot.memory);
+    }
+
+    #[test]
+    fn test_seeded_cell_is_read_by_program() {
+        let input = ">>>>>[->+<]>.";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+        let instructions = optimizer.optimize(lexer).unwrap();
+
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+        interpreter.set_cell(5, 42).unwrap();
+        assert_eq!(interpreter.cell(5), Some(42));
+        interpreter.run().unwrap();
+
+        assert_eq!(interpreter.cell(5), Some(0));
+        assert_eq!(interpreter.cell(6), Some(42));
+        assert_eq!(output, vec![42]);
+    }
+
+    #[test]
+    fn test_set_pointer_starts_execution_there() {
+        let instructions = vec![Instruction::Increment(7)];
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.set_pointer(10).unwrap();
+        interpreter.run().unwrap();
+
+        assert_eq!(interpreter.pointer(), 10);
+        assert_eq!(interpreter.cell(10), Some(7));
+        assert_eq!(interpreter.cell(0), Some(0));
+    }
+
+    #[test]
+    fn test_load_memory() {
+        let config = InterpreterConfig {
+            memory_size: 4,
+            initial_cell_value: 9,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(Vec::new(), config);
+        interpreter.load_memory(&[1, 2]);
+        assert_eq!(interpreter.memory_state(), [1, 2, 9, 9]);
+
+        // Cells beyond the tape are dropped
+        interpreter.load_memory(&[5, 6, 7, 8, 10]);
+        assert_eq!(interpreter.memory_state(), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_tape_to_string() {
+        let mut interpreter = Interpreter::new(Vec::new(), InterpreterConfig::default());
+        interpreter.set_cell(0, b'H').unwrap();
+        interpreter.set_cell(1, b'i').unwrap();
+        interpreter.set_cell(2, 10).unwrap();
+        interpreter.set_pointer(2).unwrap();
+
+        let grid = interpreter.tape_to_string(0..4);
+        let rows: Vec<&str> = grid.lines().collect();
+        assert_eq!(rows[0], "addr |   0 |   1 |   2 |   3 |");
+        assert_eq!(rows[1], "val  |  72 | 105 |  10 |   0 |");
+        assert_eq!(rows[2], "char |   H |   i |   . |   . |");
+        assert_eq!(rows[3], "     |     |     |   ^ |     |");
+
+        // The marker sits in the same column as the pointer's address
+        assert_eq!(rows[3].find('^'), rows[0].find('2'));
+    }
+
+    #[test]
+    fn test_tape_to_string_clips_to_tape() {
+        let config = InterpreterConfig {
+            memory_size: 2,
+            ..Default::default()
+        };
+        let interpreter = Interpreter::new(Vec::new(), config);
+        assert_eq!(interpreter.tape_to_string(1..10).lines().next(), Some("addr |   1 |"));
+    }
+
+    #[test]
+    fn test_cell_access_bounds() {
+        let config = InterpreterConfig {
+            memory_size: 8,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(Vec::new(), config);
+
+        assert_eq!(interpreter.cell(8), None);
+        assert!(interpreter.set_cell(8, 1).is_err());
+        assert!(interpreter.set_pointer(8).is_err());
+        assert!(interpreter.set_cell(7, 1).is_ok());
+        assert!(interpreter.set_pointer(7).is_ok());
+    }
+
+    #[test]
+    fn test_reset_runs_program_again() {
+        let input = "++++++++[>++++++++<-]>+.";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+        let instructions = optimizer.optimize(lexer).unwrap();
+
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+        interpreter.run().unwrap();
+        let first = interpreter.snapshot();
+
+        interpreter.reset();
+        assert_eq!(interpreter.pointer(), 0);
+        assert_eq!(interpreter.instruction_pointer(), 0);
+        assert!(interpreter.memory_state().iter().all(|&cell| cell == 0));
+
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.snapshot(), first);
+        assert_eq!(output, b"AA");
+    }
+
+    #[test]
+    fn test_trace_file() {
+        let path = std::env::temp_dir().join(format!("bf_trace_test_{}.tsv", std::process::id()));
+        let instructions = vec![Instruction::Increment(2), Instruction::MoveRight(1)];
+
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.set_trace(Box::new(std::fs::File::create(&path).unwrap())).unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.steps(), 2);
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rows: Vec<Vec<&str>> = trace.lines().map(|line| line.split('\t').collect()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], ["step", "ip", "pointer", "cell", "instruction"]);
+        assert_eq!(rows[1], ["0", "0", "0", "0", "Increment(2)"]);
+        assert_eq!(rows[2], ["1", "1", "0", "2", "MoveRight(1)"]);
+    }
+
+    #[test]
+    fn test_replay_trace() {
+        let interpreter = |input: u8| {
+            Interpreter::with_io(optimize(",[->+<]>."), InterpreterConfig::default(), Cursor::new([input]), io::sink())
+        };
+        let replay = |input: u8, trace: &str| {
+            let mut interpreter = interpreter(input);
+            interpreter.set_replay(Box::new(Cursor::new(trace.to_string())))?;
+            interpreter.run()
+        };
+
+        let path = std::env::temp_dir().join(format!("bf_replay_test_{}.tsv", std::process::id()));
+        let mut recorded = interpreter(3);
+        recorded.set_trace(Box::new(std::fs::File::create(&path).unwrap())).unwrap();
+        recorded.run().unwrap();
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        replay(3, &trace).unwrap();
+
+        // A different input takes a different path through the program
+        let err = replay(4, &trace).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Runtime error: replay diverged at step 1: expected ip 1, pointer 0, cell 3 but found ip 1, pointer 0, cell 4"
+        );
+
+        // So does a tampered trace
+        let tampered = trace.replacen("1\t1\t0\t3\t", "1\t1\t1\t3\t", 1);
+        let err = replay(3, &tampered).unwrap_err();
+        assert!(err.to_string().contains("expected ip 1, pointer 1, cell 3"), "{}", err);
+
+        let extended = format!("{}9\t0\t0\t0\tHalt\n", trace);
+        let err = replay(3, &extended).unwrap_err();
+        assert!(err.to_string().contains("the program finished but the trace continues"), "{}", err);
+
+        let truncated = &trace[..trace.trim_end().rfind('\n').unwrap() + 1];
+        let err = replay(3, truncated).unwrap_err();
+        assert!(err.to_string().contains("the trace ended but execution continued"), "{}", err);
+
+        assert!(replay(3, "not a trace\n").is_err());
+    }
+
+    #[test]
+    fn test_debug_dump_window() {
+        let path = std::env::temp_dir().join(format!("bf_dump_test_{}.txt", std::process::id()));
+        let config = InterpreterConfig {
+            debug_window: 2,
+            ..Default::default()
+        };
+        let instructions = vec![Instruction::MoveRight(5), Instruction::Increment(7), Instruction::DebugDump];
+
+        let mut interpreter = Interpreter::new(instructions, config);
+        interpreter.set_debug_output(Box::new(std::fs::File::create(&path).unwrap()));
+        interpreter.run().unwrap();
+
+        let dump = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(dump, "3..8: 0 0 [7] 0 0\n");
+    }
+
+    #[test]
+    fn test_signed_debug_dump() {
+        let path = std::env::temp_dir().join(format!("bf_signed_dump_test_{}.txt", std::process::id()));
+        let config = InterpreterConfig {
+            debug_window: 1,
+            signed: true,
+            ..Default::default()
+        };
+        let instructions = vec![
+            Instruction::Increment(200),
+            Instruction::MoveRight(1),
+            Instruction::Decrement(1),
+            Instruction::DebugDump,
+        ];
+
+        let mut interpreter = Interpreter::new(instructions, config);
+        interpreter.set_debug_output(Box::new(std::fs::File::create(&path).unwrap()));
+        interpreter.run().unwrap();
+
+        let dump = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(dump, "0..3: -56 [-1] 0\n");
+        // The cell itself still holds the raw byte
+        assert_eq!(interpreter.cell(1), Some(255));
+    }
+
+    #[test]
+    fn test_copy_and_multiply_loops() {
+        let input = "+++++[->+>++<<]>>[-<<+>>]";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+        let instructions = optimizer.optimize(lexer).unwrap();
+
+        let config = InterpreterConfig::default();
+        let mut interpreter = Interpreter::new(instructions, config);
+        interpreter.run().unwrap();
+
+        assert_eq!(&interpreter.memory_state()[..3], &[10, 5, 0]);
+    }
+
+    #[test]
+    fn test_nops_execute_identically() {
+        let program = "++++++++[>++++[>++>+++<<-]>+<<-]>>.>+.";
+        let run = |instructions: Vec<Instruction>| {
+            let mut output = Vec::new();
+            let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+            interpreter.run().unwrap();
+            let state = (interpreter.memory_state()[..4].to_vec(), interpreter.pointer());
+            drop(interpreter);
+            (output, state)
+        };
+
+        // A `Nop` after every instruction doubles each index
+        let instructions = optimize(program);
+        let padded = instructions
+            .iter()
+            .flat_map(|instruction| {
+                let instruction = match *instruction {
+                    Instruction::JumpForward(target) => Instruction::JumpForward(target * 2),
+                    Instruction::JumpBackward(target) => Instruction::JumpBackward(target * 2),
+                    ref other => other.clone(),
+                };
+                [instruction, Instruction::Nop]
+            })
+            .collect();
+        assert_eq!(run(padded), run(instructions));
+    }
+
+    #[test]
+    fn test_add_and_move_idiom() {
+        for (input, counter, target) in [("[->+<]", 0, 1), ("[-<+>]", 1, 0)] {
+            let instructions = optimize(input);
+            assert_eq!(instructions.len(), 1, "{}", input);
+
+            let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+            interpreter.set_cell(counter, 5).unwrap();
+            interpreter.set_cell(target, 2).unwrap();
+            interpreter.set_pointer(counter).unwrap();
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.cell(counter), Some(0));
+            assert_eq!(interpreter.cell(target), Some(7));
+        }
+    }
+
+    #[test]
+    fn test_multiply_target_out_of_bounds() {
+        let instructions = vec![Instruction::Increment(1), Instruction::MultiplyAdd { targets: vec![(-1, 2)] }];
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        assert!(interpreter.run().is_err());
+
+        // A zero counter never touches the targets, just like the original loop
+        let instructions = vec![Instruction::MultiplyAdd { targets: vec![(-1, 2)] }];
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        assert!(interpreter.run().is_ok());
+    }
+
+    #[test]
+    fn test_scan_add_loop() {
+        let input = "+>+>+<<[+>]";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let instructions = Optimizer::new().optimize(lexer).unwrap();
+        assert!(matches!(instructions.last(), Some(Instruction::ScanAdd { .. })));
+
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.run().unwrap();
+        assert_eq!(&interpreter.memory_state()[..4], &[2, 2, 2, 0]);
+        assert_eq!(interpreter.pointer(), 3);
+    }
+
+    #[test]
+    fn test_scan_add_past_end() {
+        let config = InterpreterConfig {
+            memory_size: 4,
+            initial_cell_value: 1,
+            ..Default::default()
+        };
+        let instructions = vec![Instruction::ScanAdd { stride: 1, adds: vec![(0, 1)] }];
+        let mut interpreter = Interpreter::new(instructions, config);
+
+        assert_eq!(out_of_bounds_address(interpreter.run()), 4);
+    }
+
+    #[test]
+    fn test_scan_by_stride() {
+        // A stride of one would stop on the zero at 1
+        let instructions = optimize("+>>+<<[>>]");
+        assert_eq!(instructions.last(), Some(&Instruction::ScanRight(2)));
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.pointer(), 4);
+
+        let mut interpreter = Interpreter::new(optimize("[<<]"), InterpreterConfig::default());
+        interpreter.load_memory(&[0, 0, 1, 1, 1]);
+        interpreter.set_pointer(4).unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.pointer(), 0);
+    }
+
+    #[test]
+    fn test_scan_by_stride_past_end() {
+        // The last step from 3 overshoots a tape of 5 cells
+        let config = InterpreterConfig {
+            memory_size: 5,
+            initial_cell_value: 1,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(vec![Instruction::MoveRight(1), Instruction::ScanRight(2)], config.clone());
+        assert_eq!(out_of_bounds_address(interpreter.run()), 5);
+        assert_eq!(interpreter.pointer(), 3);
+
+        let mut interpreter = Interpreter::new(vec![Instruction::MoveRight(3), Instruction::ScanLeft(2)], config);
+        assert_eq!(out_of_bounds_address(interpreter.run()), usize::MAX);
+    }
+
+    #[test]
+    fn test_optimized_loops_check_the_same_cells() {
+        // Each body strays a cell past the ones it adds to, which is off the
+        // end of a four cell tape
+        for program in ["+[->>>><<+<<]", "+[->+>>><<<<]", "+[->++>>><<<<]", "+[>>>><<<<-]"] {
+            let config = InterpreterConfig {
+                memory_size: 4,
+                ..Default::default()
+            };
+            let naive = Optimizer::with_config(OptimizerConfig {
+                opt_level: OptLevel::None,
+                ..Default::default()
+            })
+            .optimize(Lexer::new(Cursor::new(program.as_bytes())))
+            .unwrap();
+
+            let expected = out_of_bounds_address(Interpreter::new(naive, config.clone()).run());
+            let mut interpreter = Interpreter::new(optimize(program), config);
+            assert_eq!(out_of_bounds_address(interpreter.run()), expected, "{}", program);
+        }
+    }
+
+    fn run_with_input(instructions: Vec<Instruction>, config: InterpreterConfig, input: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(instructions, config, Cursor::new(input), &mut output);
+        interpreter.run()?;
+        Ok(interpreter.memory_state()[..2].to_vec())
+    }
+
+    #[test]
+    fn test_eof_behaviors() {
+        let instructions = vec![Instruction::Increment(7), Instruction::Input(2)];
+        let with_eof = |eof_behavior| InterpreterConfig {
+            eof_behavior,
+            ..Default::default()
+        };
+
+        assert!(run_with_input(instructions.clone(), with_eof(EofBehavior::Error), b"").is_err());
+        assert_eq!(run_with_input(instructions.clone(), with_eof(EofBehavior::Unchanged), b"").unwrap(), [7, 0]);
+        assert_eq!(run_with_input(instructions.clone(), with_eof(EofBehavior::Zero), b"").unwrap(), [0, 0]);
+        assert_eq!(run_with_input(instructions.clone(), with_eof(EofBehavior::NegativeOne), b"").unwrap(), [255, 0]);
+        assert_eq!(run_with_input(instructions.clone(), with_eof(EofBehavior::Value(10)), b"").unwrap(), [10, 0]);
+
+        // EOF only kicks in once the real input runs out
+        assert_eq!(run_with_input(instructions, with_eof(EofBehavior::Value(10)), b"A").unwrap(), [10, 0]);
+        let instructions = vec![Instruction::Input(1), Instruction::MoveRight(1), Instruction::Input(1)];
+        assert_eq!(run_with_input(instructions, with_eof(EofBehavior::Value(10)), b"A").unwrap(), [b'A', 10]);
+    }
+
+    fn out_of_bounds_address(result: Result<()>) -> usize {
+        match result.unwrap_err().downcast::<BrainfuckError>() {
+            Ok(BrainfuckError::MemoryOutOfBounds { address, .. }) => address,
+            other => panic!("expected MemoryOutOfBounds, got {:?}", other),
+        }
+    }
+
+    fn optimize(program: &str) -> Vec<Instruction> {
+        Optimizer::new().optimize(Lexer::new(Cursor::new(program.as_bytes()))).unwrap()
+    }
+
+    #[test]
+    fn test_find_safe_loops() {
+        // The output keeps the loop from being folded into one instruction
+        let safe_loops = find_safe_loops(&optimize("+++[>+.<-]"));
+        assert_eq!(safe_loops.len(), 1);
+        assert_eq!(safe_loops[&1], SafeLoop { end: 7, lowest: 0, highest: 1 });
+
+        // Unbalanced loops and loops containing other loops are not safe
+        assert!(find_safe_loops(&optimize("+[>.]")).is_empty());
+        let safe_loops = find_safe_loops(&optimize("+[<[.-]>-]"));
+        assert_eq!(safe_loops.keys().collect::<Vec<_>>(), [&3]);
+    }
+
+    #[test]
+    fn test_safe_loop_out_of_bounds_at_entry_is_checked() {
+        // Balanced, but the body reaches one cell left of the start
+        let mut interpreter = Interpreter::new(optimize("+[<+>-.]"), InterpreterConfig::default());
+        assert_eq!(out_of_bounds_address(interpreter.run()), 0usize.wrapping_sub(1));
+
+        // Unbalanced loops keep their checks on every iteration
+        let config = InterpreterConfig {
+            memory_size: 4,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(optimize("+[>+.]"), config);
+        assert_eq!(out_of_bounds_address(interpreter.run()), 4);
+    }
+
+    #[test]
+    fn test_safe_loop_matches_stepping() {
+        let program = "++[>+++[>+.<-]<-]>>.";
+        let run = |traced: bool| {
+            let mut output = Vec::new();
+            let mut interpreter =
+                Interpreter::with_io(optimize(program), InterpreterConfig::default(), io::empty(), &mut output);
+            if traced {
+                interpreter.set_trace(Box::new(io::sink())).unwrap();
+            }
+            interpreter.run().unwrap();
+            let state = (interpreter.snapshot(), interpreter.steps());
+            (state, output)
+        };
+
+        assert_eq!(run(false), run(true));
+    }
+
+    #[test]
+    fn test_offset_instructions() {
+        let instructions = vec![
+            Instruction::MoveRight(2),
+            Instruction::AddAtOffset { offset: -2, value: 5 },
+            Instruction::AddAtOffset { offset: -2, value: 1 },
+            Instruction::SetAtOffset { offset: 3, value: 9 },
+            Instruction::AddAtOffset { offset: 0, value: 4 },
+        ];
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.run().unwrap();
+
+        assert_eq!(&interpreter.memory_state()[..6], &[6, 0, 4, 0, 0, 9]);
+        assert_eq!(interpreter.pointer(), 2);
+    }
+
+    #[test]
+    fn test_out_of_bounds_message_includes_tape_size() {
+        let mut interpreter = Interpreter::new(vec![Instruction::MoveRight(30000)], InterpreterConfig::default());
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Memory access out of bounds: address 30000 exceeds tape size 30000"
+        );
+    }
+
+    #[test]
+    fn test_offset_below_zero() {
+        let instructions = vec![Instruction::MoveRight(1), Instruction::AddAtOffset { offset: -3, value: 1 }];
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+
+        // Addresses below zero are reported wrapped, like `<` past the start
+        assert_eq!(out_of_bounds_address(interpreter.run()), 1usize.wrapping_sub(3));
+    }
+
+    #[test]
+    fn test_offset_past_end() {
+        let config = InterpreterConfig {
+            memory_size: 10,
+            ..Default::default()
+        };
+        let instructions = vec![Instruction::MoveRight(8), Instruction::SetAtOffset { offset: 4, value: 1 }];
+        let mut interpreter = Interpreter::new(instructions, config);
+
+        assert_eq!(out_of_bounds_address(interpreter.run()), 12);
+        assert!(interpreter.memory_state().iter().all(|&cell| cell == 0));
+    }
+
+    #[test]
+    fn test_initial_cell_value() {
+        let config = InterpreterConfig {
+            memory_size: 4,
+            initial_cell_value: 5,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(Vec::new(), config);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.cell(0), Some(5));
+        assert_eq!(interpreter.memory_state(), &[5, 5, 5, 5]);
+
+        interpreter.set_cell(0, 1).unwrap();
+        interpreter.reset();
+        assert_eq!(interpreter.memory_state(), &[5, 5, 5, 5]);
+    }
+
+    #[test]
+    fn test_cell_modulus() {
+        let config = InterpreterConfig {
+            cell_modulus: Some(10),
+            ..Default::default()
+        };
+        let instructions = vec![Instruction::Increment(1); 11];
+        let mut interpreter = Interpreter::new(instructions, config.clone());
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.cell(0), Some(1));
+
+        let instructions = vec![Instruction::Decrement(1), Instruction::MoveRight(1), Instruction::Decrement(13)];
+        let mut interpreter = Interpreter::new(instructions, config);
+        interpreter.run().unwrap();
+        assert_eq!(&interpreter.memory_state()[..2], &[9, 7]);
+    }
+
+    #[test]
+    fn test_clear_loops_across_cell_moduli() {
+        // A step clears from any start only if it shares no factor with the
+        // number of values a cell can hold
+        for (modulus, program, clears) in [
+            (None, "+++[+]", true),
+            (None, "+++[+++]", true),
+            (None, "+++[++]", false),
+            (Some(10), "+++[+]", true),
+            (Some(10), "+++[+++]", true),
+            (Some(10), "+++[+++++]", false),
+            (Some(7), "+++[++]", true),
+            (Some(7), "+++[+++++++]", false),
+        ] {
+            let config = InterpreterConfig {
+                cell_modulus: modulus,
+                max_loop_iterations: Some(1000),
+                ..Default::default()
+            };
+            let lexer = Lexer::new(Cursor::new(program.as_bytes()));
+            let naive = Optimizer::with_config(OptimizerConfig {
+                opt_level: OptLevel::None,
+                ..Default::default()
+            })
+            .optimize(lexer)
+            .unwrap();
+            let mut interpreter = Interpreter::new(naive, config);
+            assert_eq!(interpreter.run().is_ok(), clears, "{} modulo {:?}", program, modulus);
+
+            // Only byte cells let the optimizer rely on that
+            if modulus.is_none() {
+                assert_eq!(optimize(program).contains(&Instruction::SetZero), clears, "{}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn test_halt_stops_execution() {
+        let config = LexerConfig {
+            halt_char: Some('@'),
+            ..Default::default()
+        };
+        let lexer = Lexer::with_config(Cursor::new("+.@+.".as_bytes()), config);
+        let instructions = Optimizer::new().optimize(lexer).unwrap();
+
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.cell(0), Some(1));
+        assert_eq!(interpreter.steps(), 3);
+        assert!(!interpreter.step().unwrap());
+        assert_eq!(output, [1]);
+    }
+
+    /// Records prompts and reads in the order they happen
+    #[derive(Clone, Default)]
+    struct EventLog(std::rc::Rc<std::cell::RefCell<Vec<String>>>);
+
+    impl Write for EventLog {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().push(String::from_utf8_lossy(buf).to_string());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for EventLog {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.borrow_mut().push("read".to_string());
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_interactive_input_prompts_before_each_read() {
+        let log = EventLog::default();
+        let config = InterpreterConfig {
+            interactive_input: true,
+            ..Default::default()
+        };
+        let instructions = vec![Instruction::Input(2), Instruction::MoveRight(1), Instruction::Input(1)];
+        let mut interpreter = Interpreter::with_io(instructions, config, log.clone(), io::sink());
+        interpreter.set_prompt_output(Box::new(log.clone()));
+        interpreter.run().unwrap();
+
+        let events = log.0.borrow();
+        assert_eq!(
+            *events,
+            ["input byte: ", "read", "input byte: ", "read", "input byte: ", "read"]
+        );
+    }
+
+    #[test]
+    fn test_no_prompt_by_default() {
+        let log = EventLog::default();
+        let mut interpreter =
+            Interpreter::with_io(vec![Instruction::Input(1)], InterpreterConfig::default(), log.clone(), io::sink());
+        interpreter.set_prompt_output(Box::new(log.clone()));
+        interpreter.run().unwrap();
+
+        assert_eq!(*log.0.borrow(), ["read"]);
+    }
+
+    #[test]
+    fn test_run_chain_shares_tape() {
+        let set_five = optimize("+++++");
+        let print = optimize(".");
+        assert_eq!(run_chain(&[set_five, print], InterpreterConfig::default()).unwrap(), [5]);
+
+        // The pointer carries over too
+        let programs = [optimize(">++"), optimize("+."), optimize("<.")];
+        assert_eq!(run_chain(&programs, InterpreterConfig::default()).unwrap(), [3, 0]);
+    }
+
+    #[test]
+    fn test_run_chain_stops_at_first_error() {
+        let programs = [optimize("<"), optimize("+.")];
+        assert!(run_chain(&programs, InterpreterConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_programs_equivalent() {
+        let optimize = |source: &str| Optimizer::new().optimize(Lexer::new(Cursor::new(source))).unwrap();
+        let config = InterpreterConfig {
+            eof_behavior: EofBehavior::Zero,
+            ..Default::default()
+        };
+        let inputs = [Vec::new(), b"abc".to_vec()];
+
+        assert!(programs_equivalent(&optimize("++"), &optimize("+ a comment +"), &inputs, config.clone()));
+        assert!(!programs_equivalent(&optimize("++"), &optimize("+++"), &inputs, config.clone()));
+        // Same output, different tape
+        assert!(!programs_equivalent(&optimize(",."), &optimize(",.>+"), &inputs, config.clone()));
+        // Only one input tells them apart
+        assert!(programs_equivalent(&optimize(",+."), &optimize(",[-]+."), &inputs[..1], config.clone()));
+        assert!(!programs_equivalent(&optimize(",+."), &optimize(",[-]+."), &inputs, config));
+    }
+
+    #[test]
+    fn test_run_all_isolates_errors() {
+        let programs = [optimize("++."), optimize("<"), optimize("+.+.")];
+        let mut results = run_all(&programs, InterpreterConfig::default()).into_iter();
+        assert_eq!(results.next().unwrap().unwrap(), [2]);
+        assert_eq!(out_of_bounds_address(results.next().unwrap().map(drop)), usize::MAX);
+        // Each program starts from a fresh tape
+        assert_eq!(results.next().unwrap().unwrap(), [1, 2]);
+        assert!(results.next().is_none());
+    }
+
+    fn run_with_register(program: &str) -> Interpreter {
+        let config = LexerConfig {
+            register: true,
+            ..Default::default()
+        };
+        let lexer = Lexer::with_config(Cursor::new(program.as_bytes()), config);
+        let instructions = Optimizer::new().optimize(lexer).unwrap();
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        interpreter.run().unwrap();
+        interpreter
+    }
+
+    #[test]
+    fn test_register_survives_loop() {
+        // Store before clearing the cell in a loop, then restore it
+        let interpreter = run_with_register("+++$[-]}>}");
+        assert_eq!(&interpreter.memory_state()[..2], &[3, 3]);
+    }
+
+    #[test]
+    fn test_register_stored_inside_loop() {
+        // The last store happens when the counter is 1
+        let interpreter = run_with_register("+++[$-]}");
+        assert_eq!(interpreter.cell(0), Some(1));
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = InterpreterConfig::builder()
+            .memory_size(100)
+            .debug(true)
+            .eof_behavior(EofBehavior::Zero)
+            .build()
+            .unwrap();
+        assert_eq!(config.memory_size, 100);
+        assert!(config.debug);
+        assert_eq!(config.eof_behavior, EofBehavior::Zero);
+        assert!(config.optimize);
+
+        let err = InterpreterConfig::builder().memory_size(0).build().unwrap_err();
+        assert!(err.to_string().contains("memory size must be greater than 0"));
+        assert!(InterpreterConfig::builder().cell_modulus(Some(0)).build().is_err());
+    }
+
+    #[test]
+    fn test_max_memory_bytes() {
+        let err = InterpreterConfig::builder()
+            .memory_size(1000)
+            .max_memory_bytes(Some(512))
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("a dense tape of 1000 cells needs more than the memory limit of 512 bytes"));
+
+        // A sparse tape only counts the cells written so far
+        let config = InterpreterConfig::builder()
+            .tape(TapeKind::Sparse)
+            .memory_size(1000)
+            .max_memory_bytes(Some(3))
+            .build()
+            .unwrap();
+        Interpreter::new(optimize("+>+>+"), config.clone()).run().unwrap();
+        let mut interpreter = Interpreter::new(optimize("+>+>+>+"), config);
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: tape grew past the memory limit of 3 bytes");
+        assert_eq!(interpreter.pointer(), 3);
+    }
+
+    #[test]
+    fn test_on_step_sees_each_instruction() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let executed = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&executed);
+        let mut interpreter = Interpreter::new(optimize("++>"), InterpreterConfig::default());
+        interpreter.set_on_step(Box::new(move |event| {
+            seen.borrow_mut().push((std::mem::discriminant(event.instruction), event.pointer));
+        }));
+        interpreter.run().unwrap();
+
+        let expected = [
+            (std::mem::discriminant(&Instruction::Increment(0)), 0),
+            (std::mem::discriminant(&Instruction::MoveRight(0)), 1),
+        ];
+        assert_eq!(*executed.borrow(), expected);
+    }
+
+    /// Sets the current cell to a fixed value on `?`
+    struct SetCell(u8);
+
+    impl InstructionHandler for SetCell {
+        fn character(&self) -> char {
+            '?'
+        }
+
+        fn execute(&mut self, memory: &mut Tape, pointer: &mut usize) -> Result<()> {
+            memory[*pointer] = self.0;
+            Ok(())
+        }
+    }
+
+    fn optimize_with_extension(program: &str) -> Vec<Instruction> {
+        let config = LexerConfig {
+            extensions: vec!['?'],
+            ..Default::default()
+        };
+        let lexer = Lexer::with_config(Cursor::new(program.as_bytes()), config);
+        Optimizer::new().optimize(lexer).unwrap()
+    }
+
+    #[test]
+    fn test_custom_instruction_handler() {
+        let mut output = Vec::new();
+        let instructions = optimize_with_extension("?.+.");
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+        interpreter.register_handler(Box::new(SetCell(42)));
+        interpreter.run().unwrap();
+        assert_eq!(output, [42, 43]);
+    }
+
+    #[test]
+    fn test_custom_instruction_without_handler_fails() {
+        let mut interpreter = Interpreter::new(optimize_with_extension(">?"), InterpreterConfig::default());
+        let err = interpreter.run().unwrap_err();
+        assert!(err.to_string().contains("no handler registered for custom command '?'"));
+    }
+
+    #[test]
+    fn test_watchpoint_fires_on_change() {
+        let mut interpreter = Interpreter::new(optimize(">+"), InterpreterConfig::default());
+        interpreter.add_watchpoint(1).unwrap();
+
+        let hit = interpreter.run_until_watchpoint().unwrap();
+        assert_eq!(hit, Some(WatchpointHit { address: 1, old: 0, new: 1 }));
+        assert_eq!(interpreter.instruction_pointer(), 2);
+        assert_eq!(interpreter.run_until_watchpoint().unwrap(), None);
+    }
+
+    #[test]
+    fn test_watchpoint_ignores_other_cells() {
+        let mut interpreter = Interpreter::new(optimize("+>+<-"), InterpreterConfig::default());
+        interpreter.add_watchpoint(1).unwrap();
+        assert!(interpreter.add_watchpoint(30000).is_err());
+
+        assert_eq!(interpreter.run_until_watchpoint().unwrap().map(|hit| hit.address), Some(1));
+        assert_eq!(interpreter.run_until_watchpoint().unwrap(), None);
+        assert_eq!(interpreter.cell(0), Some(0));
+
+        interpreter.reset();
+        interpreter.remove_watchpoint(1);
+        assert_eq!(interpreter.run_until_watchpoint().unwrap(), None);
+    }
+
+    #[test]
+    fn test_max_loop_iterations() {
+        let config = InterpreterConfig {
+            max_loop_iterations: Some(100),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(optimize("+[]"), config.clone());
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Runtime error: loop starting at instruction 1 exceeded 100 iterations"
+        );
+        // The `+`, then the `[` and 100 taken `]` jumps, then the failing `]`
+        assert_eq!(interpreter.steps(), 1 + 101 + 100);
+
+        // Each run of the inner loop starts counting afresh, so ten runs of
+        // nine jumps back stay under a limit of nine
+        let config = InterpreterConfig {
+            max_loop_iterations: Some(9),
+            ..Default::default()
+        };
+        let program = optimize("++++++++++[>++++++++++[.-]<-]");
+        let mut interpreter = Interpreter::with_io(program, config, io::empty(), io::sink());
+        assert!(interpreter.run().is_ok());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let config = InterpreterConfig {
+            timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(optimize("+[]"), config.clone());
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(error.to_string(), "Runtime error: execution exceeded the 10ms timeout");
+
+        // Checking the clock on every step lets a short program finish
+        let mut interpreter = Interpreter::with_io(optimize("+++[-]"), config, io::empty(), io::sink());
+        interpreter.timeout_check_interval = 1;
+        assert!(interpreter.run().is_ok());
+    }
+
+    #[test]
+    fn test_interrupt() {
+        // Interrupted between steps, everything so far has happened
+        let flag = Arc::new(AtomicBool::new(true));
+        let mut interpreter = Interpreter::new(optimize("+>+>+"), InterpreterConfig::default());
+        interpreter.timeout_check_interval = 2;
+        interpreter.set_interrupt(flag.clone());
+        let error = interpreter.run().unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(BrainfuckError::Interrupted { steps: 2 })));
+        assert_eq!((interpreter.instruction_pointer(), interpreter.pointer()), (2, 1));
+        assert_eq!(interpreter.memory_state()[..2], [1, 0]);
+
+        // A loop on the fast path sees the flag set mid-run, and stops back
+        // at its `[`
+        let mut interpreter = Interpreter::new(optimize("+[->+<+]"), InterpreterConfig::default());
+        flag.store(false, Ordering::Relaxed);
+        interpreter.set_interrupt(flag.clone());
+        let setter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let error = interpreter.run().unwrap_err();
+        setter.join().unwrap();
+        assert_eq!(error.to_string(), format!("Execution interrupted after {} steps", interpreter.steps()));
+        assert_eq!((interpreter.instruction_pointer(), interpreter.pointer()), (1, 0));
+        assert_eq!(interpreter.cell(0), Some(1));
+    }
+
+    /// A reader that never runs out of `y`s, like `yes`
+    struct EndlessReader;
+
+    impl Read for EndlessReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            buf.fill(b'y');
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_echo_stream_limits_on_endless_input() {
+        let config = InterpreterConfig {
+            timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::with_io(optimize(",[.,]"), config, EndlessReader, io::sink());
+        interpreter.timeout_check_interval = 1;
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(error.to_string(), "Runtime error: execution exceeded the 10ms timeout");
+
+        let mut interpreter =
+            Interpreter::with_io(optimize(",[.,]"), InterpreterConfig::default(), EndlessReader, io::sink());
+        interpreter.timeout_check_interval = 1;
+        interpreter.set_interrupt(Arc::new(AtomicBool::new(true)));
+        let error = interpreter.run().unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(BrainfuckError::Interrupted { .. })));
+
+        // Each echoed byte after the first is one jump back
+        let config = InterpreterConfig {
+            max_loop_iterations: Some(5),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize(",[.,]"), config, EndlessReader, &mut output);
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(error.to_string(), "Runtime error: loop starting at instruction 0 exceeded 5 iterations");
+        assert_eq!(output, b"yyyyyy");
+    }
+
+    /// A writer that takes a while over every write
+    struct SlowWriter;
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(2));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_timing() {
+        let instructions = vec![Instruction::Increment(1), Instruction::Output(5)];
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), SlowWriter);
+        interpreter.enable_timing();
+        interpreter.run().unwrap();
+
+        let timing = interpreter.timing().unwrap();
+        assert!(timing[1] >= Duration::from_millis(10), "{:?}", timing);
+        assert!(timing[0] < timing[1] / 10, "{:?}", timing);
+        assert_eq!(interpreter.profile(), Some([1, 1].as_slice()));
+    }
+
+    #[test]
+    fn test_long_increment_run_matches_naive() {
+        let program = "+".repeat(300);
+        let naive: Vec<Instruction> = (0..300).map(|_| Instruction::Increment(1)).collect();
+
+        let mut optimized = Interpreter::new(optimize(&program), InterpreterConfig::default());
+        optimized.run().unwrap();
+        let mut stepped = Interpreter::new(naive, InterpreterConfig::default());
+        stepped.run().unwrap();
+
+        assert_eq!(optimized.cell(0), Some(44));
+        assert_eq!(stepped.cell(0), Some(44));
+    }
+
+    #[test]
+    fn test_sparse_tape_far_offsets() {
+        let config = InterpreterConfig {
+            memory_size: usize::MAX,
+            tape: TapeKind::Sparse,
+            ..Default::default()
+        };
+        let program = format!("+++{}++[-<+>]{}.", ">".repeat(1_000_000), "<".repeat(1_000_000));
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize(&program), config, io::empty(), &mut output);
+        interpreter.run().unwrap();
+
+        assert_eq!(interpreter.cell(999_999), Some(2));
+        assert!(interpreter.tape().allocated() <= 3);
+        assert_eq!(output, [3]);
+    }
+
+    /// Run `instructions` on a sparse tape spanning the whole address space
+    /// and return the error, checking the pointer did not move
+    fn overflow_error(pointer: usize, instruction: Instruction) -> String {
+        let config = InterpreterConfig {
+            memory_size: usize::MAX,
+            tape: TapeKind::Sparse,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(vec![instruction], config);
+        interpreter.set_pointer(pointer).unwrap();
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(interpreter.pointer(), pointer);
+        err.to_string()
+    }
+
+    #[test]
+    fn test_move_right_overflow_is_out_of_bounds() {
+        // Wrapping would land back on cell 0 of an ordinary tape
+        let instructions = vec![Instruction::MoveRight(1), Instruction::MoveRight(usize::MAX)];
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        let err = interpreter.run().unwrap_err();
+        let expected = format!("address {} exceeds tape size 30000", usize::MAX);
+        assert!(err.to_string().contains(&expected));
+        assert_eq!(interpreter.pointer(), 1);
+
+        // On a tape covering nearly every address, any wrapped pointer is valid
+        for count in [usize::MAX, usize::MAX - 1, usize::MAX - 4] {
+            assert!(overflow_error(5, Instruction::MoveRight(count)).contains("Memory access out of bounds"));
+        }
+        assert!(overflow_error(usize::MAX - 1, Instruction::MoveRight(1)).contains("Memory access out of bounds"));
+    }
+
+    #[test]
+    fn test_move_left_and_offset_overflow_are_out_of_bounds() {
+        assert!(overflow_error(5, Instruction::MoveLeft(usize::MAX)).contains("Memory access out of bounds"));
+        assert!(overflow_error(5, Instruction::MoveLeft(6)).contains("Memory access out of bounds"));
+
+        for offset in [isize::MIN, -6] {
+            let instruction = Instruction::AddAtOffset { offset, value: 1 };
+            assert!(overflow_error(5, instruction).contains("Memory access out of bounds"));
+        }
+        let instruction = Instruction::SetAtOffset { offset: isize::MAX, value: 1 };
+        assert!(overflow_error(usize::MAX - 1, instruction).contains("Memory access out of bounds"));
+    }
+
+    #[test]
+    fn test_pad_output() {
+        let config = InterpreterConfig {
+            pad_output: NonZeroUsize::new(4),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize("+.+.+."), config.clone(), io::empty(), &mut output);
+        interpreter.run().unwrap();
+        assert_eq!(output, [1, 2, 3, 0]);
+
+        // Already a multiple, and nothing written at all, need no padding
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize("+...."), config.clone(), io::empty(), &mut output);
+        interpreter.run().unwrap();
+        assert_eq!(output, [1, 1, 1, 1]);
+        let mut output = Vec::new();
+        Interpreter::with_io(optimize("+"), config, io::empty(), &mut output).run().unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_max_output_bytes() {
+        let config = InterpreterConfig {
+            max_output_bytes: Some(5),
+            ..Default::default()
+        };
+        // Prints 1, 2, 3, ... forever
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize("+[.+]"), config.clone(), io::empty(), &mut output);
+        let err = interpreter.run().unwrap_err();
+        assert!(err.to_string().contains("output exceeded the limit of 5 bytes"));
+        drop(interpreter);
+        assert_eq!(output, [1, 2, 3, 4, 5]);
+
+        // A run of `.` that crosses the limit is cut short at it
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize("+......."), config.clone(), io::empty(), &mut output);
+        assert!(interpreter.run().is_err());
+        drop(interpreter);
+        assert_eq!(output, [1; 5]);
+
+        // Output exactly at the limit is fine
+        let mut output = Vec::new();
+        Interpreter::with_io(optimize("+....."), config, io::empty(), &mut output).run().unwrap();
+        assert_eq!(output, [1; 5]);
+    }
+
+    #[test]
+    fn test_print_const() {
+        let instructions = vec![Instruction::PrintConst(b"Hi".to_vec())];
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), &mut output);
+        interpreter.run().unwrap();
+
+        assert!(interpreter.memory_state().iter().all(|&cell| cell == 0));
+        assert_eq!(output, b"Hi");
+    }
+
+    #[test]
+    fn test_io_disabled() {
+        let config = InterpreterConfig {
+            allow_io: false,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize(",+."), config.clone(), Cursor::new(b"A"), &mut output);
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: program tried to use input with I/O disabled");
+        drop(interpreter);
+
+        // Output folded into constants is refused too
+        let mut interpreter = Interpreter::with_io(optimize("+++."), config.clone(), io::empty(), &mut output);
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: program tried to use output with I/O disabled");
+        drop(interpreter);
+        assert!(output.is_empty());
+
+        let mut interpreter = Interpreter::new(optimize("++[->+++<]"), config);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.cell(1), Some(6));
+    }
+
+    #[test]
+    fn test_crlf_newlines() {
+        let config = InterpreterConfig {
+            output_newline: NewlineMode::CrLf,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(optimize("++++++++++.,."), config, Cursor::new(b"\n"), &mut output);
+        interpreter.run().unwrap();
+        drop(interpreter);
+        assert_eq!(output, [13, 10, 13, 10]);
+
+        // Folded constants are translated too
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::with_io(
+            vec![Instruction::PrintConst(vec![b'A', b'\n'])],
+            InterpreterConfig::builder().output_newline(NewlineMode::CrLf).build().unwrap(),
+            io::empty(),
+            &mut output,
+        );
+        interpreter.run().unwrap();
+        drop(interpreter);
+        assert_eq!(output, b"A\r\n");
+    }
+
+    #[test]
+    fn test_switch_input() {
+        let config = LexerConfig {
+            input_switch: true,
+            ..Default::default()
+        };
+        let lexer = Lexer::with_config(Cursor::new(",>~,>~,".as_bytes()), config);
+        let instructions = Optimizer::new().optimize(lexer).unwrap();
+
+        let mut interpreter = Interpreter::with_io(instructions, InterpreterConfig::default(), Cursor::new(b"ac"), io::sink());
+        interpreter.add_input(Box::new(Cursor::new(b"b")));
+        interpreter.run().unwrap();
+        assert_eq!(&interpreter.memory_state()[..3], b"abc");
+    }
+
+    #[test]
+    fn test_cat_large_input() {
+        let instructions = Optimizer::new().optimize(Lexer::new(Cursor::new(",[.,]"))).unwrap();
+        let input: Vec<u8> = (0..1 << 20).map(|i| (i % 255 + 1) as u8).collect();
+
+        // Whatever the buffer size, every byte makes it through in order
+        for input_buffer_size in [0, 3, DEFAULT_INPUT_BUFFER_SIZE] {
+            let config = InterpreterConfig::builder()
+                .eof_behavior(EofBehavior::Zero)
+                .input_buffer_size(input_buffer_size)
+                .build()
+                .unwrap();
+            let mut output = Vec::new();
+            let mut interpreter = Interpreter::with_io(instructions.clone(), config, Cursor::new(&input), &mut output);
+            interpreter.run().unwrap();
+            drop(interpreter);
+            assert!(output == input, "buffer size {}", input_buffer_size);
+        }
+    }
+
+    #[test]
+    fn test_wide_io() {
+        let config = InterpreterConfig {
+            memory_size: 4,
+            wide_io: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let instructions = vec![Instruction::Output(1)];
+        let mut interpreter = Interpreter::with_io(instructions, config.clone(), io::empty(), &mut output);
+        interpreter.load_memory(&[0x12, 0x34]);
+        interpreter.run().unwrap();
+        drop(interpreter);
+        assert_eq!(output, [0x12, 0x34]);
+
+        // `,` reads the high byte into the current cell
+        let instructions = vec![Instruction::MoveRight(1), Instruction::Input(1)];
+        let mut interpreter = Interpreter::with_io(instructions, config.clone(), Cursor::new([0xab, 0xcd]), io::sink());
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.memory_state(), [0, 0xab, 0xcd, 0]);
+
+        // The last cell has no next cell to pair with
+        let instructions = vec![Instruction::MoveRight(3), Instruction::Output(1)];
+        let mut interpreter = Interpreter::with_io(instructions, config, io::empty(), io::sink());
+        assert_eq!(out_of_bounds_address(interpreter.run()), 4);
+    }
+
     #[test]
     fn test_debug_mode() {
         let input = "+++";
@@ -273,8 +3356,10 @@ mod tests {
         let mut optimizer = Optimizer::new();
         let instructions = optimizer.optimize(lexer).unwrap();
         
-        let mut config = InterpreterConfig::default();
-        config.debug = true;
+        let config = InterpreterConfig {
+            debug: true,
+            ..Default::default()
+        };
         let mut interpreter = Interpreter::new(instructions, config);
         interpreter.run().unwrap();
         
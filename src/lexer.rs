@@ -1,6 +1,11 @@
 use crate::error::{BrainfuckError, Position};
 use anyhow::Result;
+use std::borrow::Cow;
 use std::io::Read;
+use std::str::FromStr;
+
+/// The byte order mark some editors put at the start of UTF-8 files
+const BOM: char = '\u{feff}';
 
 /// Represents a Brainfuck token with position information
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -28,25 +33,46 @@ pub enum TokenKind {
     LoopStart,
     /// End loop: `]`
     LoopEnd,
+    /// Dump the tape around the pointer: `#`, only when enabled in
+    /// [`LexerConfig`]
+    DebugDump,
+    /// Stop the program: the configured halt character, if any
+    Halt,
+    /// Copy the current cell into the register: `$`, only when enabled in
+    /// [`LexerConfig`]
+    StoreRegister,
+    /// Copy the register into the current cell: `}`, only when enabled in
+    /// [`LexerConfig`]
+    RestoreRegister,
+    /// Switch `,` to the next input source: `~`, only when enabled in
+    /// [`LexerConfig`]
+    SwitchInput,
+    /// A custom command listed in [`LexerConfig::extensions`]
+    Extension(char),
 }
 
 impl TokenKind {
-    /// Check if a character is a valid Brainfuck token
+    /// The eight standard commands, in the order [`CharMap`] lists them
+    pub const COMMANDS: [Self; 8] = [
+        Self::MoveRight,
+        Self::MoveLeft,
+        Self::Increment,
+        Self::Decrement,
+        Self::Output,
+        Self::Input,
+        Self::LoopStart,
+        Self::LoopEnd,
+    ];
+
+    /// Check if a character is a valid Brainfuck token in the standard
+    /// alphabet
     pub fn from_char(c: char) -> Option<Self> {
-        match c {
-            '>' => Some(Self::MoveRight),
-            '<' => Some(Self::MoveLeft),
-            '+' => Some(Self::Increment),
-            '-' => Some(Self::Decrement),
-            '.' => Some(Self::Output),
-            ',' => Some(Self::Input),
-            '[' => Some(Self::LoopStart),
-            ']' => Some(Self::LoopEnd),
-            _ => None,
-        }
+        CharMap::STANDARD.kind(c)
     }
 
     /// Get the character representation of this token
+    ///
+    /// `Halt` has no fixed character and is shown as `@`.
     pub fn to_char(self) -> char {
         match self {
             Self::MoveRight => '>',
@@ -57,6 +83,253 @@ impl TokenKind {
             Self::Input => ',',
             Self::LoopStart => '[',
             Self::LoopEnd => ']',
+            Self::DebugDump => '#',
+            Self::Halt => '@',
+            Self::StoreRegister => '$',
+            Self::RestoreRegister => '}',
+            Self::SwitchInput => '~',
+            Self::Extension(c) => c,
+        }
+    }
+}
+
+/// The characters the eight standard commands are written with
+///
+/// Characters are listed in the order `><+-.,[]`, so the standard alphabet
+/// parses from that string and a remapped one from any eight distinct
+/// characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharMap {
+    chars: [char; 8],
+}
+
+impl CharMap {
+    /// Standard Brainfuck
+    pub const STANDARD: Self = Self {
+        chars: ['>', '<', '+', '-', '.', ',', '[', ']'],
+    };
+
+    /// The command `c` stands for, if any
+    pub fn kind(&self, c: char) -> Option<TokenKind> {
+        let index = self.chars.iter().position(|&command| command == c)?;
+        Some(TokenKind::COMMANDS[index])
+    }
+
+    /// Whether `c` is one of the eight command characters
+    pub fn contains(&self, c: char) -> bool {
+        self.chars.contains(&c)
+    }
+}
+
+impl Default for CharMap {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+impl FromStr for CharMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().collect();
+        let chars: [char; 8] = chars
+            .try_into()
+            .map_err(|_| "expected eight characters for `><+-.,[]`".to_string())?;
+        for (i, &c) in chars.iter().enumerate() {
+            if let Some(first) = chars[..i].iter().position(|&earlier| earlier == c) {
+                return Err(format!(
+                    "'{}' is used for both `{}` and `{}`",
+                    c,
+                    TokenKind::COMMANDS[first].to_char(),
+                    TokenKind::COMMANDS[i].to_char()
+                ));
+            }
+        }
+        Ok(Self { chars })
+    }
+}
+
+/// Settings a program chose for itself with `!` lines at the top of its
+/// source, e.g. `!memsize 65536`
+///
+/// Directive lines are skipped rather than lexed. A `!` line that doesn't
+/// name a known directive is lexed as ordinary source instead, so any
+/// commands on it still run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Directives {
+    /// Tape size requested with `!memsize N`
+    pub memory_size: Option<usize>,
+}
+
+impl Directives {
+    /// Names of the directives that are understood
+    const NAMES: [&'static str; 1] = ["memsize"];
+
+    /// Whether `name` is a directive that is understood
+    fn is_known(name: &str) -> bool {
+        Self::NAMES.contains(&name)
+    }
+
+    /// Apply the directive on a line, without its leading `!`
+    fn apply(&mut self, line: &str, position: Position) -> Result<()> {
+        let mut words = line.split_whitespace();
+        if words.next() == Some("memsize") {
+            let size = words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .filter(|&size| size > 0)
+                .ok_or_else(|| BrainfuckError::ParseError {
+                    position,
+                    message: "`!memsize` needs a tape size greater than 0".to_string(),
+                })?;
+            self.memory_size = Some(size);
+        }
+        Ok(())
+    }
+}
+
+/// Configuration for the lexer
+#[derive(Debug, Clone, Default)]
+pub struct LexerConfig {
+    /// Characters the eight commands are written with (default: standard)
+    pub charmap: CharMap,
+    /// Whether `#` is lexed as a debug dump instead of being a comment
+    pub debug_dump: bool,
+    /// Character lexed as a halt instead of being a comment (default: none)
+    ///
+    /// The eight standard commands always keep their usual meaning.
+    pub halt_char: Option<char>,
+    /// Whether `$` and `}` store and restore a scratch register instead of
+    /// being comments
+    pub register: bool,
+    /// Whether `~` switches `,` between input sources instead of being a
+    /// comment
+    pub input_switch: bool,
+    /// Whether characters other than commands and whitespace are an error
+    /// instead of comments
+    pub strict: bool,
+    /// Characters lexed as custom commands instead of being comments, to be
+    /// run by an [`InstructionHandler`](crate::interpreter::InstructionHandler)
+    pub extensions: Vec<char>,
+}
+
+/// State shared by [`Lexer`] and [`SliceLexer`] as they take the source a
+/// character at a time
+struct Scanner {
+    config: LexerConfig,
+    position: Position,
+    directives: Directives,
+    /// Whether every line so far has been a directive
+    in_header: bool,
+    /// The directive line being read and where its `!` is; while it is all
+    /// letters and digits, the directive's name is still being read
+    directive: Option<(String, Position)>,
+}
+
+impl Scanner {
+    fn new(config: LexerConfig) -> Self {
+        Self {
+            config,
+            position: Position::default(),
+            directives: Directives::default(),
+            in_header: true,
+            directive: None,
+        }
+    }
+
+    /// Take the next character of the source, returning the token it is, if any
+    fn scan(&mut self, c: char) -> Result<Option<Token>> {
+        let position = self.position;
+        // A byte order mark at the very start is not part of the source
+        if c == BOM && position == Position::default() {
+            return Ok(None);
+        }
+        self.update_position(c);
+
+        if let Some((line, _)) = &self.directive {
+            let extends_name = is_name(line) && c.is_ascii_alphanumeric() && self.token_kind(c).is_none();
+            // Otherwise an unknown name leaves the line to be lexed as usual
+            if extends_name || !self.drop_unknown_directive()? {
+                if c == '\n' {
+                    self.finish_directive()?;
+                } else if let Some((line, _)) = &mut self.directive {
+                    line.push(c);
+                }
+                return Ok(None);
+            }
+        }
+        if self.in_header && position.column == 1 {
+            if c == '!' && self.token_kind(c).is_none() {
+                self.directive = Some((String::new(), position));
+                return Ok(None);
+            }
+            self.in_header = false;
+        }
+
+        if let Some(kind) = self.token_kind(c) {
+            return Ok(Some(Token { kind, position }));
+        }
+        if self.config.strict && !c.is_whitespace() {
+            return Err(BrainfuckError::InvalidCharacter { character: c, position }.into());
+        }
+        Ok(None)
+    }
+
+    /// Apply the directive line being read, if any
+    fn finish_directive(&mut self) -> Result<()> {
+        if self.drop_unknown_directive()? {
+            return Ok(());
+        }
+        match self.directive.take() {
+            Some((line, position)) => self.directives.apply(&line, position),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop reading a directive whose whole name has been read but is not
+    /// one that is understood, returning whether it was dropped
+    ///
+    /// Its `!` and name are then comments, or an error in strict mode.
+    fn drop_unknown_directive(&mut self) -> Result<bool> {
+        match self.directive.take() {
+            Some((line, position)) if is_name(&line) && !Directives::is_known(&line) => {
+                self.in_header = false;
+                if self.config.strict {
+                    return Err(BrainfuckError::InvalidCharacter { character: '!', position }.into());
+                }
+                Ok(true)
+            }
+            directive => {
+                self.directive = directive;
+                Ok(false)
+            }
+        }
+    }
+
+    /// The token a character stands for, including enabled extensions
+    fn token_kind(&self, c: char) -> Option<TokenKind> {
+        self.config.charmap.kind(c).or(match c {
+            '#' if self.config.debug_dump => Some(TokenKind::DebugDump),
+            '$' if self.config.register => Some(TokenKind::StoreRegister),
+            '}' if self.config.register => Some(TokenKind::RestoreRegister),
+            '~' if self.config.input_switch => Some(TokenKind::SwitchInput),
+            _ if self.config.halt_char == Some(c) => Some(TokenKind::Halt),
+            _ if self.config.extensions.contains(&c) => Some(TokenKind::Extension(c)),
+            _ => None,
+        })
+    }
+
+    /// Update position based on character
+    ///
+    /// `\r` takes up no column, so `\r\n` counts as a single line break.
+    fn update_position(&mut self, c: char) {
+        match c {
+            '\n' => {
+                self.position.line += 1;
+                self.position.column = 1;
+            }
+            '\r' => {}
+            _ => self.position.column += 1,
         }
     }
 }
@@ -64,9 +337,11 @@ impl TokenKind {
 /// A lexer that tokenizes Brainfuck source code
 pub struct Lexer<R> {
     reader: R,
-    position: Position,
     buffer: Vec<char>,
     buffer_pos: usize,
+    /// The start of a character cut off at the end of the last chunk
+    partial: Vec<u8>,
+    scanner: Scanner,
 }
 
 impl<R> Lexer<R>
@@ -75,71 +350,79 @@ where
 {
     /// Create a new lexer from a reader
     pub fn new(reader: R) -> Self {
+        Self::with_config(reader, LexerConfig::default())
+    }
+
+    /// Create a new lexer from a reader with the given configuration
+    pub fn with_config(reader: R, config: LexerConfig) -> Self {
         Self {
             reader,
-            position: Position::default(),
             buffer: Vec::new(),
             buffer_pos: 0,
+            partial: Vec::new(),
+            scanner: Scanner::new(config),
         }
     }
 
     /// Read the next token from the source
     pub fn next_token(&mut self) -> Result<Option<Token>> {
-        // Read more characters if buffer is empty
-        if self.buffer_pos >= self.buffer.len() {
-            self.read_more()?;
-        }
+        loop {
+            // Skip non-Brainfuck characters
+            while self.buffer_pos < self.buffer.len() {
+                let c = self.buffer[self.buffer_pos];
+                self.buffer_pos += 1;
+                if let Some(token) = self.scanner.scan(c)? {
+                    return Ok(Some(token));
+                }
+            }
 
-        // Skip non-Brainfuck characters
-        while self.buffer_pos < self.buffer.len() {
-            let c = self.buffer[self.buffer_pos];
-            self.buffer_pos += 1;
-            self.update_position(c);
-
-            if let Some(kind) = TokenKind::from_char(c) {
-                return Ok(Some(Token {
-                    kind,
-                    position: self.position,
-                }));
+            // A chunk made only of comments is not the end of the source
+            if !self.read_more()? {
+                self.scanner.finish_directive()?;
+                return Ok(None);
             }
         }
+    }
 
-        Ok(None)
+    /// Directives read from the top of the source so far
+    pub fn directives(&self) -> &Directives {
+        &self.scanner.directives
     }
 
-    /// Read more characters into the buffer
-    fn read_more(&mut self) -> Result<()> {
+    /// Replace the consumed buffer with the next chunk, returning false at EOF
+    fn read_more(&mut self) -> Result<bool> {
         let mut buf = [0u8; 1024];
+        let kept = self.partial.len();
+        buf[..kept].copy_from_slice(&self.partial);
+        self.partial.clear();
         let bytes_read = self
             .reader
-            .read(&mut buf)
+            .read(&mut buf[kept..])
             .map_err(|e| BrainfuckError::IoError {
                 message: format!("Failed to read source: {}", e),
             })?;
 
-        if bytes_read == 0 {
-            return Ok(());
+        if bytes_read == 0 && kept == 0 {
+            return Ok(false);
         }
 
-        // Convert bytes to chars, handling UTF-8 properly
-        let string = String::from_utf8_lossy(&buf[..bytes_read]);
-        self.buffer.extend(string.chars());
-        Ok(())
-    }
-
-    /// Update position based on character
-    fn update_position(&mut self, c: char) {
-        if c == '\n' {
-            self.position.line += 1;
-            self.position.column = 1;
-        } else {
-            self.position.column += 1;
+        // Convert bytes to chars, holding back a character split across
+        // chunks until the rest of it is read, or replacing it at the end
+        let mut end = kept + bytes_read;
+        if bytes_read > 0 {
+            end -= incomplete_tail(&buf[..end]);
+            self.partial.extend_from_slice(&buf[end..kept + bytes_read]);
         }
+        let string = String::from_utf8_lossy(&buf[..end]);
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.buffer.extend(string.chars());
+        Ok(true)
     }
 
     /// Get the current position
     pub fn position(&self) -> Position {
-        self.position
+        self.scanner.position
     }
 }
 
@@ -158,6 +441,122 @@ where
     }
 }
 
+/// Whether a directive line read so far is still just its name
+fn is_name(line: &str) -> bool {
+    line.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Length of the start of a UTF-8 character cut off at the end of `bytes`
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    // A character is at most four bytes, so a cut one starts in the last three
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xC0 != 0x80 {
+            let width = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if width > back { back } else { 0 };
+        }
+    }
+    0
+}
+
+/// A lexer over source already in memory, which walks the bytes directly
+/// instead of copying them through a buffer as [`Lexer`] does
+///
+/// It yields the same tokens as [`Lexer`] would for the same source. Invalid
+/// UTF-8 is replaced with U+FFFD, which only then needs a copy.
+pub struct SliceLexer<'a> {
+    text: Cow<'a, str>,
+    /// Byte offset of the next character in `text`
+    offset: usize,
+    scanner: Scanner,
+}
+
+impl<'a> SliceLexer<'a> {
+    /// Create a new lexer over `source`
+    pub fn new(source: &'a [u8]) -> Self {
+        Self::with_config(source, LexerConfig::default())
+    }
+
+    /// Create a new lexer over `source` with the given configuration
+    pub fn with_config(source: &'a [u8], config: LexerConfig) -> Self {
+        Self {
+            text: String::from_utf8_lossy(source),
+            offset: 0,
+            scanner: Scanner::new(config),
+        }
+    }
+
+    /// Read the next token from the source
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        while let Some(c) = self.text[self.offset..].chars().next() {
+            self.offset += c.len_utf8();
+            if let Some(token) = self.scanner.scan(c)? {
+                return Ok(Some(token));
+            }
+        }
+        self.scanner.finish_directive()?;
+        Ok(None)
+    }
+
+    /// Directives read from the top of the source so far
+    pub fn directives(&self) -> &Directives {
+        &self.scanner.directives
+    }
+
+    /// Get the current position
+    pub fn position(&self) -> Position {
+        self.scanner.position
+    }
+}
+
+impl Iterator for SliceLexer<'_> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_token() {
+            Ok(Some(token)) => Some(Ok(token)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Every error in a source without optimizing it: characters the lexer
+/// rejects, `]` with no `[` and `[` with no `]`, in source order
+///
+/// Lexing carries on past each error, but stops at one with no position,
+/// such as a failed read.
+pub fn validate(tokens: impl Iterator<Item = Result<Token>>) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    let mut open = Vec::new();
+    for token in tokens {
+        match token {
+            Ok(Token { kind: TokenKind::LoopStart, position }) => open.push(position),
+            Ok(Token { kind: TokenKind::LoopEnd, position }) => {
+                if open.pop().is_none() {
+                    errors.push(BrainfuckError::UnmatchedBracket { position }.into());
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let positioned = e.downcast_ref().and_then(BrainfuckError::position).is_some();
+                errors.push(e);
+                if !positioned {
+                    return errors;
+                }
+            }
+        }
+    }
+    errors.extend(open.into_iter().map(|position| BrainfuckError::UnmatchedBracket { position }.into()));
+    errors.sort_by_key(|e: &anyhow::Error| e.downcast_ref().and_then(BrainfuckError::position).map(|p| (p.line, p.column)));
+    errors
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,6 +576,38 @@ mod tests {
         assert_eq!(TokenKind::from_char(' '), None);
     }
 
+    #[test]
+    fn test_charmap_remaps_commands() {
+        let config = LexerConfig {
+            charmap: "RLIDOSab".parse().unwrap(),
+            ..Default::default()
+        };
+        let tokens: Vec<TokenKind> = Lexer::with_config(Cursor::new("IIaRI+<LDbO".as_bytes()), config)
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert_eq!(tokens, [
+            TokenKind::Increment,
+            TokenKind::Increment,
+            TokenKind::LoopStart,
+            TokenKind::MoveRight,
+            TokenKind::Increment,
+            TokenKind::MoveLeft,
+            TokenKind::Decrement,
+            TokenKind::LoopEnd,
+            TokenKind::Output,
+        ]);
+    }
+
+    #[test]
+    fn test_charmap_parse_errors() {
+        assert_eq!("><+-.,[]".parse::<CharMap>(), Ok(CharMap::STANDARD));
+        assert!("><+-.,[".parse::<CharMap>().is_err());
+        assert_eq!(
+            "abcdefga".parse::<CharMap>(),
+            Err("'a' is used for both `>` and `]`".to_string())
+        );
+    }
+
     #[test]
     fn test_token_kind_to_char() {
         assert_eq!(TokenKind::MoveRight.to_char(), '>');
@@ -195,14 +626,14 @@ mod tests {
         let cursor = Cursor::new(input.as_bytes());
         let mut lexer = Lexer::new(cursor);
 
-        let expected_tokens = vec![
+        let expected_tokens = [
             TokenKind::MoveRight,
             TokenKind::Increment,
             TokenKind::MoveLeft,
             TokenKind::Decrement,
             TokenKind::LoopStart,
-            TokenKind::Input,
             TokenKind::LoopEnd,
+            TokenKind::Input,
             TokenKind::Output,
         ];
 
@@ -220,14 +651,14 @@ mod tests {
         let cursor = Cursor::new(input.as_bytes());
         let mut lexer = Lexer::new(cursor);
 
-        let expected_tokens = vec![
+        let expected_tokens = [
             TokenKind::MoveRight,
             TokenKind::Increment,
             TokenKind::MoveLeft,
             TokenKind::Decrement,
             TokenKind::LoopStart,
-            TokenKind::Input,
             TokenKind::LoopEnd,
+            TokenKind::Input,
             TokenKind::Output,
         ];
 
@@ -260,4 +691,248 @@ mod tests {
         assert_eq!(token.kind, TokenKind::MoveLeft);
         assert_eq!(token.position, Position::new(2, 1));
     }
+
+    #[test]
+    fn test_lexer_crlf_and_bom() {
+        let positions = |source: &str| -> Vec<Position> {
+            Lexer::new(Cursor::new(source.as_bytes())).map(|token| token.unwrap().position).collect()
+        };
+        assert_eq!(positions("+\r\n >-\r\n"), [Position::new(1, 1), Position::new(2, 2), Position::new(2, 3)]);
+        assert_eq!(positions("\u{feff}+\r\n-"), [Position::new(1, 1), Position::new(2, 1)]);
+
+        // Neither is a stray character in strict mode, and a directive can
+        // still start the file
+        let config = LexerConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_config(Cursor::new("\u{feff}!memsize 10\r\n+\r\n".as_bytes()), config);
+        let tokens: Vec<Token> = lexer.by_ref().map(|token| token.unwrap()).collect();
+        assert_eq!(tokens, [Token { kind: TokenKind::Increment, position: Position::new(2, 1) }]);
+        assert_eq!(lexer.directives().memory_size, Some(10));
+    }
+
+    #[test]
+    fn test_validate_collects_every_error() {
+        assert!(validate(Lexer::new(Cursor::new("+[>[-]<]".as_bytes()))).is_empty());
+
+        let config = LexerConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let errors = validate(Lexer::with_config(Cursor::new("+]x\n[[]".as_bytes()), config));
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, [
+            "Unmatched bracket at position 1:2",
+            "Invalid character 'x' at position 1:3",
+            "Unmatched bracket at position 2:1",
+        ]);
+    }
+
+    #[test]
+    fn test_slice_lexer_matches_reader_lexer() {
+        // Characters that straddle the 1024-byte chunks `Lexer` reads in
+        let split_two = format!("{}é+", " ".repeat(1023));
+        let split_four = format!("{}🦀x+]", "+".repeat(2046));
+        let sources = [
+            "++[>+<-]>.",
+            "!memsize 64\r\n\u{feff}+ comment, with é and 🦀\n[-]#@",
+            "\u{feff}>>\r\n<<",
+            "+]x[",
+            "!hello +++.\n!memsize",
+            &split_two,
+            &split_four,
+        ];
+        let configs = [
+            LexerConfig::default(),
+            LexerConfig {
+                strict: true,
+                debug_dump: true,
+                halt_char: Some('@'),
+                ..Default::default()
+            },
+        ];
+        for source in sources {
+            for config in &configs {
+                let mut reader = Lexer::with_config(Cursor::new(source.as_bytes()), config.clone());
+                let mut slice = SliceLexer::with_config(source.as_bytes(), config.clone());
+                let describe = |token: Result<Token>| token.map_or_else(|e| e.to_string(), |token| format!("{:?}", token));
+                let from_reader: Vec<String> = reader.by_ref().map(describe).collect();
+                let from_slice: Vec<String> = slice.by_ref().map(describe).collect();
+                assert_eq!(from_slice, from_reader, "{:?}", source);
+                assert_eq!(slice.directives(), reader.directives());
+                assert_eq!(slice.position(), reader.position());
+            }
+        }
+
+        // A character cut off by the end of the source is one U+FFFD in both
+        let truncated = b"+\xE2\x82";
+        let mut reader = Lexer::new(Cursor::new(&truncated[..]));
+        let mut slice = SliceLexer::new(truncated);
+        assert_eq!(reader.by_ref().count(), slice.by_ref().count());
+        assert_eq!(reader.position(), slice.position());
+        assert_eq!(reader.position(), Position::new(1, 3));
+    }
+
+    #[test]
+    fn test_lexer_debug_dump() {
+        let tokens: Vec<TokenKind> = Lexer::new(Cursor::new("+#".as_bytes()))
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert_eq!(tokens, [TokenKind::Increment]);
+
+        let config = LexerConfig {
+            debug_dump: true,
+            ..Default::default()
+        };
+        let tokens: Vec<TokenKind> = Lexer::with_config(Cursor::new("+#".as_bytes()), config)
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert_eq!(tokens, [TokenKind::Increment, TokenKind::DebugDump]);
+    }
+
+    #[test]
+    fn test_lexer_halt_char() {
+        let tokens: Vec<TokenKind> = Lexer::new(Cursor::new("+@-".as_bytes()))
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert_eq!(tokens, [TokenKind::Increment, TokenKind::Decrement]);
+
+        let config = LexerConfig {
+            halt_char: Some('@'),
+            ..Default::default()
+        };
+        let tokens: Vec<TokenKind> = Lexer::with_config(Cursor::new("+@-".as_bytes()), config)
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert_eq!(tokens, [TokenKind::Increment, TokenKind::Halt, TokenKind::Decrement]);
+
+        // A standard command cannot be turned into a halt
+        let config = LexerConfig {
+            halt_char: Some('+'),
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_config(Cursor::new("+".as_bytes()), config);
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenKind::Increment);
+    }
+
+    #[test]
+    fn test_lexer_register() {
+        let tokens: Vec<TokenKind> = Lexer::new(Cursor::new("$}".as_bytes()))
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert!(tokens.is_empty());
+
+        let config = LexerConfig {
+            register: true,
+            ..Default::default()
+        };
+        let tokens: Vec<TokenKind> = Lexer::with_config(Cursor::new("$+}".as_bytes()), config)
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            [TokenKind::StoreRegister, TokenKind::Increment, TokenKind::RestoreRegister]
+        );
+    }
+
+    #[test]
+    fn test_lexer_input_switch() {
+        let kinds = |config: LexerConfig| -> Vec<TokenKind> {
+            Lexer::with_config(Cursor::new("~,".as_bytes()), config)
+                .map(|token| token.unwrap().kind)
+                .collect()
+        };
+        assert_eq!(kinds(LexerConfig::default()), [TokenKind::Input]);
+
+        let config = LexerConfig {
+            input_switch: true,
+            ..Default::default()
+        };
+        assert_eq!(kinds(config), [TokenKind::SwitchInput, TokenKind::Input]);
+    }
+
+    #[test]
+    fn test_lexer_directives() {
+        let source = "!memsize 100 +\n+!memsize 5\n";
+        let mut lexer = Lexer::new(Cursor::new(source.as_bytes()));
+        let tokens: Vec<TokenKind> = lexer.by_ref().map(|token| token.unwrap().kind).collect();
+
+        // Only the leading `!` lines are directives, and their commands are skipped
+        assert_eq!(tokens, [TokenKind::Increment]);
+        assert_eq!(lexer.directives().memory_size, Some(100));
+    }
+
+    #[test]
+    fn test_lexer_unknown_directive_is_source() {
+        let source = "!hello +++.\n!memsize 5\n";
+        let mut lexer = Lexer::new(Cursor::new(source.as_bytes()));
+        let tokens: Vec<Token> = lexer.by_ref().map(|token| token.unwrap()).collect();
+
+        // Its commands are kept, and it ends the directives
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[0], Token { kind: TokenKind::Increment, position: Position::new(1, 8) });
+        assert_eq!(lexer.directives().memory_size, None);
+
+        // A name cut short by a command is not a directive either
+        let mut lexer = Lexer::new(Cursor::new("!mem+".as_bytes()));
+        assert_eq!(lexer.next_token().unwrap().map(|token| token.kind), Some(TokenKind::Increment));
+
+        let config = LexerConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_config(Cursor::new("!hello\n+".as_bytes()), config);
+        let err = lexer.next_token().unwrap_err();
+        assert!(err.to_string().contains("Invalid character '!' at position 1:1"));
+    }
+
+    #[test]
+    fn test_lexer_invalid_directive() {
+        let mut lexer = Lexer::new(Cursor::new("!memsize 0".as_bytes()));
+        let err = lexer.next_token().unwrap_err();
+        assert!(err.to_string().contains("`!memsize` needs a tape size greater than 0"));
+        assert_eq!(lexer.directives().memory_size, None);
+    }
+
+    #[test]
+    fn test_lexer_extensions() {
+        let config = LexerConfig {
+            extensions: vec!['?', '+'],
+            ..Default::default()
+        };
+        let tokens: Vec<TokenKind> = Lexer::with_config(Cursor::new("?+!".as_bytes()), config)
+            .map(|token| token.unwrap().kind)
+            .collect();
+        // Standard commands keep their meaning
+        assert_eq!(tokens, [TokenKind::Extension('?'), TokenKind::Increment]);
+    }
+
+    #[test]
+    fn test_lexer_reads_past_comment_only_chunk() {
+        let input = format!("{}+", " ".repeat(4096));
+        let cursor = Cursor::new(input.into_bytes());
+        let mut lexer = Lexer::new(cursor);
+
+        let token = lexer.next().unwrap().unwrap();
+        assert_eq!(token.kind, TokenKind::Increment);
+        assert_eq!(token.position, Position::new(1, 4097));
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_lexer_strict() {
+        let strict = LexerConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_config(Cursor::new("+ \n\t+a+".as_bytes()), strict);
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenKind::Increment);
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenKind::Increment);
+        let error = lexer.next().unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "Invalid character 'a' at position 2:3");
+
+        let tokens = Lexer::new(Cursor::new("+a+".as_bytes())).count();
+        assert_eq!(tokens, 2);
+    }
 } 
\ No newline at end of file
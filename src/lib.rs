@@ -0,0 +1,21 @@
+//! A fast and efficient Brainfuck interpreter.
+//!
+//! The crate is organised as a classic pipeline: the [`lexer`] turns source
+//! text into tokens, the [`optimizer`] folds those tokens into instructions,
+//! and the [`interpreter`] executes them.
+
+#[cfg(feature = "bignum")]
+pub mod bignum;
+pub mod bytecode;
+pub mod diagnostic;
+pub mod error;
+pub mod interpreter;
+pub mod lexer;
+pub mod optimizer;
+pub mod output;
+pub mod profile;
+pub mod program;
+pub mod source;
+pub mod tape;
+pub mod transpiler;
+pub mod wasm;
@@ -1,18 +1,47 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+#[cfg(feature = "bignum")]
+use brainfuck_interpreter::bignum::BignumInterpreter;
+use brainfuck_interpreter::bytecode;
+use brainfuck_interpreter::diagnostic::SourceText;
+use brainfuck_interpreter::error::{BrainfuckError, Position};
+use brainfuck_interpreter::interpreter::{EofBehavior, Interpreter, InterpreterConfig, NewlineMode, OutputMode};
+use brainfuck_interpreter::lexer::{self, CharMap, LexerConfig, SliceLexer, Token, TokenKind};
+use brainfuck_interpreter::optimizer::{self, OptLevel, Optimizer, OptimizerConfig};
+use brainfuck_interpreter::output::{EscapeOutput, HexDump};
+use brainfuck_interpreter::profile;
+use brainfuck_interpreter::program::{self, Program};
+use brainfuck_interpreter::source::Source;
+use brainfuck_interpreter::tape::TapeKind;
+use brainfuck_interpreter::transpiler;
+use clap::{Parser, ValueEnum};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
 
-mod error;
-mod interpreter;
-mod lexer;
-mod optimizer;
-
+/// Languages the optimized program can be translated to instead of running it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EmitTarget {
+    /// A standalone C program
+    C,
+    /// Canonical Brainfuck rebuilt from the optimized instructions
+    Bf,
+    /// A GraphViz digraph of the basic blocks and the jumps between them
+    Dot,
+}
 
-use interpreter::{Interpreter, InterpreterConfig};
-use lexer::Lexer;
-use optimizer::Optimizer;
+/// How the program's output is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// The bytes exactly as the program wrote them
+    Raw,
+    /// A hex dump with offsets and an ASCII column
+    Hex,
+    /// Printable ASCII as is, and every other byte but newlines and tabs
+    /// escaped as `\xNN`
+    Escaped,
+}
 
 /// A fast and efficient Brainfuck interpreter written in Rust
 #[derive(Parser)]
@@ -23,33 +52,238 @@ use optimizer::Optimizer;
     long_about = "A Brainfuck interpreter that supports all standard Brainfuck operations with optimization and robust error handling."
 )]
 struct Cli {
-    /// The Brainfuck source file to execute
+    /// The Brainfuck source file to execute, or `-` to read it from stdin
+    ///
+    /// When the program is read from stdin, any `,` input it performs sees
+    /// whatever remains on stdin after the source, which is usually nothing.
     #[arg(value_name = "FILE")]
     file: PathBuf,
 
-    /// Enable debug output showing instruction execution
+    /// Enable debug output showing instruction execution; `#` in the program
+    /// then dumps the tape around the pointer
     #[arg(short, long)]
     debug: bool,
 
-    /// Set the memory size (default: 30000)
-    #[arg(short, long, default_value = "30000")]
-    memory_size: usize,
+    /// Cells shown on each side of the pointer in debug dumps
+    #[arg(long, default_value = "8", value_name = "CELLS")]
+    debug_window: usize,
+
+    /// Set the memory size, overriding a `!memsize` directive (default: 30000)
+    #[arg(short, long)]
+    memory_size: Option<usize>,
+
+    /// How the tape is stored; a sparse tape allows huge memory sizes
+    #[arg(long, value_enum, default_value_t = TapeKind::Dense)]
+    tape: TapeKind,
 
     /// Disable optimizations
     #[arg(long)]
     no_optimize: bool,
 
+    /// How much to optimize; `fold` merges runs but keeps loops as jumps
+    #[arg(long, value_enum, default_value_t = OptLevel::Full, conflicts_with = "no_optimize")]
+    opt_level: OptLevel,
+
+    /// Drop unmatched `]` with a warning instead of refusing to run
+    #[arg(long)]
+    lenient: bool,
+
+    /// Warn about `-` that wraps a cell known to be zero, e.g. at program start
+    #[arg(long)]
+    warn_wrapping: bool,
+
+    /// Warn about loops that leave the pointer somewhere other than where they started
+    #[arg(long)]
+    warn_pointer_drift: bool,
+
     /// Show program statistics after execution
     #[arg(short, long)]
     stats: bool,
+
+    /// How `.` encodes the current cell
+    #[arg(long, value_enum, default_value_t = OutputMode::Bytes)]
+    output_mode: OutputMode,
+
+    /// Initial value of every memory cell
+    #[arg(long, value_name = "N", default_value = "0")]
+    fill: u8,
+
+    /// Show cells as signed values from -128 to 127 in debug output
+    #[arg(long)]
+    signed: bool,
+
+    /// Wrap `+` and `-` at this modulus instead of 256; disables optimization
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=256))]
+    modulus: Option<u32>,
+
+    /// Fail when one run of a loop jumps back more than N times
+    #[arg(long, value_name = "N")]
+    max_loop_iterations: Option<u64>,
+
+    /// Pad the output with zero bytes to a multiple of N bytes at program end
+    #[arg(long, value_name = "N")]
+    pad_output: Option<NonZeroUsize>,
+
+    /// Fail once the program writes more than N bytes, keeping the first N
+    #[arg(long, value_name = "N")]
+    max_output: Option<u64>,
+
+    /// Refuse to hold more than N bytes of tape, counting cells as they are
+    /// written with a sparse tape
+    #[arg(long, value_name = "N")]
+    max_memory_bytes: Option<usize>,
+
+    /// Read input up to N bytes at a time, or 0 to read a byte at a time
+    #[arg(long, value_name = "N", default_value = "8192")]
+    input_buffer_size: usize,
+
+    /// Fail on `.` and `,` instead of writing output or reading input
+    #[arg(long)]
+    no_io: bool,
+
+    /// Give cells unbounded integers that never wrap, printing them modulo
+    /// 256; experimental, disables optimization and can't be combined with
+    /// debugging, tracing, statistics or output encoding options
+    #[cfg(feature = "bignum")]
+    #[arg(long, conflicts_with_all = [
+        "verify", "debug", "stats", "trace", "replay", "profile_output", "profile_time", "memory_snapshot",
+        "second_input", "max_loop_iterations", "max_output", "max_memory_bytes", "pad_output", "wide_io", "crlf",
+        "output_mode", "modulus", "signed", "tape", "interactive_input",
+    ])]
+    bignum: bool,
+
+    /// Write each `\n` the program outputs as `\r\n`
+    #[arg(long)]
+    crlf: bool,
+
+    /// Make `.` and `,` write and read 16-bit values across two cells
+    #[arg(long)]
+    wide_io: bool,
+
+    /// Fail when the program runs for longer than SECONDS of wall-clock time
+    #[arg(long, value_name = "SECONDS", value_parser = parse_timeout)]
+    timeout: Option<Duration>,
+
+    /// Store this byte when `,` reads past the end of input (default: error)
+    #[arg(long, value_name = "N")]
+    eof_value: Option<u8>,
+
+    /// Write a tab-separated execution trace to this file
+    #[arg(long, value_name = "PATH")]
+    trace: Option<PathBuf>,
+
+    /// Check every step against a trace written by --trace, failing at the
+    /// first step that differs
+    #[arg(long, value_name = "TRACE")]
+    replay: Option<PathBuf>,
+
+    /// Write the commands `><+-.,[]` with these eight characters instead
+    #[arg(long, value_name = "CHARS")]
+    charmap: Option<CharMap>,
+
+    /// Stop the program when it reaches this character, e.g. `@`
+    #[arg(long, value_name = "CHAR", value_parser = parse_halt_char)]
+    halt_char: Option<char>,
+
+    /// Prompt on stderr before every byte `,` reads when input is a terminal
+    #[arg(long)]
+    interactive_input: bool,
+
+    /// Enable the `$` (store) and `}` (restore) scratch register extension
+    #[arg(long)]
+    register: bool,
+
+    /// Reject characters that are neither commands nor whitespace
+    #[arg(long)]
+    strict: bool,
+
+    /// Read the program's `,` input from this file instead of stdin
+    #[arg(long, value_name = "PATH")]
+    input: Option<PathBuf>,
+
+    /// Read input from this file too, with `~` in the program switching `,`
+    /// between it and the usual input
+    #[arg(long, value_name = "PATH")]
+    second_input: Option<PathBuf>,
+
+    /// Write the program's `.` output to this file instead of stdout,
+    /// replacing any existing contents
+    #[arg(long, value_name = "PATH", conflicts_with = "verify")]
+    output: Option<PathBuf>,
+
+    /// How to show the program's output; `hex` renders it as a hex dump and
+    /// `escaped` keeps control and non-ASCII bytes from reaching the terminal
+    #[arg(long, value_enum, default_value_t = OutputFormat::Raw, conflicts_with = "verify")]
+    output_format: OutputFormat,
+
+    /// Show control and non-ASCII output bytes as `\xNN`; short for
+    /// `--output-format escaped`
+    #[arg(long, conflicts_with_all = ["output_format", "verify"])]
+    escape_output: bool,
+
+    /// Fill the start of the tape from this file, one byte per cell, before
+    /// running; extra bytes are ignored and missing ones keep the --fill value
+    #[arg(long, value_name = "PATH")]
+    load_memory: Option<PathBuf>,
+
+    /// Write the final contents of the tape to this file, one byte per cell,
+    /// even if execution fails
+    #[arg(long, value_name = "PATH")]
+    memory_snapshot: Option<PathBuf>,
+
+    /// Write per-loop execution counts as folded stacks for flamegraph tools
+    #[arg(long, value_name = "PATH")]
+    profile_output: Option<PathBuf>,
+
+    /// Print the instructions that took the most wall-clock time
+    #[arg(long)]
+    profile_time: bool,
+
+    /// Print static metrics about the program instead of running it
+    #[arg(long)]
+    analyze: bool,
+
+    /// Print what each loop the optimizer recognised does instead of running
+    /// the program
+    #[arg(long)]
+    explain: bool,
+
+    /// Print each token the lexer finds as `line:column kind` instead of
+    /// optimizing or running the program
+    #[arg(long)]
+    dump_tokens: bool,
+
+    /// Only lex the program and match its brackets, printing `OK` or every
+    /// error found and failing if there are any
+    #[arg(long)]
+    check: bool,
+
+    /// Compare the program's output with this file instead of printing it,
+    /// failing with the first difference on a mismatch
+    #[arg(long, value_name = "EXPECTED_FILE")]
+    verify: Option<PathBuf>,
+
+    /// Save the optimized program as bytecode instead of running it; pass the
+    /// saved `.bfc` file as FILE to run it without parsing again
+    #[arg(long, value_name = "OUT.bfc")]
+    compile: Option<PathBuf>,
+
+    /// Print the program translated to another language instead of running it
+    #[arg(long, value_enum, value_name = "TARGET")]
+    emit: Option<EmitTarget>,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    // Validate memory size
-    if cli.memory_size == 0 {
-        return Err(anyhow::anyhow!("Memory size must be greater than 0"));
+    if let (Some(charmap), Some(halt_char)) = (cli.charmap, cli.halt_char) {
+        if charmap.contains(halt_char) {
+            return Err(anyhow::anyhow!("Halt character '{}' is already in the charmap", halt_char));
+        }
+    }
+
+    if cli.memory_snapshot.is_some() && cli.tape == TapeKind::Sparse {
+        return Err(anyhow::anyhow!("Memory snapshots need a dense tape"));
     }
 
     // Read and execute the Brainfuck program
@@ -59,41 +293,295 @@ fn main() -> Result<()> {
 }
 
 fn run_brainfuck_program(cli: &Cli) -> Result<()> {
-    // Open the source file
-    let file = File::open(&cli.file).with_context(|| {
-        format!("Failed to open file '{}'", cli.file.display())
-    })?;
+    let source = Source::from_path(&cli.file);
+    let color = io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
 
-    let reader = BufReader::new(file);
+    #[cfg(feature = "bignum")]
+    let bignum = cli.bignum;
+    #[cfg(not(feature = "bignum"))]
+    let bignum = false;
 
-    // Create lexer
-    let lexer = Lexer::new(reader);
+    // Folded arithmetic assumes byte wrapping, so a custom modulus or
+    // unbounded cells run naively
+    let opt_level = if cli.no_optimize || cli.modulus.is_some() || bignum {
+        OptLevel::None
+    } else if cli.wide_io {
+        // Output of known cells is folded a byte at a time
+        cli.opt_level.min(OptLevel::Fold)
+    } else {
+        cli.opt_level
+    };
 
-    // Create optimizer and parse instructions
-    let mut optimizer = Optimizer::new();
-    let instructions = optimizer.optimize(lexer)
-        .with_context(|| format!("Failed to parse Brainfuck program from '{}'", cli.file.display()))?;
+    // Create optimizer
+    let mut optimizer = Optimizer::with_config(OptimizerConfig {
+        opt_level,
+        // A loaded tape's contents are only known at run time, and compiled
+        // bytecode may be run with any tape
+        initial_cell_value: Some(cli.fill).filter(|_| cli.load_memory.is_none() && cli.compile.is_none()),
+        lenient: cli.lenient,
+        warn_wrapping: cli.warn_wrapping,
+        warn_pointer_drift: cli.warn_pointer_drift,
+    });
+
+    // Bytecode skips lexing and optimizing, but has no source text for
+    // diagnostics to point into
+    let bytes = source.read_bytes()?;
+    let bytecode = program::is_bytecode(&source, &bytes);
+    let text = SourceText::new(
+        source.to_string(),
+        if bytecode { String::new() } else { String::from_utf8_lossy(&bytes).into_owned() },
+    );
+    let lexer_config = LexerConfig {
+        charmap: cli.charmap.unwrap_or_default(),
+        debug_dump: cli.debug,
+        halt_char: cli.halt_char,
+        register: cli.register,
+        input_switch: cli.second_input.is_some(),
+        strict: cli.strict,
+        ..Default::default()
+    };
+
+    if cli.dump_tokens {
+        if bytecode {
+            anyhow::bail!("'{}' is compiled bytecode, which has no tokens to dump", source);
+        }
+        return dump_tokens(SliceLexer::with_config(&bytes, lexer_config), &mut io::stdout().lock())
+            .with_context(|| format!("Failed to read tokens from '{}'", source));
+    }
+
+    if cli.check {
+        if bytecode {
+            anyhow::bail!("'{}' is compiled bytecode, which has no source to check", source);
+        }
+        let errors = check_source(&text, SliceLexer::with_config(&bytes, lexer_config), color);
+        if !errors.is_empty() {
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            eprintln!("{} error{} in '{}'", errors.len(), if errors.len() == 1 { "" } else { "s" }, source);
+            std::process::exit(1);
+        }
+        println!("OK");
+        return Ok(());
+    }
+
+    let program = match Program::load_with(&source, &bytes, &mut optimizer, lexer_config) {
+        Ok(program) => program,
+        Err(e) => {
+            // Point into the source at errors that know where they are
+            if let Some(error) = e.downcast_ref::<BrainfuckError>() {
+                if let Some(position) = error.position() {
+                    eprintln!("{}", text.render(&format!("error: {}", error), position, color));
+                    std::process::exit(1);
+                }
+            }
+            return Err(e);
+        }
+    };
+
+    for warning in optimizer.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+
+    // The command line takes precedence over the program's own directives
+    let memory_size = cli
+        .memory_size
+        .or(program.directives().memory_size)
+        .unwrap_or(InterpreterConfig::default().memory_size);
 
     // Create interpreter configuration
-    let mut config = InterpreterConfig::default();
-    config.memory_size = cli.memory_size;
-    config.debug = cli.debug;
-    config.optimize = !cli.no_optimize;
+    let config = InterpreterConfig::builder()
+        .memory_size(memory_size)
+        .tape(cli.tape)
+        .debug(cli.debug)
+        .optimize(opt_level != OptLevel::None)
+        .output_mode(cli.output_mode)
+        .eof_behavior(cli.eof_value.map_or(EofBehavior::Error, EofBehavior::Value))
+        .initial_cell_value(cli.fill)
+        .debug_window(cli.debug_window)
+        .signed(cli.signed)
+        .cell_modulus(cli.modulus)
+        // Prompts would only clutter piped or file input
+        .interactive_input(cli.interactive_input && cli.input.is_none() && io::stdin().is_terminal())
+        .max_loop_iterations(cli.max_loop_iterations)
+        .timeout(cli.timeout)
+        .pad_output(cli.pad_output)
+        .max_output_bytes(cli.max_output)
+        .wide_io(cli.wide_io)
+        .allow_io(!cli.no_io)
+        .output_newline(if cli.crlf { NewlineMode::CrLf } else { NewlineMode::Raw })
+        .max_memory_bytes(cli.max_memory_bytes)
+        .input_buffer_size(cli.input_buffer_size)
+        .build()?;
+
+    let instructions = program.instructions();
+
+    if cli.analyze {
+        print_analysis(&program);
+        return Ok(());
+    }
+
+    if cli.explain {
+        for (index, (instruction, position)) in program.iter_with_positions().enumerate() {
+            if let Some(explanation) = instruction.explain() {
+                let location = if bytecode { format!("instruction {}", index) } else { format!("loop at {}", position) };
+                println!("{} → {}", location, explanation);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(target) = cli.emit {
+        match target {
+            EmitTarget::C => print!("{}", transpiler::to_c(instructions, program.positions(), &config)),
+            EmitTarget::Bf => println!("{}", transpiler::instructions_to_source(instructions)),
+            EmitTarget::Dot => print!("{}", transpiler::to_dot(instructions)),
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &cli.compile {
+        std::fs::write(path, bytecode::serialize(instructions))
+            .with_context(|| format!("Failed to write bytecode '{}'", path.display()))?;
+        return Ok(());
+    }
+
+    // Program input comes from --input when given, otherwise stdin
+    let input: Box<dyn Read> = match &cli.input {
+        Some(path) => {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open input file '{}'", path.display()))?;
+            Box::new(file)
+        }
+        None => Box::new(io::stdin()),
+    };
+
+    // With --verify, output is captured for comparison instead of printed
+    let expected = match &cli.verify {
+        Some(path) => Some(
+            std::fs::read(path)
+                .with_context(|| format!("Failed to read expected output '{}'", path.display()))?,
+        ),
+        None => None,
+    };
+    let mut captured = Vec::new();
+    let output: Box<dyn Write + '_> = match (&expected, &cli.output) {
+        (Some(_), _) => Box::new(&mut captured),
+        (None, Some(path)) => {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create output file '{}'", path.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        (None, None) => Box::new(io::stdout()),
+    };
+    let output_format = if cli.escape_output { OutputFormat::Escaped } else { cli.output_format };
+    let output: Box<dyn Write + '_> = match output_format {
+        OutputFormat::Raw => output,
+        OutputFormat::Hex => Box::new(HexDump::new(output)),
+        OutputFormat::Escaped => Box::new(EscapeOutput::new(output)),
+    };
+
+    let loaded_memory = match &cli.load_memory {
+        Some(path) => Some(
+            std::fs::read(path).with_context(|| format!("Failed to read memory file '{}'", path.display()))?,
+        ),
+        None => None,
+    };
+
+    #[cfg(feature = "bignum")]
+    if cli.bignum {
+        let mut interpreter = BignumInterpreter::with_io(instructions.to_vec(), config, input, output);
+        if let Some(cells) = &loaded_memory {
+            interpreter.load_memory(cells);
+        }
+        if let Err(e) = interpreter.run() {
+            let mut message = format!("Error during execution: {}", e);
+            if let Some(position) = program.position(interpreter.instruction_pointer()).filter(|_| !bytecode) {
+                message = text.render(&message, position, color);
+            }
+            eprintln!("{}", message);
+            drop(interpreter);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     // Create and run interpreter
-    let mut interpreter = Interpreter::new(instructions.clone(), config);
+    let mut interpreter = Interpreter::with_io(instructions.to_vec(), config, input, output);
+
+    if let Some(path) = &cli.second_input {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open input file '{}'", path.display()))?;
+        interpreter.add_input(Box::new(BufReader::new(file)));
+    }
+
+    if let Some(cells) = &loaded_memory {
+        interpreter.load_memory(cells);
+    }
+
+    if let Some(path) = &cli.trace {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create trace file '{}'", path.display()))?;
+        interpreter.set_trace(Box::new(BufWriter::new(file)))?;
+    }
+
+    if let Some(path) = &cli.replay {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open replay trace '{}'", path.display()))?;
+        interpreter.set_replay(Box::new(BufReader::new(file)))?;
+    }
+
+    if cli.profile_output.is_some() {
+        interpreter.enable_profile();
+    }
+
+    if cli.profile_time {
+        interpreter.enable_timing();
+    }
+
+    if cli.stats {
+        interpreter.enable_touch_tracking();
+    }
+
+    // Ctrl-C stops the run cleanly so statistics can still be shown
+    #[cfg(feature = "ctrlc")]
+    {
+        let flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let handler_flag = flag.clone();
+        ctrlc::set_handler(move || handler_flag.store(true, std::sync::atomic::Ordering::Relaxed))
+            .context("Failed to install the Ctrl-C handler")?;
+        interpreter.set_interrupt(flag);
+    }
 
     if cli.debug {
-        eprintln!("Starting execution of '{}'", cli.file.display());
-        eprintln!("Memory size: {}", cli.memory_size);
+        eprintln!("Starting execution of '{}'", source);
+        eprintln!("Memory size: {}", memory_size);
         eprintln!("Instructions: {}", instructions.len());
-        eprintln!("Optimizations: {}", !cli.no_optimize);
+        eprintln!("Optimization level: {:?}", opt_level);
         eprintln!("---");
     }
 
     // Execute the program
     let result = interpreter.run();
 
+    // The profile is written even if execution failed part way
+    if let (Some(path), Some(counts)) = (&cli.profile_output, interpreter.profile()) {
+        let folded = profile::folded_stacks(instructions, program.positions(), counts);
+        std::fs::write(path, folded)
+            .with_context(|| format!("Failed to write profile '{}'", path.display()))?;
+    }
+
+    if let Some(timing) = interpreter.timing() {
+        eprintln!("\n=== Hottest Instructions by Time ===");
+        eprint!("{}", profile::hottest_by_time(instructions, program.positions(), &timing, 10));
+    }
+
+    // So is the snapshot, which is most useful for post-mortems
+    if let (Some(path), Some(cells)) = (&cli.memory_snapshot, interpreter.tape().as_slice()) {
+        std::fs::write(path, cells)
+            .with_context(|| format!("Failed to write memory snapshot '{}'", path.display()))?;
+    }
+
     // Handle execution result
     match result {
         Ok(()) => {
@@ -103,45 +591,179 @@ fn run_brainfuck_program(cli: &Cli) -> Result<()> {
             }
 
             if cli.stats {
-                print_statistics(&interpreter, &instructions);
+                print_statistics(&interpreter, &optimizer, instructions);
+            }
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(BrainfuckError::Interrupted { .. })) => {
+            eprintln!("\n{}", e);
+            if cli.stats {
+                print_statistics(&interpreter, &optimizer, instructions);
             }
+            // Write out whatever the output writers still hold
+            drop(interpreter);
+            // The conventional status for a program stopped by SIGINT
+            std::process::exit(130);
         }
         Err(e) => {
-            eprintln!("Error during execution: {}", e);
+            let location = program
+                .position(interpreter.instruction_pointer())
+                .filter(|_| !bytecode)
+                .map(|position| (&text, position, color));
+            eprintln!("{}", runtime_diagnostic(&interpreter, &e, cli.debug.then_some(cli.debug_window), location));
+            drop(interpreter);
             std::process::exit(1);
         }
     }
 
+    drop(interpreter);
+    if let (Some(path), Some(expected)) = (&cli.verify, expected) {
+        match output_mismatch(&expected, &captured) {
+            Some(report) => {
+                eprintln!("Output does not match '{}'\n{}", path.display(), report);
+                std::process::exit(1);
+            }
+            None => eprintln!("Output matches '{}'", path.display()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Write each token as `line:column kind`, one per line
+fn dump_tokens(tokens: impl Iterator<Item = Result<Token>>, out: &mut impl Write) -> Result<()> {
+    for token in tokens {
+        let token = token?;
+        writeln!(out, "{} {:?}", token.position, token.kind)?;
+    }
     Ok(())
 }
 
-fn print_statistics(interpreter: &Interpreter, instructions: &[optimizer::Instruction]) {
+/// Every lexing and bracket error in the source, rendered with the line it
+/// is on where its position is known
+fn check_source(text: &SourceText, tokens: impl Iterator<Item = Result<Token>>, color: bool) -> Vec<String> {
+    lexer::validate(tokens)
+        .iter()
+        .map(|e| match e.downcast_ref::<BrainfuckError>().and_then(BrainfuckError::position) {
+            Some(position) => text.render(&format!("error: {}", e), position, color),
+            None => format!("error: {:#}", e),
+        })
+        .collect()
+}
+
+/// Parse a halt character, rejecting the standard commands and extensions
+fn parse_halt_char(value: &str) -> Result<char, String> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if TokenKind::from_char(c).is_some() || matches!(c, '#' | '$' | '}' | '~') => {
+            Err(format!("'{}' is already a Brainfuck command", c))
+        }
+        (Some(c), None) => Ok(c),
+        _ => Err("expected a single character".to_string()),
+    }
+}
+
+/// Parse a timeout in seconds, which may be fractional
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|_| format!("'{}' is not a number of seconds", value))?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("'{}' is not a valid timeout", value))
+}
+
+/// Describe where `actual` output first differs from `expected`, or `None`
+/// if they are identical
+fn output_mismatch(expected: &[u8], actual: &[u8]) -> Option<String> {
+    let offset = expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .or_else(|| (expected.len() != actual.len()).then(|| expected.len().min(actual.len())))?;
+
+    let describe = |bytes: &[u8]| match bytes.get(offset) {
+        Some(&byte) => format!("{:?} ({:#04x})", byte as char, byte),
+        None => "end of output".to_string(),
+    };
+    Some(format!(
+        "First difference at byte {}: expected {}, got {}\nExpected {} bytes, got {}",
+        offset,
+        describe(expected),
+        describe(actual),
+        expected.len(),
+        actual.len()
+    ))
+}
+
+/// Build the message shown when execution fails, pointing at the failing
+/// instruction's source when its location is known and including `window`
+/// cells either side of the pointer when debugging
+fn runtime_diagnostic<R: Read, W: Write>(
+    interpreter: &Interpreter<R, W>,
+    error: &anyhow::Error,
+    window: Option<usize>,
+    location: Option<(&SourceText, Position, bool)>,
+) -> String {
+    let mut message = format!("Error during execution: {}", error);
+    if let Some((text, position, color)) = location {
+        message = text.render(&message, position, color);
+    }
+    if let Some(window) = window {
+        message.push_str(&format!(
+            "\nMemory around pointer {}: {}",
+            interpreter.pointer(),
+            interpreter.memory_window(window)
+        ));
+    }
+    message
+}
+
+fn print_statistics<R: Read, W: Write>(
+    interpreter: &Interpreter<R, W>,
+    optimizer: &Optimizer,
+    instructions: &[optimizer::Instruction],
+) {
     eprintln!("\n=== Program Statistics ===");
+    eprintln!("Source tokens: {}", optimizer.token_count());
     eprintln!("Total instructions: {}", instructions.len());
-    eprintln!("Memory cells used: {}", interpreter.memory_state().len());
+    eprintln!("Tokens per instruction: {:.2}", optimizer.compression_ratio());
+    eprintln!("Memory cells used: {}", interpreter.tape().allocated());
     eprintln!("Final pointer position: {}", interpreter.pointer());
+    eprintln!(
+        "Peak pointer position: {} (--memory-size {} would be enough)",
+        interpreter.max_pointer(),
+        interpreter.max_pointer() + 1
+    );
+    if let Some(touched) = interpreter.touched_cells() {
+        eprintln!("Unique cells touched: {}", touched);
+    }
     eprintln!("Final instruction pointer: {}", interpreter.instruction_pointer());
 
+    print_instruction_breakdown(instructions);
+}
+
+fn print_analysis(program: &Program) {
+    let info = program.info();
+    let io = match (info.does_io, info.always_does_io) {
+        (false, _) => "none",
+        (true, false) => "possible",
+        (true, true) => "always",
+    };
+
+    eprintln!("=== Program Analysis ===");
+    eprintln!("Total instructions: {}", info.instruction_count);
+    eprintln!("Loops: {}", info.loop_count);
+    eprintln!("Maximum loop nesting depth: {}", info.max_nesting);
+    eprintln!("Performs I/O: {}", io);
+
+    print_instruction_breakdown(program.instructions());
+}
+
+fn print_instruction_breakdown(instructions: &[optimizer::Instruction]) {
     // Count instruction types
-    let mut counts = std::collections::HashMap::new();
+    let mut counts = std::collections::BTreeMap::new();
     for instruction in instructions {
-        let count = counts.entry(std::mem::discriminant(instruction)).or_insert(0);
-        *count += 1;
+        *counts.entry(instruction.name()).or_insert(0) += 1;
     }
 
     eprintln!("\nInstruction breakdown:");
-    for (discriminant, count) in counts {
-        let name = match discriminant {
-            _ if std::mem::discriminant(&optimizer::Instruction::MoveRight(0)) == discriminant => "MoveRight",
-            _ if std::mem::discriminant(&optimizer::Instruction::MoveLeft(0)) == discriminant => "MoveLeft",
-            _ if std::mem::discriminant(&optimizer::Instruction::Increment(0)) == discriminant => "Increment",
-            _ if std::mem::discriminant(&optimizer::Instruction::Decrement(0)) == discriminant => "Decrement",
-            _ if std::mem::discriminant(&optimizer::Instruction::Output(0)) == discriminant => "Output",
-            _ if std::mem::discriminant(&optimizer::Instruction::Input(0)) == discriminant => "Input",
-            _ if std::mem::discriminant(&optimizer::Instruction::JumpForward(0)) == discriminant => "JumpForward",
-            _ if std::mem::discriminant(&optimizer::Instruction::JumpBackward(0)) == discriminant => "JumpBackward",
-            _ => "Unknown",
-        };
+    for (name, count) in counts {
         eprintln!("  {}: {}", name, count);
     }
 }
@@ -149,7 +771,7 @@ fn print_statistics(interpreter: &Interpreter, instructions: &[optimizer::Instru
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use brainfuck_interpreter::lexer::Lexer;
 
     #[test]
     fn test_cli_parsing() {
@@ -157,9 +779,14 @@ mod tests {
         let cli = Cli::try_parse_from(args).unwrap();
         assert_eq!(cli.file, PathBuf::from("test.bf"));
         assert!(!cli.debug);
-        assert_eq!(cli.memory_size, 30000);
+        assert_eq!(cli.memory_size, None);
         assert!(!cli.no_optimize);
+        assert_eq!(cli.opt_level, OptLevel::Full);
         assert!(!cli.stats);
+        assert_eq!(cli.fill, 0);
+        assert_eq!(cli.eof_value, None);
+        assert_eq!(cli.output_format, OutputFormat::Raw);
+        assert_eq!(cli.input_buffer_size, 8192);
     }
 
     #[test]
@@ -175,8 +802,91 @@ mod tests {
         let cli = Cli::try_parse_from(args).unwrap();
         assert_eq!(cli.file, PathBuf::from("test.bf"));
         assert!(cli.debug);
-        assert_eq!(cli.memory_size, 50000);
+        assert_eq!(cli.memory_size, Some(50000));
         assert!(cli.no_optimize);
         assert!(cli.stats);
     }
+
+    #[test]
+    fn test_parse_halt_char() {
+        assert_eq!(parse_halt_char("@"), Ok('@'));
+        for value in ["+", "#", "$", "}", "~", "ab", ""] {
+            assert!(parse_halt_char(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_timeout("2"), Ok(Duration::from_secs(2)));
+        for value in ["-1", "soon", "inf"] {
+            assert!(parse_timeout(value).is_err(), "{:?}", value);
+        }
+    }
+
+    fn failed_interpreter(program: &str) -> (Interpreter, anyhow::Error) {
+        let lexer = Lexer::new(std::io::Cursor::new(program.as_bytes()));
+        let instructions = Optimizer::new().optimize(lexer).unwrap();
+        let mut interpreter = Interpreter::new(instructions, InterpreterConfig::default());
+        let error = interpreter.run().unwrap_err();
+        (interpreter, error)
+    }
+
+    #[test]
+    fn test_runtime_diagnostic_dumps_memory_in_debug() {
+        let (interpreter, error) = failed_interpreter("+++>++<<<");
+        let message = runtime_diagnostic(&interpreter, &error, Some(8), None);
+        assert!(message.contains("out of bounds"));
+        assert!(message.contains("Memory around pointer 1: 0..10: 3 [2] 0"));
+    }
+
+    #[test]
+    fn test_runtime_diagnostic_points_at_instruction() {
+        let (interpreter, error) = failed_interpreter("+++>++<<<");
+        let text = SourceText::new("prog.bf", "+++>++<<<");
+        let message = runtime_diagnostic(&interpreter, &error, None, Some((&text, Position::new(1, 7), false)));
+        assert!(message.starts_with("Error during execution: Memory access out of bounds"));
+        assert!(message.ends_with("1 | +++>++<<<\n  |       ^"));
+    }
+
+    #[test]
+    fn test_runtime_diagnostic_without_debug() {
+        let (interpreter, error) = failed_interpreter("+++>++<<<");
+        let message = runtime_diagnostic(&interpreter, &error, None, None);
+        assert!(!message.contains("Memory around pointer"));
+    }
+
+    #[test]
+    fn test_output_mismatch() {
+        assert_eq!(output_mismatch(b"Hello", b"Hello"), None);
+        assert_eq!(
+            output_mismatch(b"Hello", b"Help!").unwrap(),
+            "First difference at byte 3: expected 'l' (0x6c), got 'p' (0x70)\nExpected 5 bytes, got 5"
+        );
+        assert_eq!(
+            output_mismatch(b"Hi\n", b"Hi").unwrap(),
+            "First difference at byte 2: expected '\\n' (0x0a), got end of output\nExpected 3 bytes, got 2"
+        );
+    }
+
+    #[test]
+    fn test_dump_tokens() {
+        let mut out = Vec::new();
+        dump_tokens(Lexer::new("+\n>".as_bytes()), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1:1 Increment\n2:1 MoveRight\n");
+    }
+
+    #[test]
+    fn test_check_source() {
+        let text = SourceText::new("prog.bf", "+[>[-]<]");
+        assert!(check_source(&text, Lexer::new(text.text().as_bytes()), false).is_empty());
+
+        let text = SourceText::new("prog.bf", "[[]");
+        let errors = check_source(&text, Lexer::new(text.text().as_bytes()), false);
+        assert_eq!(errors, ["error: Unmatched bracket at position 1:1
+ --> prog.bf:1:1
+  |
+1 | [[]
+  | ^"]);
+    }
 }
@@ -1,6 +1,9 @@
 use crate::error::{BrainfuckError, Position};
 use crate::lexer::{Token, TokenKind};
+use crate::program::Program;
 use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
 
 /// Optimized instruction that can be executed by the interpreter
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -9,9 +12,9 @@ pub enum Instruction {
     MoveRight(usize),
     /// Move pointer left by N positions
     MoveLeft(usize),
-    /// Increment current cell by N
+    /// Increment current cell by N, a run of `+` modulo 256
     Increment(u8),
-    /// Decrement current cell by N
+    /// Decrement current cell by N, a run of `-` modulo 256
     Decrement(u8),
     /// Output current cell N times
     Output(usize),
@@ -21,82 +24,392 @@ pub enum Instruction {
     JumpForward(usize),
     /// Jump backward to instruction at index if current cell is not 0
     JumpBackward(usize),
+    /// Set current cell to 0 (`[-]`)
+    SetZero,
+    /// Set current cell to a value, a clear followed by a run (`[-]+++`)
+    SetValue(u8),
+    /// Add current cell to the cell at each offset, then set it to 0 (`[->+>+<<]`)
+    Copy { targets: Vec<isize> },
+    /// Add current cell times a factor to the cell at each offset, then set it
+    /// to 0 (`[->++>+++<<]`)
+    MultiplyAdd { targets: Vec<(isize, u8)> },
+    /// Add a value to the cell at an offset from the pointer
+    AddAtOffset { offset: isize, value: u8 },
+    /// Set the cell at an offset from the pointer to a value
+    SetAtOffset { offset: isize, value: u8 },
+    /// Output bytes that were computed at optimize time
+    PrintConst(Vec<u8>),
+    /// While the current cell is not 0, add a value to the cell at each offset
+    /// and then move the pointer by `stride` (`[>+>]`)
+    ScanAdd { stride: isize, adds: Vec<(isize, u8)> },
+    /// Move the pointer right by N positions until it reaches a zero cell
+    /// (`[>>]`)
+    ScanRight(usize),
+    /// Move the pointer left by N positions until it reaches a zero cell
+    /// (`[<<]`)
+    ScanLeft(usize),
+    /// Read a byte, then write and read again until a zero byte is read
+    /// (`,[.,]`)
+    EchoStream,
+    /// Print the tape around the pointer for debugging (`#`)
+    DebugDump,
+    /// Stop the program
+    Halt,
+    /// Copy the current cell into the register (`$`)
+    StoreRegister,
+    /// Copy the register into the current cell (`}`)
+    RestoreRegister,
+    /// Make `,` read from the next input source (`~`)
+    SwitchInput,
+    /// Run the handler registered for a custom command character
+    Extension(char),
+    /// Do nothing
+    ///
+    /// Passes replace what they remove with `Nop` instead of shifting every
+    /// jump target, and the final pass strips them all, so optimized
+    /// instructions never contain one.
+    Nop,
 }
 
 impl Instruction {
+    /// Name of the variant, for statistics and reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MoveRight(_) => "MoveRight",
+            Self::MoveLeft(_) => "MoveLeft",
+            Self::Increment(_) => "Increment",
+            Self::Decrement(_) => "Decrement",
+            Self::Output(_) => "Output",
+            Self::Input(_) => "Input",
+            Self::JumpForward(_) => "JumpForward",
+            Self::JumpBackward(_) => "JumpBackward",
+            Self::SetZero => "SetZero",
+            Self::SetValue(_) => "SetValue",
+            Self::Copy { .. } => "Copy",
+            Self::MultiplyAdd { .. } => "MultiplyAdd",
+            Self::AddAtOffset { .. } => "AddAtOffset",
+            Self::SetAtOffset { .. } => "SetAtOffset",
+            Self::PrintConst(_) => "PrintConst",
+            Self::ScanAdd { .. } => "ScanAdd",
+            Self::ScanRight(_) => "ScanRight",
+            Self::ScanLeft(_) => "ScanLeft",
+            Self::EchoStream => "EchoStream",
+            Self::DebugDump => "DebugDump",
+            Self::Halt => "Halt",
+            Self::StoreRegister => "StoreRegister",
+            Self::RestoreRegister => "RestoreRegister",
+            Self::SwitchInput => "SwitchInput",
+            Self::Extension(_) => "Extension",
+            Self::Nop => "Nop",
+        }
+    }
+
+    /// Describe what a loop the optimizer recognised does, or `None` for
+    /// anything that is not a recognised loop
+    ///
+    /// Cells are named by their offset from the pointer, so `cell[0]` is the
+    /// current cell.
+    pub fn explain(&self) -> Option<String> {
+        let adds = |targets: &[(isize, u8)]| -> String {
+            targets
+                .iter()
+                .map(|&(target, factor)| match factor {
+                    1 => format!("add cell[0] to cell[{}]", target),
+                    _ => format!("add {}× cell[0] to cell[{}]", factor, target),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let explanation = match self {
+            Self::SetZero => "clear cell".to_string(),
+            Self::SetValue(value) => format!("set cell to {}", value),
+            Self::Copy { targets } => {
+                let targets: Vec<_> = targets.iter().map(|&target| (target, 1)).collect();
+                format!("{}, then clear cell[0]", adds(&targets))
+            }
+            Self::MultiplyAdd { targets } => format!("{}, then clear cell[0]", adds(targets)),
+            Self::ScanAdd { stride, adds } => {
+                let added: Vec<_> = adds.iter().map(|&(offset, value)| format!("add {} to cell[{}]", value, offset)).collect();
+                let direction = if *stride > 0 { "right" } else { "left" };
+                format!("{} and move {} by {} until a zero cell", added.join(", "), direction, stride.unsigned_abs())
+            }
+            Self::ScanRight(stride) => format!("move right by {} until a zero cell", stride),
+            Self::ScanLeft(stride) => format!("move left by {} until a zero cell", stride),
+            Self::EchoStream => "copy input to output until a zero byte".to_string(),
+            _ => return None,
+        };
+        Some(explanation)
+    }
+
     /// Get the number of operations this instruction represents
     pub fn operation_count(&self) -> usize {
         match self {
             Self::MoveRight(n) | Self::MoveLeft(n) | Self::Output(n) | Self::Input(n) => *n,
             Self::Increment(n) | Self::Decrement(n) => *n as usize,
             Self::JumpForward(_) | Self::JumpBackward(_) => 1,
+            Self::SetZero | Self::SetValue(_) | Self::Copy { .. } | Self::MultiplyAdd { .. } | Self::ScanAdd { .. } => 1,
+            Self::ScanRight(_) | Self::ScanLeft(_) => 1,
+            Self::AddAtOffset { .. } | Self::SetAtOffset { .. } | Self::DebugDump | Self::Halt => 1,
+            Self::StoreRegister | Self::RestoreRegister | Self::SwitchInput => 1,
+            Self::EchoStream | Self::Extension(_) => 1,
+            Self::PrintConst(bytes) => bytes.len(),
+            Self::Nop => 0,
+        }
+    }
+}
+
+/// A non-fatal diagnostic produced while optimizing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// Source position the warning refers to
+    pub position: Position,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.position)
+    }
+}
+
+/// How much the optimizer does, with each level doing everything the one
+/// before it does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "std", derive(clap::ValueEnum))]
+pub enum OptLevel {
+    /// Every token becomes its own instruction
+    None,
+    /// Fold runs of the same command, but keep every loop as jumps
+    Fold,
+    /// Also recognise loop patterns such as `[-]`, and fold what is known at
+    /// optimize time
+    #[default]
+    Full,
+}
+
+/// Configuration for the optimizer
+#[derive(Debug, Clone)]
+pub struct OptimizerConfig {
+    /// How much to optimize (default: everything)
+    pub opt_level: OptLevel,
+    /// Value every cell holds when the program starts, if known
+    ///
+    /// Leave this as `None` when memory may be seeded before running.
+    pub initial_cell_value: Option<u8>,
+    /// Drop a `]` with no matching `[` and record a warning instead of failing
+    pub lenient: bool,
+    /// Record a warning for each `-` that wraps a cell known to be below the
+    /// amount subtracted, such as `-` at the start of the program
+    pub warn_wrapping: bool,
+    /// Record a warning for each loop whose body leaves the pointer somewhere
+    /// other than where it started, such as `[>+]`
+    pub warn_pointer_drift: bool,
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::Full,
+            initial_cell_value: None,
+            lenient: false,
+            warn_wrapping: false,
+            warn_pointer_drift: false,
         }
     }
 }
 
 /// An optimizer that combines consecutive operations for better performance
 pub struct Optimizer {
+    config: OptimizerConfig,
     instructions: Vec<Instruction>,
+    /// Source position of the first token folded into each instruction
+    positions: Vec<Position>,
     jump_stack: Vec<usize>,
+    /// Deepest `jump_stack` seen while optimizing the last program
+    max_nesting_depth: usize,
+    /// Number of tokens read while optimizing the last program
+    token_count: usize,
+    warnings: Vec<Warning>,
+    /// Cells known after the settled instructions, before dead loops are
+    /// dropped, for linting
+    lint_cells: KnownCells,
+    /// Cells known after the settled instructions
+    known_cells: KnownCells,
 }
 
 impl Optimizer {
     /// Create a new optimizer
     pub fn new() -> Self {
+        Self::with_config(OptimizerConfig::default())
+    }
+
+    /// Create a new optimizer with the given configuration
+    pub fn with_config(config: OptimizerConfig) -> Self {
         Self {
             instructions: Vec::new(),
+            positions: Vec::new(),
             jump_stack: Vec::new(),
+            max_nesting_depth: 0,
+            token_count: 0,
+            warnings: Vec::new(),
+            lint_cells: KnownCells::new(config.initial_cell_value),
+            known_cells: KnownCells::new(config.initial_cell_value),
+            config,
         }
     }
 
+    /// Source positions of the last optimized program, one per instruction
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    /// Maximum loop nesting depth of the last optimized program
+    ///
+    /// Loops the optimizer replaced with a single instruction still count.
+    pub fn max_nesting_depth(&self) -> usize {
+        self.max_nesting_depth
+    }
+
+    /// Number of tokens in the last optimized program
+    pub fn token_count(&self) -> usize {
+        self.token_count
+    }
+
+    /// Number of instructions the last optimized program became
+    pub fn instruction_count(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Tokens per instruction for the last optimized program, or 0 if it
+    /// produced no instructions
+    pub fn compression_ratio(&self) -> f64 {
+        if self.instructions.is_empty() {
+            0.0
+        } else {
+            self.token_count as f64 / self.instructions.len() as f64
+        }
+    }
+
+    /// Warnings collected while optimizing the last program
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
     /// Optimize a stream of tokens into instructions
     pub fn optimize(&mut self, tokens: impl Iterator<Item = Result<Token>>) -> Result<Vec<Instruction>> {
-        self.instructions.clear();
-        self.jump_stack.clear();
+        self.reset();
 
         for token_result in tokens {
             let token = token_result?;
+            self.token_count += 1;
             self.process_token(token)?;
         }
+        self.check_balanced()?;
 
-        // Check for unmatched brackets
-        if !self.jump_stack.is_empty() {
-            let position = self.instructions
-                .iter()
-                .filter_map(|inst| {
-                    if let Instruction::JumpForward(_) = inst {
-                        Some(Position::new(1, 1)) // We don't track positions in optimized instructions
-                    } else {
-                        None
-                    }
-                })
-                .next()
-                .unwrap_or(Position::default());
+        let instructions = std::mem::take(&mut self.instructions);
+        let positions = std::mem::take(&mut self.positions);
+        let (mut kept, mut kept_positions) = (Vec::new(), Vec::new());
+        self.settle(instructions, positions, &mut kept, &mut kept_positions, 0);
+        (self.instructions, self.positions) = (kept, kept_positions);
 
-            return Err(BrainfuckError::UnmatchedBracket { position }.into());
+        Ok(self.instructions.clone())
+    }
+
+    /// Optimize a stream of tokens lazily, yielding each instruction once no
+    /// later token can change it
+    ///
+    /// The instructions are the same as [`optimize`](Self::optimize) returns,
+    /// but only a loop being read and the instructions either side of it are
+    /// held at once, which suits consumers that write out instructions as they go.
+    /// Token count, nesting depth and warnings are recorded as usual, but
+    /// [`positions`](Self::positions) and the instruction statistics are not.
+    pub fn optimize_streaming<I>(&mut self, tokens: I) -> OptimizeStream<'_, I>
+    where
+        I: Iterator<Item = Result<Token>>,
+    {
+        self.reset();
+        OptimizeStream {
+            optimizer: self,
+            tokens,
+            ready: Vec::new().into_iter(),
+            held: Vec::new(),
+            held_positions: Vec::new(),
+            yielded: 0,
+            done: false,
         }
+    }
 
-        Ok(self.instructions.clone())
+    /// Forget the last program before optimizing another
+    fn reset(&mut self) {
+        self.instructions.clear();
+        self.positions.clear();
+        self.jump_stack.clear();
+        self.max_nesting_depth = 0;
+        self.token_count = 0;
+        self.warnings.clear();
+        self.lint_cells = KnownCells::new(self.config.initial_cell_value);
+        self.known_cells = KnownCells::new(self.config.initial_cell_value);
+    }
+
+    /// Fail if a `[` was left open, reporting the innermost one
+    fn check_balanced(&self) -> Result<()> {
+        match self.jump_stack.last() {
+            Some(&start_index) => {
+                let position = self.positions[start_index];
+                Err(BrainfuckError::UnmatchedBracket { position }.into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Optimize a stream of tokens into a [`Program`] that keeps the source
+    /// position of every instruction
+    pub fn optimize_program(&mut self, tokens: impl Iterator<Item = Result<Token>>) -> Result<Program> {
+        let instructions = self.optimize(tokens)?;
+        Ok(Program::new(instructions, self.positions.clone()))
     }
 
     /// Process a single token and add optimized instructions
     fn process_token(&mut self, token: Token) -> Result<()> {
+        let position = token.position;
         match token.kind {
-            TokenKind::MoveRight => self.optimize_move(1, true),
-            TokenKind::MoveLeft => self.optimize_move(1, false),
-            TokenKind::Increment => self.optimize_arithmetic(1, true),
-            TokenKind::Decrement => self.optimize_arithmetic(1, false),
-            TokenKind::Output => self.optimize_io(1, true),
-            TokenKind::Input => self.optimize_io(1, false),
-            TokenKind::LoopStart => self.handle_loop_start(),
-            TokenKind::LoopEnd => self.handle_loop_end(token.position)?,
+            TokenKind::MoveRight => self.optimize_move(1, true, position),
+            TokenKind::MoveLeft => self.optimize_move(1, false, position),
+            TokenKind::Increment => self.optimize_arithmetic(1, true, position),
+            TokenKind::Decrement => self.optimize_arithmetic(1, false, position),
+            TokenKind::Output => self.optimize_io(1, true, position),
+            TokenKind::Input => self.optimize_io(1, false, position),
+            TokenKind::LoopStart => self.handle_loop_start(position),
+            TokenKind::LoopEnd => self.handle_loop_end(position)?,
+            TokenKind::DebugDump => self.push(Instruction::DebugDump, position),
+            TokenKind::Halt => self.push(Instruction::Halt, position),
+            TokenKind::StoreRegister => self.push(Instruction::StoreRegister, position),
+            TokenKind::RestoreRegister => self.push(Instruction::RestoreRegister, position),
+            TokenKind::SwitchInput => self.push(Instruction::SwitchInput, position),
+            TokenKind::Extension(c) => self.push(Instruction::Extension(c), position),
         }
         Ok(())
     }
 
+    /// The last instruction, if folding into it is enabled
+    fn foldable_last(&mut self) -> Option<&mut Instruction> {
+        if self.config.opt_level != OptLevel::None {
+            self.instructions.last_mut()
+        } else {
+            None
+        }
+    }
+
+    /// Append an instruction along with the position of its first token
+    fn push(&mut self, instruction: Instruction, position: Position) {
+        self.instructions.push(instruction);
+        self.positions.push(position);
+    }
+
     /// Optimize consecutive move operations
-    fn optimize_move(&mut self, count: usize, right: bool) {
-        if let Some(last_inst) = self.instructions.last_mut() {
+    fn optimize_move(&mut self, count: usize, right: bool, position: Position) {
+        if let Some(last_inst) = self.foldable_last() {
             match (last_inst, right) {
                 (Instruction::MoveRight(n), true) => *n += count,
                 (Instruction::MoveLeft(n), false) => *n += count,
@@ -106,7 +419,7 @@ impl Optimizer {
                     } else {
                         Instruction::MoveLeft(count)
                     };
-                    self.instructions.push(inst);
+                    self.push(inst, position);
                 }
             }
         } else {
@@ -115,13 +428,17 @@ impl Optimizer {
             } else {
                 Instruction::MoveLeft(count)
             };
-            self.instructions.push(inst);
+            self.push(inst, position);
         }
     }
 
     /// Optimize consecutive arithmetic operations
-    fn optimize_arithmetic(&mut self, count: u8, increment: bool) {
-        if let Some(last_inst) = self.instructions.last_mut() {
+    ///
+    /// A run folds into a single instruction whose count is the run length
+    /// modulo 256, so 300 `+` become `Increment(44)`. Cells wrap at 256, so
+    /// this is exactly what running the commands one at a time does.
+    fn optimize_arithmetic(&mut self, count: u8, increment: bool, position: Position) {
+        if let Some(last_inst) = self.foldable_last() {
             match (last_inst, increment) {
                 (Instruction::Increment(n), true) => *n = n.wrapping_add(count),
                 (Instruction::Decrement(n), false) => *n = n.wrapping_add(count),
@@ -131,7 +448,7 @@ impl Optimizer {
                     } else {
                         Instruction::Decrement(count)
                     };
-                    self.instructions.push(inst);
+                    self.push(inst, position);
                 }
             }
         } else {
@@ -140,13 +457,13 @@ impl Optimizer {
             } else {
                 Instruction::Decrement(count)
             };
-            self.instructions.push(inst);
+            self.push(inst, position);
         }
     }
 
     /// Optimize consecutive I/O operations
-    fn optimize_io(&mut self, count: usize, output: bool) {
-        if let Some(last_inst) = self.instructions.last_mut() {
+    fn optimize_io(&mut self, count: usize, output: bool, position: Position) {
+        if let Some(last_inst) = self.foldable_last() {
             match (last_inst, output) {
                 (Instruction::Output(n), true) => *n += count,
                 (Instruction::Input(n), false) => *n += count,
@@ -156,7 +473,7 @@ impl Optimizer {
                     } else {
                         Instruction::Input(count)
                     };
-                    self.instructions.push(inst);
+                    self.push(inst, position);
                 }
             }
         } else {
@@ -165,26 +482,65 @@ impl Optimizer {
             } else {
                 Instruction::Input(count)
             };
-            self.instructions.push(inst);
+            self.push(inst, position);
         }
     }
 
     /// Handle the start of a loop
-    fn handle_loop_start(&mut self) {
+    fn handle_loop_start(&mut self, position: Position) {
         self.jump_stack.push(self.instructions.len());
-        self.instructions.push(Instruction::JumpForward(0)); // Placeholder
+        self.max_nesting_depth = self.max_nesting_depth.max(self.jump_stack.len());
+        self.push(Instruction::JumpForward(0), position); // Placeholder
     }
 
     /// Handle the end of a loop
     fn handle_loop_end(&mut self, position: Position) -> Result<()> {
         if let Some(start_index) = self.jump_stack.pop() {
+            // `[]` either does nothing or never terminates
+            if start_index + 1 == self.instructions.len() {
+                self.warnings.push(Warning {
+                    position: self.positions[start_index],
+                    message: "empty loop".to_string(),
+                });
+            }
+
+            if self.config.warn_pointer_drift {
+                let drift = self.loop_drift(start_index);
+                if drift != 0 {
+                    let direction = if drift > 0 { "right" } else { "left" };
+                    self.warnings.push(Warning {
+                        position: self.positions[start_index],
+                        message: format!("loop moves the pointer {} by {} each iteration", direction, drift.unsigned_abs()),
+                    });
+                }
+            }
+
+            let full = self.config.opt_level == OptLevel::Full;
+            if full && self.is_echo_loop(start_index) {
+                self.replace_echo_loop(start_index);
+                return Ok(());
+            }
+
+            if let Some(instruction) = self.analyze_loop(start_index).filter(|_| full) {
+                let loop_position = self.positions[start_index];
+                self.instructions.truncate(start_index);
+                self.positions.truncate(start_index);
+                self.push(instruction, loop_position);
+                return Ok(());
+            }
+
             // Update the forward jump to point to the current position
             if let Some(Instruction::JumpForward(_)) = self.instructions.get_mut(start_index) {
                 self.instructions[start_index] = Instruction::JumpForward(self.instructions.len());
             }
             
             // Add the backward jump
-            self.instructions.push(Instruction::JumpBackward(start_index));
+            self.push(Instruction::JumpBackward(start_index), position);
+        } else if self.config.lenient {
+            self.warnings.push(Warning {
+                position,
+                message: "unmatched ']' dropped".to_string(),
+            });
         } else {
             return Err(BrainfuckError::UnmatchedBracket { position }.into());
         }
@@ -192,6 +548,489 @@ impl Optimizer {
     }
 }
 
+impl Optimizer {
+    /// Net pointer movement of one pass through the body of the loop opened
+    /// at `start_index`
+    ///
+    /// Nested loops and scans move the pointer by an amount only known at run
+    /// time, so they are left to be checked on their own.
+    fn loop_drift(&self, start_index: usize) -> isize {
+        let mut drift = 0isize;
+        let mut index = start_index + 1;
+        while let Some(instruction) = self.instructions.get(index) {
+            match *instruction {
+                Instruction::MoveRight(n) => drift += n as isize,
+                Instruction::MoveLeft(n) => drift -= n as isize,
+                Instruction::JumpForward(end) => index = end,
+                _ => {}
+            }
+            index += 1;
+        }
+        drift
+    }
+
+    /// Recognise a loop that can run as a single instruction
+    ///
+    /// Only loops whose body is straight-line pointer moves and cell
+    /// arithmetic are considered; anything containing I/O or a nested loop
+    /// runs as plain jumps. Of those:
+    ///
+    /// - a body that never moves the pointer and changes the current cell by
+    ///   an odd amount always ends with the cell cleared, and becomes
+    ///   `SetZero`. An odd step shares no factor with 256, so it reaches zero
+    ///   from any value; an even one such as `[--]` never does from an odd
+    ///   value and is left as a loop. This assumes byte cells: under another
+    ///   [`cell_modulus`](crate::interpreter::InterpreterConfig::cell_modulus)
+    ///   a step must share no factor with the modulus instead, so those runs
+    ///   keep the naive loops
+    /// - a body that returns the pointer to where it started and decrements
+    ///   the current cell by exactly one runs as many times as the cell's
+    ///   starting value, and becomes `Copy` or `MultiplyAdd`, provided the
+    ///   pointer never strays past the cells it adds to
+    /// - a body that ends a fixed `stride` away from where it started and
+    ///   adds to at least one cell becomes `ScanAdd`, provided the pointer
+    ///   never strays outside the cells it adds to and the stride
+    /// - a body that only moves the pointer, like `[>>]`, becomes
+    ///   `ScanRight` or `ScanLeft`, provided it never moves past the stride
+    ///
+    /// Other balanced loops, such as `[-->+<]` or `[>><]`, are rejected.
+    fn analyze_loop(&self, start_index: usize) -> Option<Instruction> {
+        let mut offset: isize = 0;
+        // Furthest the pointer moves from the loop's start in each direction
+        let (mut lowest, mut highest) = (0, 0);
+        // Net change per iteration for each offset, in order of first use
+        let mut deltas: Vec<(isize, u8)> = Vec::new();
+
+        for instruction in &self.instructions[start_index + 1..] {
+            match instruction {
+                Instruction::MoveRight(n) => offset = offset.checked_add(isize::try_from(*n).ok()?)?,
+                Instruction::MoveLeft(n) => offset = offset.checked_sub(isize::try_from(*n).ok()?)?,
+                Instruction::Increment(n) => add_delta(&mut deltas, offset, *n),
+                Instruction::Decrement(n) => add_delta(&mut deltas, offset, n.wrapping_neg()),
+                _ => return None,
+            }
+            lowest = lowest.min(offset);
+            highest = highest.max(offset);
+        }
+
+        if offset != 0 {
+            deltas.retain(|&(_, delta)| delta != 0);
+            if deltas.is_empty() {
+                // Only the cells the scan stops on are checked, as the naive
+                // loop checks each cell it visits
+                return match (lowest, highest) {
+                    (0, highest) if highest == offset => Some(Instruction::ScanRight(offset.unsigned_abs())),
+                    (lowest, 0) if lowest == offset => Some(Instruction::ScanLeft(offset.unsigned_abs())),
+                    _ => None,
+                };
+            }
+
+            // Every cell the naive body visits must be checked against the
+            // tape bounds, so the extremes have to be an add or the stride
+            let touched = |extreme: isize| extreme == 0 || extreme == offset || deltas.iter().any(|&(target, _)| target == extreme);
+            if !touched(lowest) || !touched(highest) {
+                return None;
+            }
+
+            return Some(Instruction::ScanAdd {
+                stride: offset,
+                adds: deltas,
+            });
+        }
+
+        let counter = deltas.iter().position(|&(target, _)| target == 0)?;
+        let step = deltas.remove(counter).1;
+        deltas.retain(|&(_, delta)| delta != 0);
+
+        // As with a scan, the transfer only checks the cells it adds to
+        let touched = |extreme: isize| extreme == 0 || deltas.iter().any(|&(target, _)| target == extreme);
+
+        if deltas.is_empty() && step % 2 == 1 && lowest == 0 && highest == 0 {
+            Some(Instruction::SetZero)
+        } else if step != u8::MAX || deltas.is_empty() || !touched(lowest) || !touched(highest) {
+            None
+        } else if deltas.iter().all(|&(_, factor)| factor == 1) {
+            Some(Instruction::Copy {
+                targets: deltas.into_iter().map(|(target, _)| target).collect(),
+            })
+        } else {
+            Some(Instruction::MultiplyAdd { targets: deltas })
+        }
+    }
+}
+
+impl Optimizer {
+    /// Whether the loop starting at `start_index` is the `[.,]` of a cat
+    /// program, straight after a `,`
+    fn is_echo_loop(&self, start_index: usize) -> bool {
+        start_index > 0
+            && matches!(self.instructions[start_index - 1], Instruction::Input(_))
+            && self.instructions[start_index + 1..] == [Instruction::Output(1), Instruction::Input(1)]
+    }
+
+    /// Replace the last `,` before the loop at `start_index` and the loop
+    /// itself with `EchoStream`
+    fn replace_echo_loop(&mut self, start_index: usize) {
+        let input_index = start_index - 1;
+        let position = self.positions[input_index];
+        self.instructions.truncate(start_index);
+        self.positions.truncate(start_index);
+
+        match &mut self.instructions[input_index] {
+            Instruction::Input(count) if *count > 1 => *count -= 1,
+            _ => {
+                self.instructions.pop();
+                self.positions.pop();
+            }
+        }
+        self.push(Instruction::EchoStream, position);
+    }
+}
+
+impl Optimizer {
+    /// Run the passes that look across whole loops over `instructions`,
+    /// which hold only complete loops and follow everything settled before,
+    /// and append what is left to `kept`, with jumps renumbered so `kept`
+    /// starts at `base`
+    ///
+    /// Cell values are tracked from the start of the program through
+    /// straight-line code and forgotten at every loop boundary, except that a
+    /// loop always exits on a zero cell. With that:
+    ///
+    /// - a `Decrement` that takes a known cell below zero gets a warning when
+    ///   enabled, since it usually means a forgotten `+` or a misplaced
+    ///   pointer
+    /// - a loop whose cell is known to be zero when it is reached, such as a
+    ///   comment loop at the start of the program, is dropped since its body
+    ///   can never run; loops already replaced, like `SetZero`, are too
+    /// - `Output` of a known cell becomes `PrintConst`
+    ///
+    /// Runs that end up next to each other once a loop is dropped are merged,
+    /// as they would have been had the loop never been written, and a run
+    /// straight after a clear folds into it to store a constant. This is also
+    /// the final pass, so every `Nop` is stripped here.
+    fn settle(
+        &mut self,
+        instructions: Vec<Instruction>,
+        positions: Vec<Position>,
+        kept: &mut Vec<Instruction>,
+        kept_positions: &mut Vec<Position>,
+        base: usize,
+    ) {
+        if self.config.warn_wrapping {
+            for (instruction, &position) in instructions.iter().zip(&positions) {
+                if let Instruction::Decrement(n) = *instruction {
+                    if let Some(value) = self.lint_cells.get(0).filter(|&value| value < n) {
+                        self.warnings.push(Warning {
+                            position,
+                            message: format!("decrement wraps a cell holding {} around to {}", value, value.wrapping_sub(n)),
+                        });
+                    }
+                }
+                self.lint_cells.step(instruction);
+            }
+        }
+
+        // Index just past a dropped loop
+        let mut skip_to = 0;
+
+        for (index, (mut instruction, position)) in instructions.into_iter().zip(positions).enumerate() {
+            if index < skip_to {
+                continue;
+            }
+
+            // A run that wrapped all the way round does nothing
+            if self.config.opt_level == OptLevel::Full
+                && matches!(instruction, Instruction::Increment(0) | Instruction::Decrement(0))
+            {
+                instruction = Instruction::Nop;
+            }
+            // Dropping placeholders is safe since the jumps are renumbered
+            // from the loop structure below
+            if instruction == Instruction::Nop {
+                continue;
+            }
+
+            if self.config.opt_level == OptLevel::Full {
+                let known = &mut self.known_cells;
+                if known.get(0) == Some(0) {
+                    match instruction {
+                        // Nothing in a skipped loop runs, so what is known holds
+                        Instruction::JumpForward(end) => {
+                            skip_to = end + 1;
+                            continue;
+                        }
+                        Instruction::SetZero
+                        | Instruction::Copy { .. }
+                        | Instruction::MultiplyAdd { .. }
+                        | Instruction::ScanAdd { .. }
+                        | Instruction::ScanRight(_)
+                        | Instruction::ScanLeft(_) => continue,
+                        _ => {}
+                    }
+                }
+                if let (Instruction::Output(n), Some(value)) = (&instruction, known.get(0)) {
+                    instruction = Instruction::PrintConst(vec![value; *n]);
+                }
+                known.step(&instruction);
+                if kept.last_mut().is_some_and(|last| merge_run(last, &instruction)) {
+                    continue;
+                }
+            }
+
+            kept.push(instruction);
+            kept_positions.push(position);
+        }
+
+        let mut starts = Vec::new();
+        for index in 0..kept.len() {
+            match kept[index] {
+                Instruction::JumpForward(_) => starts.push(index),
+                Instruction::JumpBackward(_) => {
+                    let start = starts.pop().expect("settled instructions hold only complete loops");
+                    kept[start] = Instruction::JumpForward(base + index);
+                    kept[index] = Instruction::JumpBackward(base + start);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// How many leading instructions no later token can change: everything,
+    /// once all loops are closed, except a last instruction that a following
+    /// run could fold into or a following `,[.,]` could take over
+    fn settled_len(&self) -> usize {
+        if !self.jump_stack.is_empty() {
+            return 0;
+        }
+        match self.instructions.last() {
+            None | Some(Instruction::JumpBackward(_)) => self.instructions.len(),
+            Some(_) => self.instructions.len() - 1,
+        }
+    }
+}
+
+/// Instructions from [`Optimizer::optimize_streaming`], yielded as soon as
+/// no later token can change them
+pub struct OptimizeStream<'a, I> {
+    optimizer: &'a mut Optimizer,
+    tokens: I,
+    /// Instructions ready to yield
+    ready: std::vec::IntoIter<Instruction>,
+    /// The last settled instruction, kept back while a run after a dropped
+    /// loop could still merge into it
+    held: Vec<Instruction>,
+    held_positions: Vec<Position>,
+    /// Instructions yielded or ready so far, which is where `held` starts
+    yielded: usize,
+    done: bool,
+}
+
+impl<I> OptimizeStream<'_, I> {
+    /// Settle the first `len` instructions the optimizer holds, and make
+    /// everything that can no longer change ready
+    fn settle(&mut self, len: usize) {
+        let instructions = self.optimizer.instructions.drain(..len).collect();
+        let positions = self.optimizer.positions.drain(..len).collect();
+        self.optimizer
+            .settle(instructions, positions, &mut self.held, &mut self.held_positions, self.yielded);
+
+        let keep = match self.held.last() {
+            Some(instruction) if !self.done && !matches!(instruction, Instruction::JumpBackward(_)) => 1,
+            _ => 0,
+        };
+        let ready = self.held.len() - keep;
+        self.held_positions.drain(..ready);
+        let instructions: Vec<_> = self.held.drain(..ready).collect();
+        self.yielded += instructions.len();
+        self.ready = instructions.into_iter();
+    }
+}
+
+impl<I> Iterator for OptimizeStream<'_, I>
+where
+    I: Iterator<Item = Result<Token>>,
+{
+    type Item = Result<Instruction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(instruction) = self.ready.next() {
+                return Some(Ok(instruction));
+            }
+            if self.done {
+                return None;
+            }
+
+            let result = match self.tokens.next() {
+                Some(token) => token.and_then(|token| {
+                    self.optimizer.token_count += 1;
+                    self.optimizer.process_token(token)
+                }),
+                None => {
+                    self.done = true;
+                    self.optimizer.check_balanced()
+                }
+            };
+            if let Err(e) = result {
+                self.done = true;
+                return Some(Err(e));
+            }
+
+            let len = if self.done {
+                self.optimizer.instructions.len()
+            } else {
+                self.optimizer.settled_len()
+            };
+            if len > 0 || self.done {
+                self.settle(len);
+            }
+        }
+    }
+}
+
+/// Cell values known at optimize time, relative to a tracked pointer offset
+struct KnownCells {
+    /// Pointer position relative to where tracking started
+    offset: isize,
+    /// Value of every cell without an explicit entry
+    default: Option<u8>,
+    /// Cells whose value differs from `default`, `None` meaning unknown
+    cells: HashMap<isize, Option<u8>>,
+}
+
+impl KnownCells {
+    fn new(default: Option<u8>) -> Self {
+        Self {
+            offset: 0,
+            default,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Value of the cell `delta` away from the pointer
+    fn get(&self, delta: isize) -> Option<u8> {
+        let address = self.offset.checked_add(delta)?;
+        self.cells.get(&address).copied().unwrap_or(self.default)
+    }
+
+    /// Replace the value of the cell `delta` away from the pointer
+    fn update(&mut self, delta: isize, f: impl FnOnce(Option<u8>) -> Option<u8>) {
+        match self.offset.checked_add(delta) {
+            Some(address) => {
+                let value = f(self.get(delta));
+                self.cells.insert(address, value);
+            }
+            None => self.forget(),
+        }
+    }
+
+    /// Move the pointer, forgetting everything if the distance is unknown
+    fn shift(&mut self, delta: Option<isize>) {
+        match delta.and_then(|delta| self.offset.checked_add(delta)) {
+            Some(offset) => self.offset = offset,
+            None => self.forget(),
+        }
+    }
+
+    /// Update the tracked cells for the effect of `instruction`
+    fn step(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::MoveRight(n) => self.shift(isize::try_from(*n).ok()),
+            Instruction::MoveLeft(n) => self.shift(isize::try_from(*n).ok().map(|n| -n)),
+            Instruction::Increment(n) => self.update(0, |value| value.map(|v| v.wrapping_add(*n))),
+            Instruction::Decrement(n) => self.update(0, |value| value.map(|v| v.wrapping_sub(*n))),
+            Instruction::Input(_) => self.update(0, |_| None),
+            Instruction::JumpForward(_) => self.forget(),
+            Instruction::JumpBackward(_) => {
+                self.forget();
+                self.update(0, |_| Some(0));
+            }
+            Instruction::SetZero | Instruction::EchoStream => self.update(0, |_| Some(0)),
+            Instruction::Copy { targets } => {
+                let counter = self.get(0);
+                for &offset in targets {
+                    self.update(offset, |value| Some(value?.wrapping_add(counter?)));
+                }
+                self.update(0, |_| Some(0));
+            }
+            Instruction::MultiplyAdd { targets } => {
+                let counter = self.get(0);
+                for &(offset, factor) in targets {
+                    self.update(offset, |value| Some(value?.wrapping_add(counter?.wrapping_mul(factor))));
+                }
+                self.update(0, |_| Some(0));
+            }
+            Instruction::AddAtOffset { offset, value: n } => {
+                self.update(*offset, |value| value.map(|v| v.wrapping_add(*n)))
+            }
+            Instruction::SetAtOffset { offset, value: n } => self.update(*offset, |_| Some(*n)),
+            Instruction::SetValue(n) => self.update(0, |_| Some(*n)),
+            Instruction::Output(_)
+            | Instruction::PrintConst(_)
+            | Instruction::DebugDump
+            | Instruction::Halt
+            | Instruction::StoreRegister
+            | Instruction::SwitchInput
+            | Instruction::Nop => {}
+            Instruction::RestoreRegister => self.update(0, |_| None),
+            Instruction::ScanAdd { .. } | Instruction::ScanRight(_) | Instruction::ScanLeft(_) => {
+                self.forget();
+                self.update(0, |_| Some(0));
+            }
+            // A handler may change any cell and move the pointer
+            Instruction::Extension(_) => self.forget(),
+        }
+    }
+
+    /// Treat every cell as unknown
+    fn forget(&mut self) {
+        self.offset = 0;
+        self.default = None;
+        self.cells.clear();
+    }
+}
+
+/// Accumulate a wrapping change to the cell at `offset`
+fn add_delta(deltas: &mut Vec<(isize, u8)>, offset: isize, amount: u8) {
+    match deltas.iter_mut().find(|(target, _)| *target == offset) {
+        Some((_, delta)) => *delta = delta.wrapping_add(amount),
+        None => deltas.push((offset, amount)),
+    }
+}
+
+/// Fold `next` into `last` if both are runs of the same command, or if
+/// `next` adds to a constant `last` just stored, so `[-]+++` stores 3
+fn merge_run(last: &mut Instruction, next: &Instruction) -> bool {
+    let constant = match (&*last, next) {
+        (Instruction::SetZero, Instruction::Increment(m)) => Some(*m),
+        (Instruction::SetZero, Instruction::Decrement(m)) => Some(m.wrapping_neg()),
+        (Instruction::SetValue(value), Instruction::Increment(m)) => Some(value.wrapping_add(*m)),
+        (Instruction::SetValue(value), Instruction::Decrement(m)) => Some(value.wrapping_sub(*m)),
+        _ => None,
+    };
+    if let Some(value) = constant {
+        *last = match value {
+            0 => Instruction::SetZero,
+            _ => Instruction::SetValue(value),
+        };
+        return true;
+    }
+
+    match (last, next) {
+        (Instruction::MoveRight(n), Instruction::MoveRight(m))
+        | (Instruction::MoveLeft(n), Instruction::MoveLeft(m))
+        | (Instruction::Output(n), Instruction::Output(m))
+        | (Instruction::Input(n), Instruction::Input(m)) => *n += m,
+        (Instruction::Increment(n), Instruction::Increment(m)) | (Instruction::Decrement(n), Instruction::Decrement(m)) => {
+            *n = n.wrapping_add(*m)
+        }
+        (Instruction::PrintConst(bytes), Instruction::PrintConst(more)) => bytes.extend_from_slice(more),
+        _ => return false,
+    }
+    true
+}
+
 impl Default for Optimizer {
     fn default() -> Self {
         Self::new()
@@ -201,9 +1040,61 @@ impl Default for Optimizer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::interpreter::{Interpreter, InterpreterConfig};
     use crate::lexer::Lexer;
     use std::io::Cursor;
 
+    #[test]
+    fn test_instruction_names() {
+        use Instruction::*;
+
+        let named = [
+            (MoveRight(1), "MoveRight"),
+            (MoveLeft(1), "MoveLeft"),
+            (Increment(1), "Increment"),
+            (Decrement(1), "Decrement"),
+            (Output(1), "Output"),
+            (Input(1), "Input"),
+            (JumpForward(0), "JumpForward"),
+            (JumpBackward(0), "JumpBackward"),
+            (SetZero, "SetZero"),
+            (SetValue(1), "SetValue"),
+            (Copy { targets: vec![1] }, "Copy"),
+            (MultiplyAdd { targets: vec![(1, 2)] }, "MultiplyAdd"),
+            (AddAtOffset { offset: 1, value: 1 }, "AddAtOffset"),
+            (SetAtOffset { offset: 1, value: 1 }, "SetAtOffset"),
+            (PrintConst(vec![b'a']), "PrintConst"),
+            (ScanAdd { stride: 1, adds: vec![(0, 1)] }, "ScanAdd"),
+            (ScanRight(1), "ScanRight"),
+            (ScanLeft(1), "ScanLeft"),
+            (EchoStream, "EchoStream"),
+            (DebugDump, "DebugDump"),
+            (Halt, "Halt"),
+            (StoreRegister, "StoreRegister"),
+            (RestoreRegister, "RestoreRegister"),
+            (SwitchInput, "SwitchInput"),
+            (Extension('?'), "Extension"),
+            (Nop, "Nop"),
+        ];
+        for (instruction, name) in named {
+            assert_eq!(instruction.name(), name);
+            // Names match how the variants are printed for debugging
+            assert!(format!("{:?}", instruction).starts_with(name));
+        }
+    }
+
+    #[test]
+    fn test_explain_recognised_loops() {
+        let explain = |input: &str| -> Vec<String> { optimize_str(input).iter().filter_map(Instruction::explain).collect() };
+
+        assert_eq!(explain("[->++>+++<<]"), ["add 2× cell[0] to cell[1], add 3× cell[0] to cell[2], then clear cell[0]"]);
+        assert_eq!(explain("+[-]>[-<+>]"), ["clear cell", "add cell[0] to cell[-1], then clear cell[0]"]);
+        assert_eq!(explain("[<<]"), ["move left by 2 until a zero cell"]);
+        assert_eq!(explain("[+>>]"), ["add 1 to cell[0] and move right by 2 until a zero cell"]);
+        // Loops left as jumps have nothing to explain
+        assert!(explain("+[>,<-]").is_empty());
+    }
+
     #[test]
     fn test_optimize_consecutive_moves() {
         let input = ">>>>";
@@ -247,15 +1138,411 @@ mod tests {
 
     #[test]
     fn test_optimize_simple_loop() {
-        let input = "[+]";
+        let input = "[++]";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+
+        let instructions = optimizer.optimize(lexer).unwrap();
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[0], Instruction::JumpForward(2));
+        assert_eq!(instructions[1], Instruction::Increment(2));
+        assert_eq!(instructions[2], Instruction::JumpBackward(0));
+    }
+
+    #[test]
+    fn test_instruction_positions() {
+        let input = "++\n>[-.]>[-]";
         let cursor = Cursor::new(input.as_bytes());
         let lexer = Lexer::new(cursor);
         let mut optimizer = Optimizer::new();
 
         let instructions = optimizer.optimize(lexer).unwrap();
-        assert_eq!(instructions.len(), 2);
-        assert_eq!(instructions[0], Instruction::JumpForward(1));
-        assert_eq!(instructions[1], Instruction::JumpBackward(0));
+        assert_eq!(instructions.len(), optimizer.positions().len());
+        assert_eq!(
+            optimizer.positions(),
+            &[
+                Position::new(1, 1),
+                Position::new(2, 1),
+                Position::new(2, 2),
+                Position::new(2, 3),
+                Position::new(2, 4),
+                Position::new(2, 5),
+                Position::new(2, 6),
+                Position::new(2, 7),
+            ]
+        );
+        // A recognised loop takes the position of its `[`
+        assert_eq!(instructions[7], Instruction::SetZero);
+    }
+
+    #[test]
+    fn test_empty_loop_warning() {
+        let input = "+\n  []";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+
+        optimizer.optimize(lexer).unwrap();
+        assert_eq!(optimizer.warnings().len(), 1);
+        assert_eq!(optimizer.warnings()[0].position, Position::new(2, 3));
+        assert_eq!(optimizer.warnings()[0].to_string(), "empty loop at 2:3");
+    }
+
+    #[test]
+    fn test_non_empty_loop_has_no_warning() {
+        let input = "[-]";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+
+        optimizer.optimize(lexer).unwrap();
+        assert!(optimizer.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_folding_statistics() {
+        let input = "+".repeat(1000);
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+
+        optimizer.optimize(lexer).unwrap();
+        assert_eq!(optimizer.token_count(), 1000);
+        assert_eq!(optimizer.instruction_count(), 1);
+        assert_eq!(optimizer.compression_ratio(), 1000.0);
+
+        // Comments are not tokens, and `[-]` folds three tokens into one
+        let cursor = Cursor::new("a+b+ [-] >".as_bytes());
+        optimizer.optimize(Lexer::new(cursor)).unwrap();
+        assert_eq!(optimizer.token_count(), 6);
+        assert_eq!(optimizer.instruction_count(), 3);
+        assert_eq!(optimizer.compression_ratio(), 2.0);
+
+        optimizer.optimize(Lexer::new(Cursor::new("".as_bytes()))).unwrap();
+        assert_eq!(optimizer.compression_ratio(), 0.0);
+    }
+
+    fn nesting_depth(input: &str) -> usize {
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+
+        optimizer.optimize(lexer).unwrap();
+        optimizer.max_nesting_depth()
+    }
+
+    #[test]
+    fn test_max_nesting_depth() {
+        assert_eq!(nesting_depth("[[[]]]"), 3);
+        assert_eq!(nesting_depth("[][]"), 1);
+        assert_eq!(nesting_depth("+++"), 0);
+        // Recognised loops are replaced but still nest
+        assert_eq!(nesting_depth("+[>[-]<-]"), 2);
+    }
+
+    fn optimize_str(input: &str) -> Vec<Instruction> {
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        Optimizer::new().optimize(lexer).unwrap()
+    }
+
+    #[test]
+    fn test_long_arithmetic_runs_fold_modulo_256() {
+        assert_eq!(optimize_str(&"+".repeat(300)), vec![Instruction::Increment(44)]);
+        assert_eq!(optimize_str(&"-".repeat(513)), vec![Instruction::Decrement(1)]);
+    }
+
+    #[test]
+    fn test_optimize_clear_loop() {
+        assert_eq!(optimize_str("[-]"), vec![Instruction::SetZero]);
+        assert_eq!(optimize_str(">[-]<"), vec![
+            Instruction::MoveRight(1),
+            Instruction::SetZero,
+            Instruction::MoveLeft(1),
+        ]);
+    }
+
+    #[test]
+    fn test_fold_constant_after_clear() {
+        let set = Instruction::SetValue;
+        assert_eq!(optimize_str("[-]+++"), vec![set(3)]);
+        assert_eq!(optimize_str("[-]--"), vec![set(254)]);
+        assert_eq!(optimize_str(">[-]+++<"), vec![Instruction::MoveRight(1), set(3), Instruction::MoveLeft(1)]);
+        // A run split by a dropped loop still folds
+        assert_eq!(optimize_str("[-][]+++"), vec![set(3)]);
+        // Changes that cancel out leave a plain clear
+        assert_eq!(optimize_str("[-]+-"), vec![Instruction::SetZero]);
+    }
+
+    #[test]
+    fn test_optimize_echo_loop() {
+        assert_eq!(optimize_str(",[.,]"), vec![Instruction::EchoStream]);
+        assert_eq!(optimize_str(">,,,[.,]"), vec![Instruction::MoveRight(1), Instruction::Input(2), Instruction::EchoStream]);
+        assert_eq!(optimize_str(",[.]"), vec![
+            Instruction::Input(1),
+            Instruction::JumpForward(3),
+            Instruction::Output(1),
+            Instruction::JumpBackward(1),
+        ]);
+        assert_eq!(optimize_str("+[.,]").len(), 5);
+    }
+
+    #[test]
+    fn test_optimize_odd_step_clear_loops() {
+        assert_eq!(optimize_str("[+]"), vec![Instruction::SetZero]);
+        assert_eq!(optimize_str("[---]"), vec![Instruction::SetZero]);
+
+        // Three decrements a time wrap around until they land on zero
+        for start in [1, 2, 128, 255] {
+            let mut interpreter = Interpreter::new(optimize_str("[---]"), InterpreterConfig::default());
+            interpreter.load_memory(&[start]);
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.memory_state()[0], 0, "starting from {}", start);
+        }
+
+        // An even step never reaches zero from an odd value, other cells
+        // changing makes it a transfer rather than a clear, and visiting
+        // another cell needs its bounds check
+        assert_eq!(optimize_str("[--]"), vec![Instruction::JumpForward(2), Instruction::Decrement(2), Instruction::JumpBackward(0)]);
+        assert_eq!(optimize_str("[->+<]"), vec![Instruction::Copy { targets: vec![1] }]);
+        assert_ne!(optimize_str("[+>+<]"), vec![Instruction::SetZero]);
+        let instructions = optimize_str("[+>-+<-+]");
+        assert_eq!(instructions.first(), Some(&Instruction::JumpForward(instructions.len() - 1)));
+    }
+
+    #[test]
+    fn test_optimize_copy_loops() {
+        assert_eq!(optimize_str("[->+<]"), vec![Instruction::Copy { targets: vec![1] }]);
+        assert_eq!(optimize_str("[->+>+<<]"), vec![Instruction::Copy { targets: vec![1, 2] }]);
+        assert_eq!(optimize_str("[<+>-]"), vec![Instruction::Copy { targets: vec![-1] }]);
+    }
+
+    #[test]
+    fn test_optimize_multiply_loops() {
+        assert_eq!(optimize_str("[->++<]"), vec![Instruction::MultiplyAdd { targets: vec![(1, 2)] }]);
+        assert_eq!(
+            optimize_str("[->+>---<<]"),
+            vec![Instruction::MultiplyAdd { targets: vec![(1, 1), (2, 253)] }]
+        );
+    }
+
+    #[test]
+    fn test_nested_loop_becomes_copy() {
+        assert_eq!(optimize_str("+[>[->+<]<-]"), vec![
+            Instruction::Increment(1),
+            Instruction::JumpForward(6),
+            Instruction::MoveRight(1),
+            Instruction::Copy { targets: vec![1] },
+            Instruction::MoveLeft(1),
+            Instruction::Decrement(1),
+            Instruction::JumpBackward(1),
+        ]);
+    }
+
+    #[test]
+    fn test_unrecognized_loops_stay_as_jumps() {
+        // Counter steps by two, no counter change, I/O, and a scan that
+        // looks past its stride
+        for input in ["[-->+<]", "[>+<]", "[-.]", "[>><]", "[<<<>]"] {
+            let instructions = optimize_str(input);
+            assert_eq!(instructions.first(), Some(&Instruction::JumpForward(instructions.len() - 1)));
+        }
+    }
+
+    #[test]
+    fn test_optimize_scan_add_loops() {
+        assert_eq!(
+            optimize_str("[>+>]"),
+            vec![Instruction::ScanAdd { stride: 2, adds: vec![(1, 1)] }]
+        );
+        assert_eq!(
+            optimize_str("[->+<<]"),
+            vec![Instruction::ScanAdd { stride: -1, adds: vec![(0, 255), (1, 1)] }]
+        );
+    }
+
+    #[test]
+    fn test_optimize_scan_loops() {
+        assert_eq!(optimize_str("[>>]"), vec![Instruction::ScanRight(2)]);
+        assert_eq!(optimize_str("[<]"), vec![Instruction::ScanLeft(1)]);
+        assert_eq!(optimize_str("[<<]"), vec![Instruction::ScanLeft(2)]);
+    }
+
+    #[test]
+    fn test_scan_add_rejects_untouched_excursion() {
+        // The pointer visits offset 4 without adding there, so the naive
+        // bounds check would be lost
+        let instructions = optimize_str("[>+>>><<]");
+        assert_eq!(instructions.first(), Some(&Instruction::JumpForward(instructions.len() - 1)));
+    }
+
+    #[test]
+    fn test_unoptimized_output_is_naive() {
+        let input = "+++>>[-]";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::with_config(OptimizerConfig {
+            opt_level: OptLevel::None,
+            ..Default::default()
+        });
+
+        let instructions = optimizer.optimize(lexer).unwrap();
+        assert_eq!(instructions, vec![
+            Instruction::Increment(1),
+            Instruction::Increment(1),
+            Instruction::Increment(1),
+            Instruction::MoveRight(1),
+            Instruction::MoveRight(1),
+            Instruction::JumpForward(7),
+            Instruction::Decrement(1),
+            Instruction::JumpBackward(5),
+        ]);
+        assert_eq!(optimizer.positions().len(), instructions.len());
+    }
+
+    #[test]
+    fn test_opt_level_fold_keeps_loops() {
+        let optimize_at = |opt_level| {
+            let lexer = Lexer::new(Cursor::new(b"+++[-]".as_slice()));
+            let config = OptimizerConfig {
+                opt_level,
+                initial_cell_value: Some(0),
+                ..Default::default()
+            };
+            Optimizer::with_config(config).optimize(lexer).unwrap()
+        };
+
+        assert_eq!(optimize_at(OptLevel::Fold), vec![
+            Instruction::Increment(3),
+            Instruction::JumpForward(3),
+            Instruction::Decrement(1),
+            Instruction::JumpBackward(1),
+        ]);
+        assert_eq!(optimize_at(OptLevel::Full), vec![Instruction::Increment(3), Instruction::SetZero]);
+    }
+
+    fn optimize_with_start(input: &str, initial_cell_value: Option<u8>) -> Vec<Instruction> {
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let config = OptimizerConfig {
+            initial_cell_value,
+            ..Default::default()
+        };
+        Optimizer::with_config(config).optimize(lexer).unwrap()
+    }
+
+    #[test]
+    fn test_constant_output_with_known_start() {
+        assert_eq!(optimize_with_start("+.", Some(0)), vec![
+            Instruction::Increment(1),
+            Instruction::PrintConst(vec![1]),
+        ]);
+        assert_eq!(optimize_with_start("+.", None), vec![
+            Instruction::Increment(1),
+            Instruction::Output(1),
+        ]);
+        assert_eq!(optimize_with_start("-..", Some(5)), vec![
+            Instruction::Decrement(1),
+            Instruction::PrintConst(vec![4, 4]),
+        ]);
+    }
+
+    #[test]
+    fn test_constant_output_through_multiply_loop() {
+        let instructions = optimize_with_start("++++++++[>++++++++<-]>+.", Some(0));
+        assert_eq!(instructions.last(), Some(&Instruction::PrintConst(vec![b'A'])));
+    }
+
+    #[test]
+    fn test_constant_output_after_clear() {
+        // The input makes the start unknown, but the clear pins the cell down
+        let instructions = optimize_with_start(",[-]+++.>.", None);
+        assert_eq!(instructions[1], Instruction::SetValue(3));
+        assert_eq!(instructions[2], Instruction::PrintConst(vec![3]));
+        assert_eq!(instructions[4], Instruction::Output(1));
+
+        // A loop always exits on a zero cell
+        let instructions = optimize_with_start(",[.-].", None);
+        assert_eq!(instructions.last(), Some(&Instruction::PrintConst(vec![0])));
+        assert_eq!(instructions[2], Instruction::Output(1));
+    }
+
+    #[test]
+    fn test_dead_loops_removed() {
+        // The clear leaves a zero cell, so the next loop can never run, and
+        // the loop after `-` is renumbered
+        assert_eq!(optimize_with_start(",[-][.+]-[.+]", None), vec![
+            Instruction::Input(1),
+            Instruction::SetValue(255),
+            Instruction::JumpForward(5),
+            Instruction::Output(1),
+            Instruction::Increment(1),
+            Instruction::JumpBackward(2),
+        ]);
+
+        // A comment loop at the start goes, but `-` makes the cell non-zero
+        assert_eq!(optimize_with_start("[a comment, with. commands-][-]-[+++]", Some(0)), vec![
+            Instruction::Decrement(1),
+            Instruction::SetZero,
+        ]);
+
+        // Runs either side of a dropped loop merge
+        assert_eq!(optimize_with_start(".[].>[-]>", Some(0)), vec![
+            Instruction::PrintConst(vec![0, 0]),
+            Instruction::MoveRight(2),
+        ]);
+
+        // Nothing is known about memory that may have been seeded
+        assert_eq!(optimize_with_start("[.+]", None).len(), 4);
+    }
+
+    #[test]
+    fn test_settle_strips_nops() {
+        use Instruction::*;
+
+        let instructions = vec![Nop, Increment(1), JumpForward(6), Nop, Decrement(1), Nop, JumpBackward(2), Nop];
+        let positions = vec![Position::default(); instructions.len()];
+        let (mut kept, mut kept_positions) = (Vec::new(), Vec::new());
+        Optimizer::new().settle(instructions, positions, &mut kept, &mut kept_positions, 0);
+        assert_eq!(kept, [Increment(1), JumpForward(3), Decrement(1), JumpBackward(1)]);
+        assert_eq!(kept_positions.len(), kept.len());
+
+        // A run that wraps all the way round becomes a `Nop`, and the runs
+        // either side of it merge once it is gone
+        assert_eq!(optimize_str(&format!(">{}>", "+".repeat(256))), [MoveRight(2)]);
+        assert!(!optimize_str(&format!(",[{}-]", "-".repeat(255))).contains(&Nop));
+    }
+
+    #[test]
+    fn test_streaming_matches_batch() {
+        let config = OptimizerConfig {
+            initial_cell_value: Some(0),
+            warn_wrapping: true,
+            warn_pointer_drift: true,
+            ..Default::default()
+        };
+        for source in [
+            "+++>>--<.,,.>>>+.-",
+            "[comment]++[->+<]>.,[.,]-[>+<<]>>[-[+.]]<.",
+            ".[].>[-]>[+]>[->+<]>",
+        ] {
+            let mut optimizer = Optimizer::with_config(config.clone());
+            let batch = optimizer.optimize(Lexer::new(Cursor::new(source.as_bytes()))).unwrap();
+            let batch_warnings = optimizer.warnings().to_vec();
+
+            let streamed = optimizer
+                .optimize_streaming(Lexer::new(Cursor::new(source.as_bytes())))
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(streamed, batch, "{}", source);
+            assert_eq!(optimizer.warnings(), batch_warnings);
+        }
+
+        // An unmatched bracket ends the stream with an error
+        let mut optimizer = Optimizer::new();
+        let results: Vec<_> = optimizer.optimize_streaming(Lexer::new(Cursor::new(b"+.[".as_slice()))).collect();
+        assert!(results.last().unwrap().is_err());
     }
 
     #[test]
@@ -269,6 +1556,13 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_unmatched_bracket_reports_open_position() {
+        let lexer = Lexer::new(Cursor::new("+[[-]\n>[".as_bytes()));
+        let error = Optimizer::new().optimize(lexer).unwrap_err();
+        assert_eq!(error.to_string(), "Unmatched bracket at position 2:2");
+    }
+
     #[test]
     fn test_optimize_unmatched_bracket_end() {
         let input = "]";
@@ -279,4 +1573,93 @@ mod tests {
         let result = optimizer.optimize(lexer);
         assert!(result.is_err());
     }
+
+    /// Warnings from optimizing `input` with the wrapping lint enabled and
+    /// cells starting at zero
+    fn wrapping_warnings(input: &str) -> Vec<String> {
+        let lexer = Lexer::new(Cursor::new(input.as_bytes()));
+        let mut optimizer = Optimizer::with_config(OptimizerConfig {
+            initial_cell_value: Some(0),
+            warn_wrapping: true,
+            ..Default::default()
+        });
+        optimizer.optimize(lexer).unwrap();
+        optimizer.warnings().iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_warn_wrapping_decrements() {
+        assert_eq!(wrapping_warnings("-"), ["decrement wraps a cell holding 0 around to 255 at 1:1"]);
+        assert_eq!(wrapping_warnings("+\n[.-]---"), ["decrement wraps a cell holding 0 around to 253 at 2:5"]);
+        assert_eq!(wrapping_warnings("++>+<---"), ["decrement wraps a cell holding 2 around to 255 at 1:6"]);
+
+        assert!(wrapping_warnings("+-").is_empty());
+        // Cells are unknown after input and inside loops
+        assert!(wrapping_warnings(",-").is_empty());
+        assert!(wrapping_warnings("+[-]").is_empty());
+        // Off unless asked for
+        let lexer = Lexer::new(Cursor::new("-".as_bytes()));
+        let mut optimizer = Optimizer::with_config(OptimizerConfig {
+            initial_cell_value: Some(0),
+            ..Default::default()
+        });
+        optimizer.optimize(lexer).unwrap();
+        assert!(optimizer.warnings().is_empty());
+    }
+
+    /// Warnings from optimizing `input` with the pointer drift lint enabled
+    fn drift_warnings(input: &str) -> Vec<String> {
+        let lexer = Lexer::new(Cursor::new(input.as_bytes()));
+        let mut optimizer = Optimizer::with_config(OptimizerConfig {
+            warn_pointer_drift: true,
+            ..Default::default()
+        });
+        optimizer.optimize(lexer).unwrap();
+        optimizer.warnings().iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_warn_pointer_drift() {
+        assert!(drift_warnings("[>+<]").is_empty());
+        assert_eq!(drift_warnings("[>+]"), ["loop moves the pointer right by 1 each iteration at 1:1"]);
+        assert_eq!(drift_warnings("+\n[<<.]"), ["loop moves the pointer left by 2 each iteration at 2:1"]);
+
+        // Each loop is checked on its own, whatever is nested inside it
+        assert!(drift_warnings("[>[-<+>]<]").is_empty());
+        assert_eq!(drift_warnings("[[>,]>]"), [
+            "loop moves the pointer right by 1 each iteration at 1:2",
+            "loop moves the pointer right by 1 each iteration at 1:1",
+        ]);
+    }
+
+    #[test]
+    fn test_lenient_drops_unmatched_bracket_end() {
+        let input = "]+++";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::with_config(OptimizerConfig {
+            lenient: true,
+            ..Default::default()
+        });
+
+        let instructions = optimizer.optimize(lexer).unwrap();
+        assert_eq!(instructions, vec![Instruction::Increment(3)]);
+        assert_eq!(optimizer.warnings().len(), 1);
+        assert_eq!(optimizer.warnings()[0].to_string(), "unmatched ']' dropped at 1:1");
+    }
+
+    #[test]
+    fn test_lenient_keeps_well_formed_loops() {
+        let input = "+[.-]]";
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::with_config(OptimizerConfig {
+            lenient: true,
+            ..Default::default()
+        });
+
+        let instructions = optimizer.optimize(lexer).unwrap();
+        assert_eq!(instructions.last(), Some(&Instruction::JumpBackward(1)));
+        assert_eq!(optimizer.warnings()[0].position, Position::new(1, 6));
+    }
 } 
\ No newline at end of file
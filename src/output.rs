@@ -0,0 +1,165 @@
+//! Adapters that reformat a program's output on its way to the real writer.
+
+use std::io::{self, Write};
+
+/// Bytes shown on each line of a hex dump
+const BYTES_PER_LINE: usize = 16;
+
+/// Renders everything written to it as a hex dump in the style of
+/// `hexdump -C`: the offset, sixteen bytes in hex, and the printable ones as
+/// ASCII
+///
+/// Lines are written as they fill. The partial last line is written by
+/// [`finish`](HexDump::finish), or when the dump is dropped.
+pub struct HexDump<W: Write> {
+    inner: W,
+    /// Bytes of the line being filled
+    line: Vec<u8>,
+    /// Offset of the first byte of `line`
+    offset: usize,
+}
+
+impl<W: Write> HexDump<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            line: Vec::with_capacity(BYTES_PER_LINE),
+            offset: 0,
+        }
+    }
+
+    /// Write the partial last line, if any, and flush
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.write_line()?;
+        }
+        self.inner.flush()
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let mut text = format!("{:08x} ", self.offset);
+        for index in 0..BYTES_PER_LINE {
+            // An extra space splits the line into two groups of eight
+            if index % 8 == 0 {
+                text.push(' ');
+            }
+            match self.line.get(index) {
+                Some(byte) => text.push_str(&format!("{:02x} ", byte)),
+                None => text.push_str("   "),
+            }
+        }
+        text.push_str(" |");
+        text.extend(self.line.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        text.push_str("|\n");
+
+        self.inner.write_all(text.as_bytes())?;
+        self.offset += self.line.len();
+        self.line.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HexDump<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.line.push(byte);
+            if self.line.len() == BYTES_PER_LINE {
+                self.write_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Flush the lines written so far; the line being filled waits for more
+    /// bytes or [`finish`](HexDump::finish)
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for HexDump<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Passes printable ASCII, newlines and tabs through, and writes every
+/// other byte as `\xNN` so it cannot garble a terminal
+///
+/// A backslash is written as `\\`, so the escaped text reads back
+/// unambiguously.
+pub struct EscapeOutput<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> EscapeOutput<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+}
+
+impl<W: Write> Write for EscapeOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut escaped = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            match byte {
+                b'\\' => escaped.extend_from_slice(b"\\\\"),
+                b'\n' | b'\t' | b' '..=b'~' => escaped.push(byte),
+                _ => escaped.extend_from_slice(format!("\\x{:02x}", byte).as_bytes()),
+            }
+        }
+        self.inner.write_all(&escaped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, InterpreterConfig};
+    use crate::optimizer::Instruction;
+
+    #[test]
+    fn test_hex_dump() {
+        let mut output = Vec::new();
+        let instructions = vec![Instruction::PrintConst(vec![0x41, 0x42])];
+        let mut interpreter =
+            Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), HexDump::new(&mut output));
+        interpreter.run().unwrap();
+        drop(interpreter);
+
+        let dump = String::from_utf8(output).unwrap();
+        assert!(dump.contains("41 42"), "{}", dump);
+        assert!(dump.contains("|AB|"), "{}", dump);
+
+        let mut output = Vec::new();
+        let mut dump = HexDump::new(&mut output);
+        dump.write_all(b"Hello, world!\n\x00\xffmore").unwrap();
+        dump.finish().unwrap();
+        drop(dump);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|\n\
+             00000010  6d 6f 72 65                                       |more|\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_output() {
+        let mut output = Vec::new();
+        let instructions = vec![Instruction::PrintConst(vec![0x01])];
+        let mut interpreter =
+            Interpreter::with_io(instructions, InterpreterConfig::default(), io::empty(), EscapeOutput::new(&mut output));
+        interpreter.run().unwrap();
+        drop(interpreter);
+        assert_eq!(output, b"\\x01");
+
+        let mut output = Vec::new();
+        EscapeOutput::new(&mut output).write_all(b"ok\tdone\n\x1b[2J\\\xff").unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "ok\tdone\n\\x1b[2J\\\\\\xff");
+    }
+}
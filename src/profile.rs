@@ -0,0 +1,152 @@
+use crate::error::Position;
+use crate::optimizer::Instruction;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Name of the outermost frame, which holds instructions outside any loop
+const ROOT_FRAME: &str = "program";
+
+/// Render execution counts as folded stacks for flamegraph tools
+///
+/// Each line is a `;`-separated stack of loop frames followed by the number
+/// of instructions executed directly in the innermost one. Loops are named
+/// `loop@line:column` after their `[`, and loops the optimizer replaced with
+/// a single instruction get a frame of their own. `counts` and `positions`
+/// hold one entry per instruction, as from
+/// [`Interpreter::profile`](crate::interpreter::Interpreter::profile) and
+/// [`Optimizer::positions`](crate::optimizer::Optimizer::positions).
+pub fn folded_stacks(instructions: &[Instruction], positions: &[Position], counts: &[u64]) -> String {
+    // The current stack joined with `;`, and where each enclosing frame of
+    // it ends, so it only changes when a loop is entered or left
+    let mut stack = ROOT_FRAME.to_string();
+    let mut frame_ends = Vec::new();
+    // Total per stack, in order of first appearance
+    let mut stacks: Vec<(String, u64)> = Vec::new();
+    let mut stack_indices: HashMap<String, usize> = HashMap::new();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        let loop_frame = || format!(";loop@{}", positions.get(index).copied().unwrap_or_default());
+        let count = counts.get(index).copied().unwrap_or(0);
+
+        match instruction {
+            Instruction::JumpForward(_) => {
+                frame_ends.push(stack.len());
+                stack.push_str(&loop_frame());
+                add_to_stack(&mut stacks, &mut stack_indices, &stack, count);
+            }
+            Instruction::JumpBackward(_) => {
+                add_to_stack(&mut stacks, &mut stack_indices, &stack, count);
+                if let Some(end) = frame_ends.pop() {
+                    stack.truncate(end);
+                }
+            }
+            Instruction::SetZero
+            | Instruction::SetValue(_)
+            | Instruction::Copy { .. }
+            | Instruction::MultiplyAdd { .. }
+            | Instruction::ScanAdd { .. }
+            | Instruction::ScanRight(_)
+            | Instruction::ScanLeft(_)
+            | Instruction::EchoStream => {
+                add_to_stack(&mut stacks, &mut stack_indices, &(stack.clone() + &loop_frame()), count)
+            }
+            _ => add_to_stack(&mut stacks, &mut stack_indices, &stack, count),
+        }
+    }
+
+    let mut folded = String::new();
+    for (stack, total) in stacks.into_iter().filter(|&(_, total)| total > 0) {
+        let _ = writeln!(folded, "{} {}", stack, total);
+    }
+    folded
+}
+
+/// Add `count` to the total for `stack`, starting a new one if it is the
+/// first time `stack` is seen
+fn add_to_stack(stacks: &mut Vec<(String, u64)>, indices: &mut HashMap<String, usize>, stack: &str, count: u64) {
+    match indices.get(stack) {
+        Some(&index) => stacks[index].1 += count,
+        None => {
+            indices.insert(stack.to_string(), stacks.len());
+            stacks.push((stack.to_string(), count));
+        }
+    }
+}
+
+/// List the `limit` instructions that took the most time, slowest first
+///
+/// Each line holds the time, the instruction's index and the instruction,
+/// followed by its source position when known. `timing` holds one entry per
+/// instruction, as from
+/// [`Interpreter::timing`](crate::interpreter::Interpreter::timing).
+pub fn hottest_by_time(instructions: &[Instruction], positions: &[Position], timing: &[Duration], limit: usize) -> String {
+    let mut indices: Vec<usize> = (0..instructions.len().min(timing.len())).collect();
+    indices.sort_by_key(|&index| std::cmp::Reverse(timing[index]));
+
+    let mut report = String::new();
+    for index in indices.into_iter().take(limit).filter(|&index| !timing[index].is_zero()) {
+        let _ = write!(report, "{:>12.3?}  #{} {:?}", timing[index], index, instructions[index]);
+        if let Some(position) = positions.get(index) {
+            let _ = write!(report, " at {}", position);
+        }
+        report.push('\n');
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, InterpreterConfig};
+    use crate::lexer::Lexer;
+    use crate::optimizer::Optimizer;
+    use std::io::{self, Cursor};
+
+    fn profile(program: &str) -> String {
+        let cursor = Cursor::new(program.as_bytes());
+        let mut optimizer = Optimizer::new();
+        let instructions = optimizer.optimize(Lexer::new(cursor)).unwrap();
+
+        let mut interpreter =
+            Interpreter::with_io(instructions.clone(), InterpreterConfig::default(), io::empty(), io::sink());
+        interpreter.enable_profile();
+        interpreter.run().unwrap();
+
+        folded_stacks(&instructions, optimizer.positions(), interpreter.profile().unwrap())
+    }
+
+    #[test]
+    fn test_nested_loop_stacks() {
+        // The outer loop runs twice and the inner loop three times per pass
+        let folded = profile("++[>+++[.-]<-]");
+        let lines: Vec<&str> = folded.lines().collect();
+        assert_eq!(
+            lines,
+            ["program 1", "program;loop@1:3 12", "program;loop@1:3;loop@1:8 24"]
+        );
+    }
+
+    #[test]
+    fn test_recognised_loop_gets_frame() {
+        let folded = profile("+++[-]");
+        assert_eq!(folded, "program 1\nprogram;loop@1:4 1\n");
+    }
+
+    #[test]
+    fn test_skipped_loop_counts_its_brackets() {
+        // The `[` jumps to the `]`, so only the body is never run
+        assert_eq!(profile("[.]+"), "program;loop@1:1 2\nprogram 1\n");
+    }
+
+    #[test]
+    fn test_hottest_by_time() {
+        let instructions = [Instruction::Increment(1), Instruction::Output(1), Instruction::SetZero];
+        let positions = [Position::new(1, 1), Position::new(1, 2)];
+        let timing = [Duration::from_micros(5), Duration::from_millis(2), Duration::ZERO];
+
+        let report = hottest_by_time(&instructions, &positions, &timing, 10);
+        assert_eq!(report, "     2.000ms  #1 Output(1) at 1:2\n     5.000µs  #0 Increment(1) at 1:1\n");
+        assert_eq!(hottest_by_time(&instructions, &positions, &timing, 1).lines().count(), 1);
+    }
+}
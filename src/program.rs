@@ -0,0 +1,298 @@
+//! Optimized instructions paired with the source positions they came from.
+
+use crate::bytecode;
+use crate::error::Position;
+use crate::lexer::{Directives, LexerConfig, SliceLexer};
+use crate::optimizer::{Instruction, Optimizer};
+use crate::source::Source;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// An optimized program with the source position of every instruction
+///
+/// This is what tooling that walks the instructions, such as source maps,
+/// profiles and diagnostics, needs; the interpreter only needs
+/// [`instructions`](Program::instructions).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    /// Source position of the first token folded into each instruction
+    positions: Vec<Position>,
+    /// Settings from the source's `!` lines, which bytecode does not keep
+    directives: Directives,
+}
+
+/// What can be told about a program without running it
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProgramInfo {
+    /// Number of optimized instructions
+    pub instruction_count: usize,
+    /// Number of loops, counting those the optimizer replaced
+    pub loop_count: usize,
+    /// Deepest loop nesting, counting replaced loops as a level
+    pub max_nesting: usize,
+    /// Whether the program has any input or output instructions, even in a
+    /// loop that may never run
+    pub does_io: bool,
+    /// Whether some input or output is outside every loop, so it always runs
+    /// unless execution fails first
+    pub always_does_io: bool,
+    /// Everything the program prints, when the optimizer folded all of its
+    /// output into constants and nothing depends on input or loops
+    ///
+    /// This assumes the run does not fail part way, such as by moving off
+    /// the tape.
+    pub static_output: Option<Vec<u8>>,
+}
+
+impl Program {
+    /// Pair instructions with their positions
+    ///
+    /// # Panics
+    ///
+    /// If there is not exactly one position per instruction.
+    pub fn new(instructions: Vec<Instruction>, positions: Vec<Position>) -> Self {
+        assert_eq!(instructions.len(), positions.len(), "one position is needed per instruction");
+        Self {
+            instructions,
+            positions,
+            directives: Directives::default(),
+        }
+    }
+
+    /// Load a program from `path`, or standard input for `-`
+    ///
+    /// Files named `*.bfc`, and anything starting with the bytecode
+    /// [`MAGIC`](bytecode::MAGIC), are deserialized; anything else, such as
+    /// `*.bf` and `*.b`, is lexed and optimized with the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = Source::from_path(path);
+        Self::load_with(&source, &source.read_bytes()?, &mut Optimizer::new(), LexerConfig::default())
+    }
+
+    /// Like [`load`](Program::load) for `bytes` already read from `source`,
+    /// lexing text with `lexer_config` and optimizing it with `optimizer`
+    pub fn load_with(source: &Source, bytes: &[u8], optimizer: &mut Optimizer, lexer_config: LexerConfig) -> Result<Self> {
+        if is_bytecode(source, bytes) {
+            return Self::from_bytecode(bytes).with_context(|| format!("Failed to load bytecode from '{}'", source));
+        }
+        let mut lexer = SliceLexer::with_config(bytes, lexer_config);
+        let mut program = optimizer
+            .optimize_program(&mut lexer)
+            .with_context(|| format!("Failed to parse Brainfuck program from '{}'", source))?;
+        program.directives = lexer.directives().clone();
+        Ok(program)
+    }
+
+    /// Decode bytecode, which has no source positions to keep
+    pub fn from_bytecode(bytes: &[u8]) -> Result<Self> {
+        let instructions = bytecode::deserialize(bytes)?;
+        let positions = vec![Position::default(); instructions.len()];
+        Ok(Self::new(instructions, positions))
+    }
+
+    /// The instructions, ready for the interpreter
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Source positions, one per instruction
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    /// Source position of the instruction at `index`
+    pub fn position(&self, index: usize) -> Option<Position> {
+        self.positions.get(index).copied()
+    }
+
+    /// Number of instructions
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Whether there are no instructions at all
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Settings the source chose for itself with `!` directive lines
+    pub fn directives(&self) -> &Directives {
+        &self.directives
+    }
+
+    /// Walk the instructions alongside their source positions
+    pub fn iter_with_positions(&self) -> impl Iterator<Item = (&Instruction, Position)> + '_ {
+        self.instructions.iter().zip(self.positions.iter().copied())
+    }
+
+    /// Summarize the program without running it
+    ///
+    /// [`ProgramInfo::static_output`] is only found when the program was
+    /// optimized at [`OptLevel::Full`](crate::optimizer::OptLevel::Full)
+    /// from a known starting cell value.
+    pub fn info(&self) -> ProgramInfo {
+        let mut info = ProgramInfo {
+            instruction_count: self.instructions.len(),
+            ..Default::default()
+        };
+        let mut static_output = Some(Vec::new());
+        let mut depth = 0;
+
+        for instruction in &self.instructions {
+            match instruction {
+                Instruction::JumpForward(_) => {
+                    depth += 1;
+                    info.loop_count += 1;
+                    info.max_nesting = info.max_nesting.max(depth);
+                    static_output = None;
+                }
+                Instruction::JumpBackward(_) => depth -= 1,
+                // Loops the optimizer replaced with a single instruction
+                Instruction::SetZero
+                | Instruction::SetValue(_)
+                | Instruction::Copy { .. }
+                | Instruction::MultiplyAdd { .. }
+                | Instruction::ScanAdd { .. }
+                | Instruction::ScanRight(_)
+                | Instruction::ScanLeft(_) => {
+                    info.loop_count += 1;
+                    info.max_nesting = info.max_nesting.max(depth + 1);
+                }
+                Instruction::PrintConst(bytes) => {
+                    info.does_io = true;
+                    info.always_does_io |= depth == 0;
+                    if let Some(output) = &mut static_output {
+                        output.extend_from_slice(bytes);
+                    }
+                }
+                // Its first read happens whatever the input is
+                Instruction::EchoStream => {
+                    info.does_io = true;
+                    info.always_does_io |= depth == 0;
+                    info.loop_count += 1;
+                    info.max_nesting = info.max_nesting.max(depth + 1);
+                    static_output = None;
+                }
+                Instruction::Output(_) | Instruction::Input(_) => {
+                    info.does_io = true;
+                    info.always_does_io |= depth == 0;
+                    static_output = None;
+                }
+                // A custom command may print anything
+                Instruction::Extension(_) => static_output = None,
+                // Nothing after a halt runs, but its I/O still counts
+                Instruction::Halt => {
+                    if let Some(output) = static_output.take() {
+                        info.static_output = Some(output);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        info.static_output = info.static_output.or(static_output);
+        info
+    }
+
+    /// Drop the positions, keeping just the instructions
+    pub fn into_instructions(self) -> Vec<Instruction> {
+        self.instructions
+    }
+}
+
+/// Whether `bytes` read from `source` are compiled bytecode rather than
+/// text, judged by the `.bfc` extension or the bytecode header
+pub fn is_bytecode(source: &Source, bytes: &[u8]) -> bool {
+    source.is_bytecode() || bytecode::is_bytecode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::optimizer::{Optimizer, OptimizerConfig};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_iter_with_positions() {
+        let lexer = Lexer::new(Cursor::new("++\n> [-]\n,".as_bytes()));
+        let program = Optimizer::new().optimize_program(lexer).unwrap();
+
+        let walked: Vec<(Instruction, Position)> =
+            program.iter_with_positions().map(|(instruction, position)| (instruction.clone(), position)).collect();
+        assert_eq!(walked, [
+            (Instruction::Increment(2), Position::new(1, 1)),
+            (Instruction::MoveRight(1), Position::new(2, 1)),
+            (Instruction::SetZero, Position::new(2, 3)),
+            (Instruction::Input(1), Position::new(3, 1)),
+        ]);
+        assert_eq!(program.position(2), Some(Position::new(2, 3)));
+        assert_eq!(program.position(4), None);
+        assert_eq!(program.len(), program.instructions().len());
+    }
+
+    #[test]
+    fn test_info() {
+        let program_info = |source: &str| {
+            let mut optimizer = Optimizer::with_config(OptimizerConfig {
+                initial_cell_value: Some(0),
+                ..Default::default()
+            });
+            optimizer.optimize_program(Lexer::new(Cursor::new(source.as_bytes()))).unwrap().info()
+        };
+
+        // 'H' is 8 × 9, and 'i' 33 more
+        let info = program_info("++++++++[>+++++++++<-]>.+++++++++++++++++++++++++++++++++.");
+        assert_eq!(info.static_output.as_deref(), Some(&b"Hi"[..]));
+        assert!(info.does_io);
+        assert_eq!(info.loop_count, 1);
+        assert_eq!(info.max_nesting, 1);
+
+        // Echoed input can't be known ahead of time
+        let info = program_info("+[>,[.>]<[<]]");
+        assert_eq!(info.static_output, None);
+        assert!(info.does_io);
+        assert_eq!((info.loop_count, info.max_nesting), (3, 2));
+
+        let info = program_info("+>++[-]");
+        assert_eq!(info.static_output, Some(Vec::new()));
+        assert!(!info.does_io);
+
+        // I/O in a loop may never run
+        let info = program_info(",[.]");
+        assert!(info.does_io && info.always_does_io);
+        let info = program_info("+[>+[>+[>+[.-]<-]<-]<-]");
+        assert!(info.does_io && !info.always_does_io);
+        assert_eq!((info.loop_count, info.max_nesting), (4, 4));
+
+        // A recognised loop still counts as a nesting level
+        let info = program_info("+[>[-]<-.]");
+        assert_eq!((info.loop_count, info.max_nesting), (2, 2));
+        assert_eq!(program_info("").max_nesting, 0);
+    }
+
+    #[test]
+    fn test_load_text_and_bytecode() {
+        let dir = std::env::temp_dir().join(format!("bf_program_load_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text_path = dir.join("program.b");
+        let bytecode_path = dir.join("program.bfc");
+        std::fs::write(&text_path, "!memsize 64\n++[>+++<-]>.").unwrap();
+
+        let text = Program::load(&text_path).unwrap();
+        std::fs::write(&bytecode_path, bytecode::serialize(text.instructions())).unwrap();
+        let compiled = Program::load(&bytecode_path).unwrap();
+
+        // Bytecode is detected by its header too, whatever the file is named
+        std::fs::rename(&bytecode_path, &text_path).unwrap();
+        let sniffed = Program::load(&text_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(compiled.instructions(), text.instructions());
+        assert_eq!(sniffed.instructions(), text.instructions());
+        assert_eq!(compiled.position(0), Some(Position::default()));
+        assert_eq!(text.directives().memory_size, Some(64));
+        assert_eq!(compiled.directives().memory_size, None);
+    }
+}
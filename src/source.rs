@@ -0,0 +1,155 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// The path argument that selects standard input instead of a file
+pub const STDIN_PATH: &str = "-";
+
+/// The first two bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Where the Brainfuck program source is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Read the program from standard input
+    Stdin,
+    /// Read the program from a file on disk
+    File(PathBuf),
+}
+
+impl Source {
+    /// Resolve a command line path, treating `-` as standard input
+    pub fn from_path(path: &Path) -> Self {
+        if path.as_os_str() == STDIN_PATH {
+            Self::Stdin
+        } else {
+            Self::File(path.to_path_buf())
+        }
+    }
+
+    /// Open the source for reading
+    ///
+    /// Files ending in `.gz` and any source starting with the gzip magic bytes
+    /// are decompressed as they are read, which needs the `gzip` feature.
+    pub fn open(&self) -> Result<Box<dyn Read>> {
+        match self {
+            Self::Stdin => self.decompress_if_gzipped(BufReader::new(io::stdin()), false),
+            Self::File(path) => {
+                let file = File::open(path)
+                    .with_context(|| format!("Failed to open file '{}'", path.display()))?;
+                let gz_extension = path.extension().is_some_and(|extension| extension == "gz");
+                self.decompress_if_gzipped(BufReader::new(file), gz_extension)
+            }
+        }
+    }
+
+    /// Wrap `reader` in a gzip decoder if `gzipped` is set or the data starts
+    /// with the gzip magic bytes
+    fn decompress_if_gzipped<B: BufRead + 'static>(&self, mut reader: B, gzipped: bool) -> Result<Box<dyn Read>> {
+        let peeked = reader.fill_buf().with_context(|| format!("Failed to read '{}'", self))?;
+        if !gzipped && !peeked.starts_with(&GZIP_MAGIC) {
+            return Ok(Box::new(reader));
+        }
+
+        #[cfg(feature = "gzip")]
+        {
+            Ok(Box::new(flate2::bufread::GzDecoder::new(reader)))
+        }
+        #[cfg(not(feature = "gzip"))]
+        {
+            anyhow::bail!("'{}' is gzip-compressed; rebuild with the `gzip` feature to read it", self)
+        }
+    }
+
+    /// Whether the source is a file of compiled bytecode, named `*.bfc`
+    pub fn is_bytecode(&self) -> bool {
+        matches!(self, Self::File(path) if path.extension().is_some_and(|extension| extension == "bfc"))
+    }
+
+    /// Read the whole source as raw bytes
+    pub fn read_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.open()?
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("Failed to read '{}'", self))?;
+        Ok(bytes)
+    }
+
+    /// Read the whole source, replacing invalid UTF-8 like the lexer does
+    pub fn read_to_string(&self) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.read_bytes()?).into_owned())
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdin => write!(f, "<stdin>"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dash_routes_to_stdin() {
+        assert_eq!(Source::from_path(Path::new("-")), Source::Stdin);
+        assert_eq!(Source::Stdin.to_string(), "<stdin>");
+    }
+
+    #[test]
+    fn test_path_routes_to_file() {
+        let source = Source::from_path(Path::new("hello.bf"));
+        assert_eq!(source, Source::File(PathBuf::from("hello.bf")));
+        assert_eq!(source.to_string(), "hello.bf");
+        assert!(!source.is_bytecode());
+        assert!(Source::from_path(Path::new("hello.bfc")).is_bytecode());
+    }
+
+    #[test]
+    fn test_open_missing_file_fails() {
+        let source = Source::from_path(Path::new("does/not/exist.bf"));
+        assert!(source.open().is_err());
+    }
+
+    /// Write `contents` to a file in the system temp directory unique to this test
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bf-source-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzipped_source_reads_like_plaintext() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"+++.").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // Detected by the extension and, without it, by the magic bytes
+        for name in ["program.bf.gz", "program.bf"] {
+            let path = temp_file(name, &compressed);
+            let source = Source::from_path(&path);
+            assert_eq!(source.read_to_string().unwrap(), "+++.");
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_gzipped_source_needs_feature() {
+        let path = temp_file("needs_feature.bf", &[0x1f, 0x8b, 0x08, 0x00]);
+        let error = Source::from_path(&path).open().err().unwrap();
+        assert!(error.to_string().contains("rebuild with the `gzip` feature"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
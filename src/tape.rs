@@ -0,0 +1,184 @@
+//! The interpreter's memory tape, stored densely or sparsely.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::{Index, IndexMut};
+
+/// How the memory tape is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(clap::ValueEnum))]
+pub enum TapeKind {
+    /// One byte per cell, allocated up front
+    #[default]
+    Dense,
+    /// Only cells that have been written, so huge tapes cost almost nothing
+    Sparse,
+}
+
+/// A fixed-length tape of byte cells
+///
+/// Both representations index the same way; reading an unwritten cell of a
+/// sparse tape gives the fill value without storing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tape {
+    /// Every cell stored contiguously
+    Dense(Vec<u8>),
+    /// Cells that have been written, with every other cell holding `fill`
+    Sparse {
+        cells: HashMap<usize, u8>,
+        fill: u8,
+        len: usize,
+    },
+}
+
+impl Tape {
+    /// Create a tape of `len` cells all holding `fill`
+    pub fn new(kind: TapeKind, len: usize, fill: u8) -> Self {
+        match kind {
+            TapeKind::Dense => Self::Dense(vec![fill; len]),
+            TapeKind::Sparse => Self::Sparse {
+                cells: HashMap::new(),
+                fill,
+                len,
+            },
+        }
+    }
+
+    /// Number of cells on the tape
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Dense(cells) => cells.len(),
+            Self::Sparse { len, .. } => *len,
+        }
+    }
+
+    /// Whether the tape has no cells at all
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read the cell at `address`, or `None` if it is outside the tape
+    pub fn get(&self, address: usize) -> Option<u8> {
+        (address < self.len()).then(|| self[address])
+    }
+
+    /// Set every cell to `value`
+    pub fn fill(&mut self, value: u8) {
+        match self {
+            Self::Dense(cells) => cells.fill(value),
+            Self::Sparse { cells, fill, .. } => {
+                cells.clear();
+                *fill = value;
+            }
+        }
+    }
+
+    /// The cells as a contiguous slice, which only a dense tape has
+    pub fn as_slice(&self) -> Option<&[u8]> {
+        match self {
+            Self::Dense(cells) => Some(cells),
+            Self::Sparse { .. } => None,
+        }
+    }
+
+    /// Whether both tapes hold the same values in every cell, however each
+    /// is stored
+    ///
+    /// Unlike `==`, a sparse cell written back to the fill value counts the
+    /// same as one never written.
+    pub fn same_cells(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        match (self, other) {
+            (Self::Dense(cells), Self::Dense(others)) => cells == others,
+            (Self::Sparse { cells, fill, len }, Self::Sparse { cells: others, fill: other_fill, .. }) => {
+                let written: HashSet<usize> = cells.keys().chain(others.keys()).copied().collect();
+                (fill == other_fill || written.len() == *len) && written.iter().all(|&address| self[address] == other[address])
+            }
+            _ => (0..self.len()).all(|address| self[address] == other[address]),
+        }
+    }
+
+    /// Number of cells actually held in memory
+    pub fn allocated(&self) -> usize {
+        match self {
+            Self::Dense(cells) => cells.len(),
+            Self::Sparse { cells, .. } => cells.len(),
+        }
+    }
+}
+
+impl Index<usize> for Tape {
+    type Output = u8;
+
+    fn index(&self, address: usize) -> &u8 {
+        match self {
+            Self::Dense(cells) => &cells[address],
+            Self::Sparse { cells, fill, len } => {
+                assert!(address < *len, "address {} is outside a tape of {} cells", address, len);
+                cells.get(&address).unwrap_or(fill)
+            }
+        }
+    }
+}
+
+impl IndexMut<usize> for Tape {
+    fn index_mut(&mut self, address: usize) -> &mut u8 {
+        match self {
+            Self::Dense(cells) => &mut cells[address],
+            Self::Sparse { cells, fill, len } => {
+                assert!(address < *len, "address {} is outside a tape of {} cells", address, len);
+                cells.entry(address).or_insert(*fill)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_reads_fill_without_storing() {
+        let mut tape = Tape::new(TapeKind::Sparse, usize::MAX, 7);
+        assert_eq!(tape[1_000_000], 7);
+        assert_eq!(tape.allocated(), 0);
+
+        tape[1_000_000] += 1;
+        assert_eq!(tape.get(1_000_000), Some(8));
+        assert_eq!(tape.allocated(), 1);
+        assert_eq!(tape.as_slice(), None);
+
+        tape.fill(0);
+        assert_eq!(tape[1_000_000], 0);
+        assert_eq!(tape.allocated(), 0);
+    }
+
+    #[test]
+    fn test_dense_and_sparse_agree() {
+        let mut dense = Tape::new(TapeKind::Dense, 4, 1);
+        let mut sparse = Tape::new(TapeKind::Sparse, 4, 1);
+        for tape in [&mut dense, &mut sparse] {
+            tape[2] = 9;
+            assert_eq!(tape.len(), 4);
+            assert_eq!((0..4).map(|address| tape[address]).collect::<Vec<_>>(), [1, 1, 9, 1]);
+            assert_eq!(tape.get(4), None);
+        }
+        assert_eq!(dense.as_slice(), Some(&[1, 1, 9, 1][..]));
+        assert!(dense.same_cells(&sparse));
+    }
+
+    #[test]
+    fn test_same_cells_ignores_storage() {
+        let untouched = Tape::new(TapeKind::Sparse, 100, 0);
+        let mut restored = untouched.clone();
+        restored[50] = 0;
+        assert_ne!(restored, untouched);
+        assert!(restored.same_cells(&untouched));
+
+        restored[50] = 1;
+        assert!(!restored.same_cells(&untouched));
+        assert!(!untouched.same_cells(&Tape::new(TapeKind::Sparse, 100, 1)));
+        assert!(!untouched.same_cells(&Tape::new(TapeKind::Dense, 99, 0)));
+    }
+}
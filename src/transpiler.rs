@@ -0,0 +1,406 @@
+use crate::error::Position;
+use crate::interpreter::{EofBehavior, InterpreterConfig};
+use crate::optimizer::Instruction;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Translate optimized instructions into a standalone C program
+///
+/// The tape size, initial cell value and end-of-input behaviour are taken
+/// from `config`. Loops and instructions recognised from loops are annotated
+/// with `/* bf line:column */` comments pointing back at the Brainfuck source
+/// when `positions` has an entry for them. The generated code does not check
+/// tape bounds, always writes raw bytes, reduces debug dumps to the pointer
+/// and current cell and leaves custom commands as comments.
+pub fn to_c(instructions: &[Instruction], positions: &[Position], config: &InterpreterConfig) -> String {
+    let mut code = String::new();
+    code.push_str("#include <stdio.h>\n#include <string.h>\n\n");
+    let _ = writeln!(code, "static unsigned char tape[{}];\n", config.memory_size);
+    code.push_str("int main(void) {\n");
+    code.push_str("    unsigned char *p = tape;\n");
+    code.push_str("    int c;\n");
+    code.push_str("    unsigned char reg = 0;\n");
+    if config.initial_cell_value != 0 {
+        let _ = writeln!(code, "    memset(tape, {}, sizeof tape);", config.initial_cell_value);
+    }
+
+    let mut depth = 1;
+    for (index, instruction) in instructions.iter().enumerate() {
+        let source_map = positions
+            .get(index)
+            .map(|position| format!(" /* bf {} */", position))
+            .unwrap_or_default();
+
+        if let Instruction::JumpBackward(_) = instruction {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+
+        match instruction {
+            Instruction::MoveRight(n) => {
+                let _ = writeln!(code, "{}p += {};", indent, n);
+            }
+            Instruction::MoveLeft(n) => {
+                let _ = writeln!(code, "{}p -= {};", indent, n);
+            }
+            Instruction::Increment(n) => match config.cell_modulus {
+                Some(m) => {
+                    let _ = writeln!(code, "{}*p = (*p + {}) % {};", indent, n, m);
+                }
+                None => {
+                    let _ = writeln!(code, "{}*p += {};", indent, n);
+                }
+            },
+            Instruction::Decrement(n) => match config.cell_modulus {
+                Some(m) => {
+                    let _ = writeln!(code, "{}*p = (*p + {}) % {};", indent, m - u32::from(*n) % m, m);
+                }
+                None => {
+                    let _ = writeln!(code, "{}*p -= {};", indent, n);
+                }
+            },
+            Instruction::Output(n) => {
+                for _ in 0..*n {
+                    let _ = writeln!(code, "{}putchar(*p);", indent);
+                }
+            }
+            Instruction::Input(n) => {
+                for _ in 0..*n {
+                    let _ = writeln!(code, "{}c = getchar();", indent);
+                    let _ = writeln!(code, "{}{}", indent, eof_statement(config.eof_behavior));
+                }
+            }
+            Instruction::JumpForward(_) => {
+                let _ = writeln!(code, "{}while (*p) {{{}", indent, source_map);
+                depth += 1;
+            }
+            Instruction::JumpBackward(_) => {
+                let _ = writeln!(code, "{}}}", indent);
+            }
+            Instruction::SetZero => {
+                let _ = writeln!(code, "{}*p = 0;{}", indent, source_map);
+            }
+            Instruction::SetValue(value) => {
+                let _ = writeln!(code, "{}*p = {};{}", indent, value, source_map);
+            }
+            Instruction::Copy { targets } => {
+                for offset in targets {
+                    let _ = writeln!(code, "{}p[{}] += *p;{}", indent, offset, source_map);
+                }
+                let _ = writeln!(code, "{}*p = 0;", indent);
+            }
+            Instruction::MultiplyAdd { targets } => {
+                for (offset, factor) in targets {
+                    let _ = writeln!(code, "{}p[{}] += *p * {};{}", indent, offset, factor, source_map);
+                }
+                let _ = writeln!(code, "{}*p = 0;", indent);
+            }
+            Instruction::AddAtOffset { offset, value } => {
+                let _ = writeln!(code, "{}p[{}] += {};", indent, offset, value);
+            }
+            Instruction::SetAtOffset { offset, value } => {
+                let _ = writeln!(code, "{}p[{}] = {};", indent, offset, value);
+            }
+            Instruction::ScanAdd { stride, adds } => {
+                let _ = writeln!(code, "{}while (*p) {{{}", indent, source_map);
+                for (offset, value) in adds {
+                    let _ = writeln!(code, "{}    p[{}] += {};", indent, offset, value);
+                }
+                let _ = writeln!(code, "{}    p += {};", indent, stride);
+                let _ = writeln!(code, "{}}}", indent);
+            }
+            Instruction::ScanRight(n) => {
+                let _ = writeln!(code, "{}while (*p) p += {};{}", indent, n, source_map);
+            }
+            Instruction::ScanLeft(n) => {
+                let _ = writeln!(code, "{}while (*p) p -= {};{}", indent, n, source_map);
+            }
+            Instruction::EchoStream => {
+                let read = eof_statement(config.eof_behavior);
+                let _ = writeln!(code, "{}c = getchar();{}", indent, source_map);
+                let _ = writeln!(code, "{}{}", indent, read);
+                let _ = writeln!(code, "{}while (*p) {{", indent);
+                let _ = writeln!(code, "{}    putchar(*p);", indent);
+                let _ = writeln!(code, "{}    c = getchar();", indent);
+                let _ = writeln!(code, "{}    {}", indent, read);
+                let _ = writeln!(code, "{}}}", indent);
+            }
+            Instruction::DebugDump => {
+                let _ = writeln!(code, "{}fprintf(stderr, \"%ld: [%d]\\n\", (long)(p - tape), *p);", indent);
+            }
+            Instruction::Halt => {
+                let _ = writeln!(code, "{}return 0;", indent);
+            }
+            Instruction::StoreRegister => {
+                let _ = writeln!(code, "{}reg = *p;", indent);
+            }
+            Instruction::RestoreRegister => {
+                let _ = writeln!(code, "{}*p = reg;", indent);
+            }
+            Instruction::SwitchInput => {
+                let _ = writeln!(code, "{}/* no C translation for switching input */", indent);
+            }
+            Instruction::Nop => {}
+            Instruction::Extension(c) => {
+                let _ = writeln!(code, "{}/* no C translation for custom command {:?} */", indent, c);
+            }
+            Instruction::PrintConst(bytes) => {
+                let literal: String = bytes.iter().map(|byte| format!("\\x{:02x}", byte)).collect();
+                let _ = writeln!(code, "{}fwrite(\"{}\", 1, {}, stdout);", indent, literal, bytes.len());
+            }
+        }
+    }
+
+    code.push_str("    return 0;\n}\n");
+    code
+}
+
+/// Reconstruct canonical Brainfuck source equivalent to optimized instructions
+///
+/// Runs become repeated commands and recognised loops are written back out
+/// as loops, e.g. `SetZero` as `[-]`. `PrintConst` only ever replaces output
+/// of a cell already holding those bytes, so it becomes plain `.` commands.
+/// Extensions use their default characters, with `@` for halts.
+pub fn instructions_to_source(instructions: &[Instruction]) -> String {
+    let mut source = String::new();
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::MoveRight(n) => source.push_str(&">".repeat(*n)),
+            Instruction::MoveLeft(n) => source.push_str(&"<".repeat(*n)),
+            Instruction::Increment(n) => source.push_str(&"+".repeat(usize::from(*n))),
+            Instruction::Decrement(n) => source.push_str(&"-".repeat(usize::from(*n))),
+            Instruction::Output(n) => source.push_str(&".".repeat(*n)),
+            Instruction::Input(n) => source.push_str(&",".repeat(*n)),
+            Instruction::JumpForward(_) => source.push('['),
+            Instruction::JumpBackward(_) => source.push(']'),
+            Instruction::SetZero => source.push_str("[-]"),
+            Instruction::SetValue(value) => {
+                source.push_str("[-]");
+                push_adds(&mut source, &[(0, *value)], 0);
+            }
+            Instruction::Copy { targets } => {
+                let adds: Vec<(isize, u8)> = targets.iter().map(|&offset| (offset, 1)).collect();
+                source.push_str("[-");
+                push_adds(&mut source, &adds, 0);
+                source.push(']');
+            }
+            Instruction::MultiplyAdd { targets } => {
+                source.push_str("[-");
+                push_adds(&mut source, targets, 0);
+                source.push(']');
+            }
+            Instruction::AddAtOffset { offset, value } => push_adds(&mut source, &[(*offset, *value)], 0),
+            Instruction::SetAtOffset { offset, value } => {
+                push_move(&mut source, *offset);
+                source.push_str("[-]");
+                push_adds(&mut source, &[(0, *value)], -offset);
+            }
+            Instruction::ScanAdd { stride, adds } => {
+                source.push('[');
+                push_adds(&mut source, adds, *stride);
+                source.push(']');
+            }
+            Instruction::ScanRight(n) => source.push_str(&format!("[{}]", ">".repeat(*n))),
+            Instruction::ScanLeft(n) => source.push_str(&format!("[{}]", "<".repeat(*n))),
+            Instruction::PrintConst(bytes) => source.push_str(&".".repeat(bytes.len())),
+            Instruction::EchoStream => source.push_str(",[.,]"),
+            Instruction::DebugDump => source.push('#'),
+            Instruction::Halt => source.push('@'),
+            Instruction::StoreRegister => source.push('$'),
+            Instruction::RestoreRegister => source.push('}'),
+            Instruction::SwitchInput => source.push('~'),
+            Instruction::Nop => {}
+            Instruction::Extension(c) => source.push(*c),
+        }
+    }
+
+    source
+}
+
+/// Draw the control flow of optimized instructions as a GraphViz digraph
+///
+/// Each node is a basic block named after the index of its first
+/// instruction, e.g. `b4`. A block ends at every jump, and a new one starts
+/// at every `[` so the `]` that repeats it has somewhere to go. Edges out of
+/// a jump are labelled with the cell value that takes them, and the edge
+/// back from `]` is dashed.
+pub fn to_dot(instructions: &[Instruction]) -> String {
+    let mut leaders = BTreeSet::from([0]);
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::JumpForward(_) => leaders.extend([index, index + 1]),
+            Instruction::JumpBackward(_) | Instruction::Halt => {
+                leaders.insert(index + 1);
+            }
+            _ => {}
+        }
+    }
+    leaders.retain(|&index| index < instructions.len());
+    let node = |index: usize| if index < instructions.len() { format!("b{}", index) } else { "end".to_string() };
+
+    let mut dot = String::from("digraph program {\n");
+    dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+    dot.push_str("    start [shape=point];\n");
+    dot.push_str("    end [shape=doublecircle, label=\"end\"];\n");
+    let _ = writeln!(dot, "    start -> {};", node(0));
+
+    let starts: Vec<usize> = leaders.into_iter().collect();
+    for (block, &start) in starts.iter().enumerate() {
+        let end = starts.get(block + 1).copied().unwrap_or(instructions.len());
+        let label: String = instructions[start..end]
+            .iter()
+            .map(|instruction| format!("{:?}\\l", instruction).replace('"', "\\\""))
+            .collect();
+        let _ = writeln!(dot, "    b{} [label=\"{}\"];", start, label);
+
+        let last = end - 1;
+        match instructions[last] {
+            Instruction::JumpForward(close) => {
+                let _ = writeln!(dot, "    b{} -> {} [label=\"nonzero\"];", start, node(end));
+                let _ = writeln!(dot, "    b{} -> {} [label=\"zero\"];", start, node(close + 1));
+            }
+            Instruction::JumpBackward(open) => {
+                let _ = writeln!(dot, "    b{} -> b{} [label=\"nonzero\", style=dashed];", start, open);
+                let _ = writeln!(dot, "    b{} -> {} [label=\"zero\"];", start, node(end));
+            }
+            Instruction::Halt => {
+                let _ = writeln!(dot, "    b{} -> end;", start);
+            }
+            _ => {
+                let _ = writeln!(dot, "    b{} -> {};", start, node(end));
+            }
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// Add each value to the cell at its offset, then move to offset `end`
+fn push_adds(source: &mut String, adds: &[(isize, u8)], end: isize) {
+    let mut offset = 0;
+    for &(target, value) in adds {
+        push_move(source, target - offset);
+        offset = target;
+        // Values past 128 are shorter written as subtraction
+        if value > 128 {
+            source.push_str(&"-".repeat(usize::from(value.wrapping_neg())));
+        } else {
+            source.push_str(&"+".repeat(usize::from(value)));
+        }
+    }
+    push_move(source, end - offset);
+}
+
+/// Move the pointer by `delta` cells
+fn push_move(source: &mut String, delta: isize) {
+    let command = if delta < 0 { "<" } else { ">" };
+    source.push_str(&command.repeat(delta.unsigned_abs()));
+}
+
+/// The C statement that stores the byte just read into `c`, honouring EOF
+fn eof_statement(eof_behavior: EofBehavior) -> String {
+    match eof_behavior {
+        EofBehavior::Error => {
+            "if (c == EOF) { fputs(\"unexpected end of input\\n\", stderr); return 1; } *p = c;".to_string()
+        }
+        EofBehavior::Unchanged => "if (c != EOF) *p = c;".to_string(),
+        EofBehavior::Zero => "*p = c == EOF ? 0 : c;".to_string(),
+        EofBehavior::NegativeOne => "*p = c == EOF ? 255 : c;".to_string(),
+        EofBehavior::Value(value) => format!("*p = c == EOF ? {} : c;", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::optimizer::Optimizer;
+    use std::io::Cursor;
+
+    fn transpile(input: &str) -> String {
+        let cursor = Cursor::new(input.as_bytes());
+        let lexer = Lexer::new(cursor);
+        let mut optimizer = Optimizer::new();
+        let instructions = optimizer.optimize(lexer).unwrap();
+        to_c(&instructions, optimizer.positions(), &InterpreterConfig::default())
+    }
+
+    #[test]
+    fn test_loop_source_map() {
+        let code = transpile("+++\n  [.-]");
+        assert!(code.contains("    while (*p) { /* bf 2:3 */\n"));
+        assert!(code.contains("        putchar(*p);\n"));
+        assert!(code.contains("    }\n"));
+    }
+
+    #[test]
+    fn test_recognised_loop_source_map() {
+        let code = transpile("+\n>+<[->++<]");
+        assert!(code.contains("p[1] += *p * 2; /* bf 2:4 */"));
+        assert!(code.contains("*p = 0;"));
+    }
+
+    fn optimize(input: &str) -> Vec<Instruction> {
+        Optimizer::new().optimize(Lexer::new(Cursor::new(input.as_bytes()))).unwrap()
+    }
+
+    #[test]
+    fn test_dot_single_loop() {
+        let dot = to_dot(&optimize("[.-]"));
+        let nodes: Vec<&str> = dot.lines().filter(|line| line.starts_with("    b") && !line.contains("->")).collect();
+        assert_eq!(nodes, ["    b0 [label=\"JumpForward(3)\\l\"];", "    b1 [label=\"Output(1)\\lDecrement(1)\\lJumpBackward(0)\\l\"];"]);
+        assert!(dot.contains("    b0 -> b1 [label=\"nonzero\"];\n"));
+        assert!(dot.contains("    b1 -> b0 [label=\"nonzero\", style=dashed];\n"));
+        assert!(dot.contains("    b0 -> end [label=\"zero\"];\n"));
+        assert!(dot.contains("    b1 -> end [label=\"zero\"];\n"));
+    }
+
+    #[test]
+    fn test_dot_nested_loops() {
+        let dot = to_dot(&optimize("+[>,[.>]<-]."));
+        // Skipping or leaving either loop lands on the block after it
+        assert!(dot.contains("    b1 -> b11 [label=\"zero\"];\n"));
+        assert!(dot.contains("    b4 -> b8 [label=\"zero\"];\n"));
+        assert!(dot.contains("    b5 -> b4 [label=\"nonzero\", style=dashed];\n"));
+        assert!(dot.contains("    b8 -> b1 [label=\"nonzero\", style=dashed];\n"));
+        assert!(dot.contains("    b11 -> end;\n"));
+        assert_eq!(to_dot(&[]).lines().filter(|line| line.contains("->")).collect::<Vec<_>>(), ["    start -> end;"]);
+    }
+
+    #[test]
+    fn test_set_zero_round_trip() {
+        let instructions = optimize("[-]");
+        let source = instructions_to_source(&instructions);
+        assert_eq!(source, "[-]");
+        assert_eq!(optimize(&source), instructions);
+    }
+
+    #[test]
+    fn test_recognised_loops_round_trip() {
+        for input in ["+++[->+>++<<]", "+[->>+<<]", "+[->-<]", "+[>+>]", "+[>+>+<]>.,[-]", ",[-]+++."] {
+            let instructions = optimize(input);
+            assert_eq!(optimize(&instructions_to_source(&instructions)), instructions, "{}", input);
+        }
+        assert_eq!(instructions_to_source(&optimize("+[->-<]")), "+[->-<]");
+    }
+
+    #[test]
+    fn test_offset_instructions_to_source() {
+        let instructions = [
+            Instruction::AddAtOffset { offset: 2, value: 3 },
+            Instruction::SetAtOffset { offset: -1, value: 255 },
+        ];
+        assert_eq!(instructions_to_source(&instructions), ">>+++<<<[-]->");
+    }
+
+    #[test]
+    fn test_program_skeleton() {
+        let code = transpile(",");
+        assert!(code.contains("static unsigned char tape[30000];"));
+        assert!(code.contains("int main(void) {"));
+        assert!(code.contains("c = getchar();"));
+        assert!(code.ends_with("    return 0;\n}\n"));
+    }
+}
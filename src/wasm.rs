@@ -0,0 +1,67 @@
+//! In-memory entry points for embedding the interpreter, e.g. in a browser.
+//!
+//! Nothing here touches the filesystem or the process's stdin/stdout: the
+//! program and its input are strings, and the output is collected into a
+//! buffer. The `wasm` feature adds a `wasm-bindgen` wrapper on top.
+
+use crate::interpreter::{EofBehavior, Interpreter, InterpreterConfig};
+use crate::lexer::Lexer;
+use crate::optimizer::Optimizer;
+use anyhow::Result;
+use std::io::Cursor;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Run a program against `input` and return everything it printed.
+///
+/// Reads past the end of `input` yield 0, since there is no terminal to wait on.
+/// Output bytes that are not valid UTF-8 are replaced with U+FFFD.
+pub fn run_to_string(source: &str, input: &str) -> Result<String> {
+    let lexer = Lexer::new(Cursor::new(source.as_bytes()));
+    let instructions = Optimizer::new().optimize(lexer)?;
+
+    let config = InterpreterConfig {
+        eof_behavior: EofBehavior::Zero,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_io(
+        instructions,
+        config,
+        Cursor::new(input.as_bytes()),
+        &mut output,
+    );
+    interpreter.run()?;
+
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// JavaScript binding for [`run_to_string`]; errors become JS strings
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn run_wasm(source: &str, input: &str) -> Result<String, JsValue> {
+    run_to_string(source, input).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_to_string_hello() {
+        let program = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        assert_eq!(run_to_string(program, "").unwrap(), "Hello World!\n");
+    }
+
+    #[test]
+    fn test_run_to_string_echoes_input() {
+        assert_eq!(run_to_string(",[.,]", "abc").unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_run_to_string_reports_errors() {
+        let err = run_to_string("[", "").unwrap_err();
+        assert!(err.to_string().contains("Unmatched bracket"));
+    }
+}
@@ -0,0 +1,584 @@
+//! End-to-end tests running the compiled command line interpreter.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Write `contents` to a file in the system temp directory unique to this test
+fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("bf-cli-{}-{}", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Run the interpreter binary with `args`
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_brainfuck-interpreter"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run the interpreter binary with `args`, writing `input` to its stdin
+fn run_with_stdin(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck-interpreter"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_input_file_feeds_reads_in_order() {
+    let program = temp_file("echo3.bf", b",.,.,.");
+    let input = temp_file("echo3.txt", b"xyz");
+
+    let output = run(&["--input", input.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"xyz");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn test_missing_input_file_fails() {
+    let program = temp_file("missing_input.bf", b",.");
+
+    let output = run(&["--input", "does/not/exist.txt", program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to open input file"));
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_unmatched_bracket_points_at_source() {
+    let program = temp_file("unmatched.bf", b"++\n+[>+\n");
+
+    let output = run(&[program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: Unmatched bracket at position 2:2"));
+    assert!(stderr.contains("2 | +[>+\n  |  ^\n"));
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_runtime_error_points_at_source() {
+    let program = temp_file("underflow.bf", b"+\n+ <");
+
+    let output = run(&[program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 | + <\n  |   ^\n"));
+
+    fs::remove_file(program).unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzipped_program_runs_like_plaintext() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"++++++++[>++++++++<-]>+.").unwrap();
+    let plain = temp_file("plain.bf", b"++++++++[>++++++++<-]>+.");
+    let gzipped = temp_file("gzipped.bf.gz", &encoder.finish().unwrap());
+
+    let expected = run(&[plain.to_str().unwrap()]);
+    let output = run(&[gzipped.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+    assert_eq!(output.stdout, expected.stdout);
+
+    fs::remove_file(plain).unwrap();
+    fs::remove_file(gzipped).unwrap();
+}
+
+#[cfg(feature = "bignum")]
+#[test]
+fn test_bignum_honours_run_limits_and_loaded_tape() {
+    let endless = temp_file("bignum_endless.bf", b"+[]");
+    let output = run(&["--bignum", "--timeout", "0.1", endless.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("execution exceeded the 100ms timeout"));
+
+    let program = temp_file("bignum_cells.bf", b".>.");
+    let memory = temp_file("bignum_cells.bin", b"AB");
+    let output = run(&["--bignum", "--load-memory", memory.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AB");
+
+    // Failures point into the source
+    let underflow = temp_file("bignum_underflow.bf", b"+\n+ <");
+    let output = run(&["--bignum", underflow.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 | + <\n  |   ^\n"));
+
+    // Options it can't honour are refused rather than ignored
+    let output = run(&["--bignum", "--stats", program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+
+    for path in [endless, program, memory, underflow] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_analyze_reports_without_running() {
+    let program = temp_file("analyze.bf", b"+[>+[.-]<-],");
+
+    let output = run(&["--analyze", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    // The `,` would fail at the end of input if the program ran
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Loops: 2\nMaximum loop nesting depth: 2\nPerforms I/O: always\n"), "{}", stderr);
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_pad_output_fills_record() {
+    let program = temp_file("three_bytes.bf", b"+.+.+.");
+
+    let output = run(&["--pad-output", "4", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [1, 2, 3, 0]);
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_verify_against_expected_output() {
+    let program = temp_file("verify.bf", b"++++++++[>++++++++<-]>+.+.");
+    let matching = temp_file("verify_match.txt", b"AB");
+    let mismatching = temp_file("verify_mismatch.txt", b"AC");
+
+    let output = run(&["--verify", matching.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = run(&["--verify", mismatching.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("First difference at byte 1: expected 'C'"));
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(matching).unwrap();
+    fs::remove_file(mismatching).unwrap();
+}
+
+#[test]
+fn test_memsize_directive_sets_tape_size() {
+    let source = format!("!memsize 100\n{}", ">".repeat(100));
+    let program = temp_file("memsize.bf", source.as_bytes());
+
+    let output = run(&[program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("address 100 exceeds tape size 100"));
+
+    // The command line overrides the directive
+    let output = run(&["--memory-size", "200", program.to_str().unwrap()]);
+    assert!(output.status.success());
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_unknown_directive_line_still_runs() {
+    let program = temp_file("unknown_directive.bf", b"!hello +++.");
+
+    let output = run(&[program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_output_file_receives_program_output() {
+    let program = temp_file("to_file.bf", b"++++++++[>++++++++<-]>+.+.");
+    let output_path = std::env::temp_dir().join(format!("bf-cli-{}-to_file.out", std::process::id()));
+    fs::write(&output_path, b"stale contents").unwrap();
+
+    let output = run(&["--output", output_path.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(fs::read(&output_path).unwrap(), b"AB");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(output_path).unwrap();
+}
+
+#[test]
+fn test_compiled_bytecode_runs_like_source() {
+    let program = temp_file("compile.bf", b"++++++++[>++++++++<-]>+.+.");
+    let compiled = std::env::temp_dir().join(format!("bf-cli-{}-compile.bfc", std::process::id()));
+
+    let output = run(&["--compile", compiled.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = run(&[compiled.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AB");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(compiled).unwrap();
+}
+
+#[test]
+fn test_compiled_bytecode_reads_loaded_tape() {
+    let program = temp_file("compile_cells.bf", b".>.");
+    let memory = temp_file("compile_cells.bin", b"AB");
+    let compiled = std::env::temp_dir().join(format!("bf-cli-{}-compile_cells.bfc", std::process::id()));
+
+    let output = run(&["--compile", compiled.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+
+    // The zeroed tape the program was compiled with is not folded in
+    let output = run(&["--load-memory", memory.to_str().unwrap(), compiled.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AB");
+    let output = run(&["--fill", "67", compiled.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"CC");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(memory).unwrap();
+    fs::remove_file(compiled).unwrap();
+}
+
+#[test]
+fn test_old_bytecode_is_compiled_again() {
+    // Version 2 bytecode for `.`, which may have the fill value folded in
+    let compiled = temp_file("old_version.bfc", b"BFC\x02\x04\x01");
+    let memory = temp_file("old_version.bin", b"A");
+
+    let output = run(&["--load-memory", memory.to_str().unwrap(), compiled.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unsupported bytecode version 2; compile the program again"));
+
+    fs::remove_file(compiled).unwrap();
+    fs::remove_file(memory).unwrap();
+}
+
+#[test]
+fn test_memory_snapshot_holds_final_tape() {
+    let program = temp_file("snapshot.bf", b"+++");
+    let snapshot = std::env::temp_dir().join(format!("bf-cli-{}-snapshot.bin", std::process::id()));
+
+    let output = run(&["--memory-size", "16", "--memory-snapshot", snapshot.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    let cells = fs::read(&snapshot).unwrap();
+    assert_eq!(cells.len(), 16);
+    assert_eq!(cells[0], 3);
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(snapshot).unwrap();
+}
+
+#[test]
+fn test_load_memory_seeds_tape() {
+    let program = temp_file("print_cells.bf", b".>.>>.");
+    let memory = temp_file("tape.bin", b"AB\0C");
+
+    let output = run(&["--load-memory", memory.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    // Nothing about the tape may be assumed at optimize time
+    assert_eq!(output.stdout, b"ABC");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(memory).unwrap();
+}
+
+#[test]
+fn test_program_read_from_stdin() {
+    let output = run_with_stdin(&["-"], b"++++++++[>++++++++<-]>+.");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+}
+
+#[test]
+fn test_timeout_stops_runaway_program() {
+    let program = temp_file("timeout.bf", b"+[]");
+    let output = run(&["--timeout", "0.1", program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("execution exceeded the 100ms timeout"));
+
+    // A program echoing input that never ends is stopped too
+    let echo = temp_file("timeout_echo.bf", b",[.,]");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck-interpreter"))
+        .args(["--timeout", "0.1", echo.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let feeder = std::thread::spawn(move || while stdin.write_all(&[b'y'; 4096]).is_ok() {});
+    let output = child.wait_with_output().unwrap();
+    feeder.join().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("execution exceeded the 100ms timeout"));
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(echo).unwrap();
+}
+
+#[test]
+fn test_output_format_renders_output() {
+    let program = temp_file("format.bf", b"++++++++[>++++++++<-]>+.+.[-]+.");
+
+    let output = run(&["--output-format", "hex", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "00000000  41 42 01                                          |AB.|\n"
+    );
+
+    let output = run(&["--output-format", "escaped", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AB\\x01");
+
+    // `--escape-output` is short for the escaped format
+    let control = temp_file("escape_output.bf", b"+.");
+    let output = run(&["--escape-output", control.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\\x01");
+    let output = run(&["--escape-output", "--output-format", "hex", control.to_str().unwrap()]);
+    assert!(!output.status.success());
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(control).unwrap();
+}
+
+#[test]
+fn test_second_input_switches_with_tilde() {
+    let program = temp_file("switch.bf", b",.~,.~,.~,.");
+    let first = temp_file("switch_first.txt", b"ab");
+    let second = temp_file("switch_second.txt", b"xy");
+
+    let output = run(&[
+        "--input", first.to_str().unwrap(),
+        "--second-input", second.to_str().unwrap(),
+        program.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"axby");
+
+    // Without the flag `~` is a comment
+    let output = run_with_stdin(&[program.to_str().unwrap()], b"abcd");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"abcd");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(first).unwrap();
+    fs::remove_file(second).unwrap();
+}
+
+#[test]
+fn test_fill_and_eof_value() {
+    let program = temp_file("fill.bf", b".,.");
+
+    let output = run(&["--fill", "65", "--eof-value", "66", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AB");
+
+    // Reading past the end of input fails by default
+    let output = run(&["--fill", "65", program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"A");
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_output_limits_and_encoding() {
+    let endless = temp_file("endless_output.bf", b"+[.]");
+    let output = run(&["--max-output", "3", endless.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, [1, 1, 1]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("output exceeded the limit of 3 bytes"));
+
+    let output = run(&["--no-io", endless.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("output with I/O disabled"));
+
+    let newline = temp_file("newline.bf", b"++++++++++.");
+    let output = run(&["--crlf", newline.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\r\n");
+
+    // 'é' is code point 0xE9
+    let accent = temp_file("accent.bf", b"++++++++++++++[>++++++++++++++++<-]>+++++++++.");
+    let output = run(&["--output-mode", "utf8-codepoint", accent.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, "é".as_bytes());
+
+    for path in [endless, newline, accent] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_cell_and_loop_options() {
+    let program = temp_file("loop_output.bf", b"+++[>+<-.]");
+    let output = run(&["--max-loop-iterations", "1", program.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, [2, 1]);
+
+    let wrapping = temp_file("wrapping.bf", b"+++.");
+    let output = run(&["--modulus", "2", wrapping.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [1]);
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(wrapping).unwrap();
+}
+
+#[test]
+fn test_language_options_change_the_commands() {
+    let halting = temp_file("halt.bf", b"+.@+.");
+    let output = run(&["--halt-char", "@", halting.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [1]);
+
+    // `$` stores the cell and `}` puts it back
+    let register = temp_file("register.bf", b"+++$[-]}.");
+    let output = run(&["--register", register.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+
+    let commented = temp_file("commented.bf", b"+. x");
+    assert!(run(&[commented.to_str().unwrap()]).status.success());
+    let output = run(&["--strict", commented.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid character 'x' at position 1:4"));
+
+    let renamed = temp_file("charmap.bf", b"IIIIIIIIaRIIIIIIIILDbRIO");
+    let output = run(&["--charmap", "RLIDOSab", renamed.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+
+    for path in [halting, register, commented, renamed] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_check_and_dump_tokens_do_not_run() {
+    let valid = temp_file("check_valid.bf", b"+\n>,");
+    let invalid = temp_file("check_invalid.bf", b"+[]]");
+
+    let output = run(&["--check", valid.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"OK\n");
+
+    let output = run(&["--check", invalid.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: Unmatched bracket at position 1:4"));
+    assert!(stderr.contains("1 error in"));
+
+    let output = run(&["--dump-tokens", valid.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"1:1 Increment\n2:1 MoveRight\n2:2 Input\n");
+
+    fs::remove_file(valid).unwrap();
+    fs::remove_file(invalid).unwrap();
+}
+
+#[test]
+fn test_emit_translates_instead_of_running() {
+    let program = temp_file("emit.bf", b"++++++++[>++++++++<-]>+.,");
+
+    let output = run(&["--emit", "c", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    let c = String::from_utf8_lossy(&output.stdout);
+    assert!(c.starts_with("#include <stdio.h>"));
+    assert!(c.contains("int main(void)"));
+
+    let output = run(&["--emit", "bf", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"++++++++[->++++++++<]>+.,\n");
+
+    let output = run(&["--emit", "dot", program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.starts_with(b"digraph program {"));
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_trace_replays_the_same_run() {
+    let program = temp_file("traced.bf", b"+++[>++<-]>.");
+    let changed = temp_file("traced_changed.bf", b"+++[>+++<-]>.");
+    let trace = std::env::temp_dir().join(format!("bf-cli-{}-traced.tsv", std::process::id()));
+
+    let output = run(&["--trace", trace.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(fs::read_to_string(&trace).unwrap().starts_with("step\tip\tpointer\tcell\tinstruction\n"));
+
+    let output = run(&["--replay", trace.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [6]);
+
+    let output = run(&["--replay", trace.to_str().unwrap(), changed.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("replay diverged at step 3"));
+
+    for path in [program, changed, trace] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_profile_output_writes_folded_stacks() {
+    let program = temp_file("profiled.bf", b"+++[>++<-]>.");
+    let profile = std::env::temp_dir().join(format!("bf-cli-{}-profiled.folded", std::process::id()));
+
+    let output = run(&["--profile-output", profile.to_str().unwrap(), program.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&profile).unwrap(), "program 3\nprogram;loop@1:4 1\n");
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(profile).unwrap();
+}
+
+#[test]
+fn test_invalid_options_are_refused() {
+    let program = temp_file("refused.bf", b"+.");
+    let path = program.to_str().unwrap();
+
+    for args in [
+        &["--opt-level", "full", "--no-optimize"][..],
+        &["--charmap", "RLIDOSa"],
+        &["--halt-char", "+"],
+        &["--halt-char", "ab"],
+        &["--modulus", "0"],
+        &["--modulus", "257"],
+        &["--pad-output", "0"],
+        &["--eof-value", "256"],
+        &["--timeout", "-1"],
+        &["--output-format", "hex", "--verify", path],
+        &["--output", "out.bin", "--verify", path],
+    ] {
+        let output = run(&[args, &[path]].concat());
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+    }
+
+    fs::remove_file(program).unwrap();
+}
@@ -0,0 +1,164 @@
+//! Property tests checking that optimized programs behave exactly like their
+//! naive, one-instruction-per-token translation, whether single stepped or
+//! run with the interpreter's fast paths, and that source rebuilt from the
+//! optimized instructions does too, and optimizes back to the same
+//! instructions.
+//!
+//! Run with `cargo test --test optimizer_equivalence`. Set `PROPTEST_CASES`
+//! to explore more programs, e.g. `PROPTEST_CASES=10000 cargo test --test
+//! optimizer_equivalence`.
+
+use anyhow::Result;
+use brainfuck_interpreter::interpreter::{EofBehavior, Interpreter, InterpreterConfig};
+use brainfuck_interpreter::lexer::Lexer;
+use brainfuck_interpreter::optimizer::{Instruction, OptLevel, Optimizer, OptimizerConfig};
+use brainfuck_interpreter::transpiler::instructions_to_source;
+use proptest::prelude::*;
+use std::io::Cursor;
+
+/// Tape size used for generated programs
+const MEMORY_SIZE: usize = 64;
+/// Generated programs start in the middle of the tape so moving left is valid
+const START_POINTER: usize = MEMORY_SIZE / 2;
+/// Programs still running after this many naive steps are discarded
+const MAX_STEPS: usize = 10_000;
+
+/// The observable result of a finished run: output bytes and final memory
+type Outcome = (Vec<u8>, Vec<u8>);
+
+/// Translate `source` with or without optimization
+fn compile(source: &str, optimize: bool) -> Result<Vec<Instruction>> {
+    let lexer = Lexer::new(Cursor::new(source.as_bytes()));
+    let optimizer_config = OptimizerConfig {
+        opt_level: if optimize { OptLevel::Full } else { OptLevel::None },
+        initial_cell_value: Some(0),
+        ..Default::default()
+    };
+    Optimizer::with_config(optimizer_config).optimize(lexer)
+}
+
+fn config() -> InterpreterConfig {
+    InterpreterConfig {
+        memory_size: MEMORY_SIZE,
+        eof_behavior: EofBehavior::Zero,
+        ..Default::default()
+    }
+}
+
+/// Step `source` to completion, or return `None` if it exceeds the step budget
+fn execute(source: &str, optimize: bool, input: &[u8]) -> Option<Result<Outcome>> {
+    let instructions = match compile(source, optimize) {
+        Ok(instructions) => instructions,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let config = config();
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_io(instructions, config, Cursor::new(input), &mut output);
+    interpreter.set_pointer(START_POINTER).unwrap();
+
+    for _ in 0..MAX_STEPS {
+        match interpreter.step() {
+            Ok(true) => {}
+            Ok(false) => {
+                let memory = interpreter.memory_state().to_vec();
+                return Some(Ok((output, memory)));
+            }
+            Err(e) => return Some(Err(e)),
+        }
+    }
+    None
+}
+
+/// Run optimized `source` with [`Interpreter::run`], which may take fast paths
+/// that single stepping never does
+fn run_optimized(source: &str, input: &[u8]) -> Result<Outcome> {
+    let instructions = compile(source, true)?;
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::with_io(instructions, config(), Cursor::new(input), &mut output);
+    interpreter.set_pointer(START_POINTER)?;
+    interpreter.run()?;
+    let memory = interpreter.memory_state().to_vec();
+    Ok((output, memory))
+}
+
+/// Rebuild source from optimized `instructions` and optimize it again
+fn reoptimize(instructions: &[Instruction]) -> Vec<Instruction> {
+    compile(&instructions_to_source(instructions), true).expect("rebuilt source is balanced")
+}
+
+/// Commands plus a few loop idioms the optimizer recognises, so they show up
+/// far more often than random generation alone would produce them
+fn fragment() -> impl Strategy<Value = String> {
+    let leaf = prop::sample::select(vec![
+        "+", "-", ">", "<", ".", ",", "[-]", "[->+<]", "[-<+>]", "[->++>+++<<]", "[->-<]", "[>+>]",
+        "[-<]", "[<<+>-]", ",[.,]", "[+]", "[>>]", "[<]",
+    ])
+    .prop_map(String::from);
+
+    leaf.prop_recursive(3, 64, 8, |inner| {
+        prop::collection::vec(inner, 0..8).prop_map(|body| format!("[{}]", body.concat()))
+    })
+}
+
+/// Balanced Brainfuck programs of bounded size
+fn program() -> impl Strategy<Value = String> {
+    prop::collection::vec(fragment(), 0..24).prop_map(|parts| parts.concat())
+}
+
+proptest! {
+    #[test]
+    fn optimized_matches_naive(source in program(), input in prop::collection::vec(any::<u8>(), 0..8)) {
+        let naive = execute(&source, false, &input);
+        prop_assume!(naive.is_some());
+        let optimized = execute(&source, true, &input)
+            .expect("optimized program must not take more steps than the naive one");
+        let run = run_optimized(&source, &input);
+
+        match (&optimized, run) {
+            (Ok(stepped), Ok(run)) => prop_assert_eq!(stepped, &run),
+            (Err(_), Err(_)) => {}
+            (stepped, run) => prop_assert!(false, "stepping and running disagree: {:?} vs {:?}", stepped.is_ok(), run.is_ok()),
+        }
+
+        match (naive.unwrap(), optimized) {
+            (Ok(expected), Ok(actual)) => prop_assert_eq!(expected, actual),
+            (Err(_), Err(_)) => {}
+            (expected, actual) => prop_assert!(
+                false,
+                "naive and optimized runs disagree on success: {:?} vs {:?}",
+                expected.map(|_| ()),
+                actual.map(|_| ())
+            ),
+        }
+    }
+
+    #[test]
+    fn reconstructed_source_matches_naive(source in program(), input in prop::collection::vec(any::<u8>(), 0..8)) {
+        let naive = execute(&source, false, &input);
+        prop_assume!(matches!(naive, Some(Ok(_))));
+        let rebuilt = instructions_to_source(&compile(&source, true).unwrap());
+
+        if let Some(result) = execute(&rebuilt, false, &input) {
+            prop_assert_eq!(naive.unwrap().unwrap(), result.unwrap(), "rebuilt source: {}", rebuilt);
+        }
+    }
+
+    #[test]
+    fn reoptimizing_is_idempotent(source in program()) {
+        let instructions = compile(&source, true).unwrap();
+        prop_assert_eq!(reoptimize(&instructions), instructions, "source: {}", source);
+    }
+}
+
+#[test]
+fn reoptimizing_examples_is_idempotent() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "bf") {
+            let source = std::fs::read_to_string(&path).unwrap();
+            let instructions = compile(&source, true).unwrap();
+            assert_eq!(reoptimize(&instructions), instructions, "{}", path.display());
+        }
+    }
+}